@@ -0,0 +1,541 @@
+use crate::{
+    energy::dto::EnergySummary,
+    money::{decimal, deserialize_legacy_option, Money, MoneyError},
+    pulse::dto::MeterReading,
+};
+use chrono::{DateTime, Datelike, Months, NaiveDate, TimeDelta, TimeZone, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+
+/// Power samples further apart are not integrated, e.g. after a restart
+const MAX_SAMPLE_GAP: TimeDelta = TimeDelta::minutes(5);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Totals {
+    start: NaiveDate,
+    import_wh: f64,
+    export_wh: f64,
+    production_wh: f64,
+    /// None until energy was drawn at a known price
+    #[serde(deserialize_with = "deserialize_legacy_option")]
+    cost: Option<Money>,
+    /// None until energy was fed in at a known remuneration
+    #[serde(default)]
+    revenue: Option<Money>,
+    peak_demand: i32,
+}
+
+impl Totals {
+    fn new(start: NaiveDate) -> Self {
+        Self {
+            start,
+            import_wh: 0.0,
+            export_wh: 0.0,
+            production_wh: 0.0,
+            cost: None,
+            revenue: None,
+            peak_demand: 0,
+        }
+    }
+
+    fn summary(&self, base_fee: Option<Money>) -> EnergySummary {
+        let self_consumed = (self.production_wh - self.export_wh).max(0.0);
+        let consumption = self.import_wh + self_consumed;
+        EnergySummary {
+            start: self.start,
+            grid_import: self.import_wh / 1000.0,
+            grid_export: self.export_wh / 1000.0,
+            production: self.production_wh / 1000.0,
+            cost: self.cost,
+            feed_in_revenue: self.revenue,
+            base_fee,
+            peak_demand: self.peak_demand,
+            pv_share: (consumption > 0.0).then(|| self_consumed / consumption),
+        }
+    }
+}
+
+/// Energy in Wh since [`Aggregator::take_period`] was called last
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct PeriodEnergy {
+    pub import_wh: f64,
+    pub export_wh: f64,
+    pub production_wh: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct GridSample {
+    time: DateTime<Utc>,
+    power: i32,
+    import_wh: Option<f64>,
+    export_wh: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct ProductionSample {
+    time: DateTime<Utc>,
+    power: f64,
+}
+
+/// Everything the aggregator needs to continue after a restart
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AggregatorState {
+    day: Totals,
+    month: Totals,
+    grid: Option<GridSample>,
+    production: Option<ProductionSample>,
+    /// Current price per kWh
+    #[serde(default, deserialize_with = "deserialize_legacy_option")]
+    price: Option<Money>,
+    /// Remuneration per kWh fed into the grid
+    #[serde(default)]
+    feed_in_price: Option<Money>,
+    /// Fee per month
+    #[serde(default)]
+    base_fee: Option<Money>,
+    #[serde(default)]
+    period: PeriodEnergy,
+}
+
+#[derive(Clone, Copy)]
+enum Flow {
+    Import,
+    Export,
+    Production,
+}
+
+/// Sums up the grid and PV energy of the current day and month. Energy is
+/// taken from the meter counters if the meter provides them, otherwise the
+/// power is integrated over time. Days start at midnight in the time zone
+/// `tz`, so they last 23 or 25 hours when daylight saving time changes.
+pub struct Aggregator<Tz: TimeZone> {
+    tz: Tz,
+    state: AggregatorState,
+}
+
+impl<Tz: TimeZone> Aggregator<Tz> {
+    pub fn new(tz: Tz, now: DateTime<Utc>) -> Self {
+        let today = now.with_timezone(&tz).date_naive();
+        Self {
+            tz,
+            state: AggregatorState {
+                day: Totals::new(today),
+                month: Totals::new(first_of_month(today)),
+                grid: None,
+                production: None,
+                price: None,
+                feed_in_price: None,
+                base_fee: None,
+                period: PeriodEnergy::default(),
+            },
+        }
+    }
+
+    /// Continues with the state saved by [`Aggregator::save`], starts from
+    /// scratch if the file does not exist
+    pub fn load(tz: Tz, path: &Path, now: DateTime<Utc>) -> io::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::new(tz, now));
+        }
+        let state = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut aggregator = Self { tz, state };
+        aggregator.advance(now);
+        Ok(aggregator)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        // Write to a temporary file first so a crash never leaves a truncated state
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, serde_json::to_vec(&self.state)?)?;
+        fs::rename(temporary, path)
+    }
+
+    /// Price per kWh applied to the energy drawn from now on
+    pub fn set_price(&mut self, price: Money) {
+        self.state.price = Some(price);
+    }
+
+    /// Remuneration per kWh applied to the energy fed in from now on
+    pub fn set_feed_in_price(&mut self, price: Option<Money>) {
+        self.state.feed_in_price = price;
+    }
+
+    /// Monthly fee, the daily summary carries its share of the day
+    pub fn set_base_fee(&mut self, fee: Option<Money>) {
+        self.state.base_fee = fee;
+    }
+
+    /// Fails if the price of the energy can not be added to the totals, the
+    /// energy itself is still counted and the totals keep their amounts
+    pub fn add_meter_reading(
+        &mut self,
+        time: DateTime<Utc>,
+        reading: &MeterReading,
+    ) -> Result<(), MoneyError> {
+        let mut result = Ok(());
+        if let Some(last) = self.state.grid {
+            let counters = last
+                .import_wh
+                .zip(reading.import_wh)
+                .zip(last.export_wh.zip(reading.export_wh));
+            match counters {
+                // A counter going backwards means the meter was replaced
+                Some(((last_import, import), (last_export, export)))
+                    if import >= last_import && export >= last_export =>
+                {
+                    result = self.distribute(
+                        last.time,
+                        time,
+                        &[
+                            (Flow::Import, import - last_import),
+                            (Flow::Export, export - last_export),
+                        ],
+                    );
+                }
+                _ if time - last.time <= MAX_SAMPLE_GAP => {
+                    let wh = last.power as f64 * hours(last.time, time);
+                    result = if wh >= 0.0 {
+                        self.distribute(last.time, time, &[(Flow::Import, wh)])
+                    } else {
+                        self.distribute(last.time, time, &[(Flow::Export, -wh)])
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        self.advance(time);
+        for totals in [&mut self.state.day, &mut self.state.month] {
+            totals.peak_demand = totals.peak_demand.max(reading.power);
+        }
+        self.state.grid = Some(GridSample {
+            time,
+            power: reading.power,
+            import_wh: reading.import_wh,
+            export_wh: reading.export_wh,
+        });
+        result
+    }
+
+    /// AC power of the inverters in W
+    pub fn add_production(&mut self, time: DateTime<Utc>, power: f64) -> Result<(), MoneyError> {
+        let mut result = Ok(());
+        if let Some(last) = self.state.production {
+            if time - last.time <= MAX_SAMPLE_GAP {
+                let wh = last.power * hours(last.time, time);
+                result = self.distribute(last.time, time, &[(Flow::Production, wh)]);
+            }
+        }
+
+        self.advance(time);
+        self.state.production = Some(ProductionSample { time, power });
+        result
+    }
+
+    /// Starts a new day or month if `now` is past its end
+    pub fn advance(&mut self, now: DateTime<Utc>) {
+        let today = now.with_timezone(&self.tz).date_naive();
+        if today > self.state.day.start {
+            self.roll_over(today);
+        }
+    }
+
+    pub fn daily(&self) -> EnergySummary {
+        let days = days_in_month(self.state.day.start);
+        let fee = self.state.base_fee.map(|fee| fee / Decimal::from(days));
+        self.state.day.summary(fee)
+    }
+
+    pub fn monthly(&self) -> EnergySummary {
+        self.state.month.summary(self.state.base_fee)
+    }
+
+    /// Energy of the current price period, the next one starts from zero
+    pub fn take_period(&mut self) -> PeriodEnergy {
+        std::mem::take(&mut self.state.period)
+    }
+
+    fn roll_over(&mut self, date: NaiveDate) {
+        self.state.day = Totals::new(date);
+        if first_of_month(date) != self.state.month.start {
+            self.state.month = Totals::new(first_of_month(date));
+        }
+    }
+
+    /// Adds the energy in Wh of the interval `from`..`to`, the part after
+    /// midnight goes to the next day. All energy is added even if an amount
+    /// fails, the first error is returned.
+    fn distribute(
+        &mut self,
+        mut from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        energy: &[(Flow, f64)],
+    ) -> Result<(), MoneyError> {
+        let mut result = Ok(());
+        let duration = hours(from, to);
+        if duration <= 0.0 {
+            self.advance(to);
+            for (flow, wh) in energy {
+                result = result.and(self.add(*wh, *flow));
+            }
+            return result;
+        }
+
+        loop {
+            let next_day = self.state.day.start.succ_opt().unwrap();
+            let day_end = local_midnight(&self.tz, next_day);
+            if from < day_end {
+                let share = hours(from, to.min(day_end)) / duration;
+                for (flow, wh) in energy {
+                    result = result.and(self.add(wh * share, *flow));
+                }
+                from = day_end;
+            }
+            if to <= day_end {
+                return result;
+            }
+            self.roll_over(next_day);
+        }
+    }
+
+    fn add(&mut self, wh: f64, flow: Flow) -> Result<(), MoneyError> {
+        let amount = |price: Option<Money>| Some(price? * decimal(wh / 1000.0).ok()?);
+        let cost = amount(self.state.price);
+        let revenue = amount(self.state.feed_in_price);
+        match flow {
+            Flow::Import => self.state.period.import_wh += wh,
+            Flow::Export => self.state.period.export_wh += wh,
+            Flow::Production => self.state.period.production_wh += wh,
+        }
+        let mut result = Ok(());
+        for totals in [&mut self.state.day, &mut self.state.month] {
+            match flow {
+                Flow::Import => {
+                    totals.import_wh += wh;
+                    result = result.and(accumulate(&mut totals.cost, cost));
+                }
+                Flow::Export => {
+                    totals.export_wh += wh;
+                    result = result.and(accumulate(&mut totals.revenue, revenue));
+                }
+                Flow::Production => totals.production_wh += wh,
+            }
+        }
+        result
+    }
+}
+
+/// Adds the amount to the total, which stays as it is if the currencies differ
+fn accumulate(total: &mut Option<Money>, amount: Option<Money>) -> Result<(), MoneyError> {
+    *total = match (*total, amount) {
+        (Some(total), Some(amount)) => Some(total.checked_add(amount)?),
+        (total, amount) => total.or(amount),
+    };
+    Ok(())
+}
+
+fn hours(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    (to - from).num_milliseconds() as f64 / 3_600_000.0
+}
+
+fn first_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap()
+}
+
+fn days_in_month(date: NaiveDate) -> u32 {
+    let first = first_of_month(date);
+    (first + Months::new(1) - first).num_days() as u32
+}
+
+/// Start of `date` in the time zone
+fn local_midnight<Tz: TimeZone>(tz: &Tz, date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    tz.from_local_datetime(&midnight)
+        .earliest()
+        // Some time zones skip midnight when daylight saving time starts
+        .or_else(|| {
+            tz.from_local_datetime(&(midnight + TimeDelta::hours(1)))
+                .earliest()
+        })
+        .map(|time| time.to_utc())
+        .unwrap_or_else(|| midnight.and_utc())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::Currency;
+    use chrono_tz::Europe::Berlin;
+
+    fn reading(power: i32) -> MeterReading {
+        MeterReading {
+            meter_id: "1".to_string(),
+            power,
+            import_wh: None,
+            export_wh: None,
+        }
+    }
+
+    #[test]
+    fn test_integrate_power() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 21, 10, 0, 0).unwrap();
+        let mut aggregator = Aggregator::new(Berlin, start);
+        aggregator.set_price(Money::from_f64(0.3, Currency::EUR).unwrap());
+
+        // 1 kW for an hour in samples every 10 seconds
+        for i in 0..=360 {
+            let time = start + TimeDelta::seconds(i * 10);
+            aggregator.add_meter_reading(time, &reading(1000)).unwrap();
+            aggregator.add_production(time, 500.0).unwrap();
+        }
+        // -200 W feed-in for 10 minutes
+        aggregator
+            .add_meter_reading(start + TimeDelta::minutes(60), &reading(-200))
+            .unwrap();
+        aggregator
+            .add_meter_reading(start + TimeDelta::minutes(70), &reading(0))
+            .unwrap();
+
+        let daily = aggregator.daily();
+        assert!((daily.grid_import - 1.0).abs() < 1e-9);
+        assert_eq!(
+            daily.cost.unwrap().round_dp(9),
+            Money::from_f64(0.3, Currency::EUR).unwrap()
+        );
+        assert!((daily.production - 0.5).abs() < 1e-9);
+        // A gap of 10 minutes is not integrated
+        assert_eq!(daily.grid_export, 0.0);
+        assert_eq!(daily.peak_demand, 1000);
+        assert!((daily.pv_share.unwrap() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(
+            aggregator.monthly(),
+            EnergySummary {
+                start: NaiveDate::from_ymd_opt(2024, 6, 1).unwrap(),
+                ..daily
+            }
+        );
+
+        let period = aggregator.take_period();
+        assert!((period.import_wh - 1000.0).abs() < 1e-6);
+        assert!((period.production_wh - 500.0).abs() < 1e-6);
+        assert_eq!(aggregator.take_period(), PeriodEnergy::default());
+    }
+
+    #[test]
+    fn test_roll_over_at_local_midnight() {
+        // 2024-10-26 23:30 CEST, the night daylight saving time ends
+        let before_midnight = Utc.with_ymd_and_hms(2024, 10, 26, 21, 30, 0).unwrap();
+        let mut aggregator = Aggregator::new(Berlin, before_midnight);
+        let meter = |import_wh| MeterReading {
+            import_wh: Some(import_wh),
+            export_wh: Some(0.0),
+            ..reading(1000)
+        };
+
+        aggregator
+            .add_meter_reading(before_midnight, &meter(10_000.0))
+            .unwrap();
+        // 00:30 local time, half of the energy belongs to the previous day
+        aggregator
+            .add_meter_reading(before_midnight + TimeDelta::hours(1), &meter(11_000.0))
+            .unwrap();
+        let daily = aggregator.daily();
+        assert_eq!(daily.start, NaiveDate::from_ymd_opt(2024, 10, 27).unwrap());
+        assert!((daily.grid_import - 0.5).abs() < 1e-9);
+        assert!((aggregator.monthly().grid_import - 1.0).abs() < 1e-9);
+
+        // The day has 25 hours, 23:30 CET is still the 27th
+        let end_of_day = Utc.with_ymd_and_hms(2024, 10, 27, 22, 30, 0).unwrap();
+        aggregator.advance(end_of_day);
+        assert_eq!(aggregator.daily().start, daily.start);
+        aggregator.advance(end_of_day + TimeDelta::hours(1));
+        assert_eq!(
+            aggregator.daily().start,
+            NaiveDate::from_ymd_opt(2024, 10, 28).unwrap()
+        );
+
+        // Persisted state survives a restart, a month later everything starts over
+        let path = std::env::temp_dir().join(format!("aggregator-{}.json", std::process::id()));
+        aggregator.save(&path).unwrap();
+        let restored = Aggregator::load(Berlin, &path, end_of_day).unwrap();
+        assert_eq!(restored.state, aggregator.state);
+        let next_month = Utc.with_ymd_and_hms(2024, 11, 2, 12, 0, 0).unwrap();
+        let restored = Aggregator::load(Berlin, &path, next_month).unwrap();
+        assert_eq!(restored.monthly().grid_import, 0.0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_feed_in_and_base_fee() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 21, 10, 0, 0).unwrap();
+        let mut aggregator = Aggregator::new(Berlin, start);
+        let eur = |amount: &str| Money::new(amount.parse().unwrap(), Currency::EUR);
+        aggregator.set_feed_in_price(Some(eur("0.08")));
+        aggregator.set_base_fee(Some(eur("15")));
+
+        // 1 kWh fed in
+        let meter = |export_wh| MeterReading {
+            import_wh: Some(0.0),
+            export_wh: Some(export_wh),
+            ..reading(-2000)
+        };
+        aggregator
+            .add_meter_reading(start, &meter(5_000.0))
+            .unwrap();
+        aggregator
+            .add_meter_reading(start + TimeDelta::minutes(30), &meter(6_000.0))
+            .unwrap();
+        let daily = aggregator.daily();
+        assert_eq!(daily.feed_in_revenue.unwrap().round_dp(9), eur("0.08"));
+        assert_eq!(daily.cost, None);
+        // June has 30 days
+        assert_eq!(daily.base_fee, Some(eur("0.5")));
+        assert_eq!(aggregator.monthly().base_fee, Some(eur("15")));
+    }
+
+    #[test]
+    fn test_currency_mismatch() {
+        let start = Utc.with_ymd_and_hms(2024, 6, 21, 10, 0, 0).unwrap();
+        let mut aggregator = Aggregator::new(Berlin, start);
+        let meter = |import_wh| MeterReading {
+            import_wh: Some(import_wh),
+            export_wh: Some(0.0),
+            ..reading(1000)
+        };
+        aggregator.set_price(Money::new("0.30".parse().unwrap(), Currency::EUR));
+        aggregator
+            .add_meter_reading(start, &meter(1_000.0))
+            .unwrap();
+        aggregator
+            .add_meter_reading(start + TimeDelta::hours(1), &meter(2_000.0))
+            .unwrap();
+
+        // The energy is counted, the cost so far is kept
+        aggregator.set_price(Money::new("3".parse().unwrap(), "SEK".parse().unwrap()));
+        let result = aggregator.add_meter_reading(start + TimeDelta::hours(2), &meter(3_000.0));
+        assert!(matches!(result, Err(MoneyError::CurrencyMismatch(..))));
+        let daily = aggregator.daily();
+        assert!((daily.grid_import - 2.0).abs() < 1e-9);
+        assert_eq!(
+            daily.cost,
+            Some(Money::new("0.30".parse().unwrap(), Currency::EUR))
+        );
+    }
+
+    #[test]
+    fn test_load_legacy_state() {
+        let state = r#"{
+            "day": {"start":"2024-06-21","import_wh":1000.0,"export_wh":0.0,"production_wh":0.0,"cost":0.35,"peak_demand":1000},
+            "month": {"start":"2024-06-01","import_wh":1000.0,"export_wh":0.0,"production_wh":0.0,"cost":0.35,"peak_demand":1000},
+            "grid": null,
+            "production": null,
+            "price": 0.3
+        }"#;
+        let state: AggregatorState = serde_json::from_str(state).unwrap();
+        let eur = |amount| Some(Money::from_f64(amount, Currency::EUR).unwrap());
+        assert_eq!(state.day.cost, eur(0.35));
+        assert_eq!(state.price, eur(0.3));
+    }
+}
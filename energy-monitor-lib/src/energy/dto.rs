@@ -0,0 +1,30 @@
+use crate::money::Money;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+/// Energy flows of a day or a month, published by emtibberd
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct EnergySummary {
+    /// First day of the period in local time
+    pub start: NaiveDate,
+    /// Energy drawn from the grid in kWh
+    pub grid_import: f64,
+    /// Energy fed into the grid in kWh
+    pub grid_export: f64,
+    /// Energy produced by the PV system in kWh
+    pub production: f64,
+    /// Cost of the energy drawn from the grid at the price of the period it
+    /// was drawn in, none until a price is known
+    pub cost: Option<Money>,
+    /// Remuneration of the energy fed into the grid, none without a feed-in tariff
+    #[serde(default)]
+    pub feed_in_revenue: Option<Money>,
+    /// Share of the monthly base fee for the period
+    #[serde(default)]
+    pub base_fee: Option<Money>,
+    /// Highest power drawn from the grid in W
+    pub peak_demand: i32,
+    /// Part of the consumption covered by the PV system (0 to 1), none
+    /// if nothing was consumed yet
+    pub pv_share: Option<f64>,
+}
@@ -0,0 +1,3 @@
+pub mod aggregator;
+pub mod dto;
+pub mod topics;
@@ -0,0 +1,8 @@
+use crate::energy::dto::*;
+use crate::topic::{Delivery, Topic};
+#[rustfmt::skip]
+pub const ENERGY_DAILY_TOPIC: Topic<EnergySummary> =
+    Topic::with_delivery("Energy/daily", Delivery::STATE);
+#[rustfmt::skip]
+pub const ENERGY_MONTHLY_TOPIC: Topic<EnergySummary> =
+    Topic::with_delivery("Energy/monthly", Delivery::STATE);
@@ -1,17 +1,34 @@
+pub mod energy;
+pub mod metrics;
+pub mod money;
+pub mod mqtt;
 pub mod opendtu;
+pub mod price;
 pub mod pulse;
+pub mod service;
+pub mod tariff;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod tibber;
 pub mod topic;
 
 #[cfg(test)]
 mod test {
+    use crate::money::{Currency, Money};
     use crate::tibber::dto;
     use crate::topic::Topic;
+
+    fn eur(amount: &str) -> Money {
+        Money::new(amount.parse().unwrap(), Currency::EUR)
+    }
+
     #[test]
     fn test_encode_decode() {
         let price_info = dto::PriceInformation {
-            total: 100.0,
+            total: eur("0.3012"),
             level: dto::PriceLevel::Cheap,
+            starts_at: Some("2025-10-01T12:15:00+02:00".parse().unwrap()),
+            duration: Some(900),
         };
 
         let topic = Topic::new("Tibber/price_information");
@@ -19,5 +36,156 @@ mod test {
         let decoded = topic.decode(&encoded).unwrap();
 
         assert_eq!(price_info, decoded);
+        // Without losing digits since schema 3
+        assert!(encoded.starts_with(br#"{"total":{"amount":"0.3012","currency":"EUR"},"#));
+        assert_eq!(
+            decoded.ends_at(),
+            Some("2025-10-01T12:30:00+02:00".parse().unwrap())
+        );
+
+        // Messages of older versions are still accepted
+        let decoded = topic.decode(r#"{"total":0.3,"level":"Normal"}"#).unwrap();
+        assert_eq!(decoded.total, eur("0.3"));
+        assert_eq!(decoded.starts_at, None);
+        assert_eq!(decoded.ends_at(), None);
+    }
+
+    #[test]
+    fn test_price_details() {
+        use crate::tibber::topics::TIBBER_PRICE_DETAILS_TOPIC;
+
+        let payload = r#"{"total":{"amount":"0.3012","currency":"EUR"},"energy":{"amount":"0.1024","currency":"EUR"},"tax":{"amount":"0.1988","currency":"EUR"},"level":"Cheap","starts_at":"2025-10-01T12:15:00+02:00","duration":900}"#;
+        let details = TIBBER_PRICE_DETAILS_TOPIC.decode(payload).unwrap();
+        assert_eq!(details.energy.checked_add(details.tax), Ok(details.total));
+        assert_eq!(TIBBER_PRICE_DETAILS_TOPIC.encode(&details), payload);
+    }
+
+    /// Messages of every schema version and levels this version does not
+    /// know are decoded, known fields keep their meaning
+    #[test]
+    fn test_compatibility_matrix() {
+        use crate::tibber::topics::{TIBBER_PRICE_DETAILS_TOPIC, TIBBER_PRICE_INFORMATION_TOPIC};
+
+        let levels = [
+            ("Cheap", dto::PriceLevel::Cheap),
+            ("Expensive", dto::PriceLevel::Expensive),
+            ("Normal", dto::PriceLevel::Normal),
+            ("VeryCheap", dto::PriceLevel::VeryCheap),
+            ("VeryExpensive", dto::PriceLevel::VeryExpensive),
+            ("None", dto::PriceLevel::None),
+            // Added by Tibber or a newer version
+            (
+                "ExtremelyCheap",
+                dto::PriceLevel::Other("ExtremelyCheap".into()),
+            ),
+            // As emtibberd publishes a level Tibber adds
+            (
+                "EXTREMELY_CHEAP",
+                dto::PriceLevel::Other("EXTREMELY_CHEAP".into()),
+            ),
+            ("", dto::PriceLevel::Other("".into())),
+        ];
+        for (name, level) in levels {
+            let information = [
+                // Schema 1 before the price period was published
+                format!(r#"{{"total":0.3,"level":"{name}"}}"#),
+                // Schema 1 with the price period
+                format!(
+                    r#"{{"total":0.3,"level":"{name}","starts_at":"2025-10-01T12:15:00+02:00","duration":900}}"#
+                ),
+                // Schema 2 with the currency
+                format!(
+                    r#"{{"total":0.3,"currency":"EUR","level":"{name}","starts_at":"2025-10-01T12:15:00+02:00","duration":900}}"#
+                ),
+                // Schema 3 with the total as decimal
+                format!(
+                    r#"{{"total":{{"amount":"0.3","currency":"EUR"}},"level":"{name}","starts_at":"2025-10-01T12:15:00+02:00","duration":900}}"#
+                ),
+                // Fields of a newer version are ignored
+                format!(r#"{{"total":0.3,"level":"{name}","trend":"rising"}}"#),
+            ];
+            for payload in information {
+                let decoded = TIBBER_PRICE_INFORMATION_TOPIC
+                    .decode(&payload)
+                    .unwrap_or_else(|e| panic!("{payload}: {e}"));
+                assert_eq!(decoded.total, eur("0.3"), "{payload}");
+                assert_eq!(decoded.level, level, "{payload}");
+                assert_eq!(decoded.level.name(), name);
+            }
+
+            let details = format!(
+                r#"{{"total":{{"amount":"0.3","currency":"EUR"}},"energy":{{"amount":"0.1","currency":"EUR"}},"tax":{{"amount":"0.2","currency":"EUR"}},"level":"{name}","starts_at":"2025-10-01T12:15:00+02:00","duration":900}}"#
+            );
+            let decoded = TIBBER_PRICE_DETAILS_TOPIC.decode(&details).unwrap();
+            assert_eq!(decoded.level, level);
+            // Unknown levels are published as received
+            assert_eq!(TIBBER_PRICE_DETAILS_TOPIC.encode(&decoded), details);
+        }
+
+        // Known levels in other spellings are published with their name
+        let spellings = [
+            ("VERY_CHEAP", dto::PriceLevel::VeryCheap),
+            ("very_cheap", dto::PriceLevel::VeryCheap),
+            ("veryExpensive", dto::PriceLevel::VeryExpensive),
+            ("NORMAL", dto::PriceLevel::Normal),
+            ("none", dto::PriceLevel::None),
+            (
+                "EXTREMELY_CHEAP",
+                dto::PriceLevel::Other("EXTREMELY_CHEAP".into()),
+            ),
+        ];
+        for (name, level) in spellings {
+            let payload = format!(r#"{{"total":0.3,"level":"{name}"}}"#);
+            let decoded = TIBBER_PRICE_INFORMATION_TOPIC.decode(&payload).unwrap();
+            assert_eq!(decoded.level, level, "{payload}");
+        }
+        let decoded = TIBBER_PRICE_INFORMATION_TOPIC
+            .decode(r#"{"total":0.3,"level":"VERY_CHEAP"}"#)
+            .unwrap();
+        assert_eq!(
+            TIBBER_PRICE_INFORMATION_TOPIC.encode(&decoded),
+            r#"{"total":{"amount":"0.3","currency":"EUR"},"level":"VeryCheap","starts_at":null,"duration":null}"#
+        );
+
+        // Schema 2 totals in other currencies keep them
+        let decoded = TIBBER_PRICE_INFORMATION_TOPIC
+            .decode(r#"{"total":2.5,"currency":"SEK","level":"Normal"}"#)
+            .unwrap();
+        assert_eq!(decoded.total.to_string(), "2.5 SEK");
+
+        // A level has to be a name
+        assert!(TIBBER_PRICE_INFORMATION_TOPIC
+            .decode(r#"{"total":0.3,"level":3}"#)
+            .is_err());
+    }
+
+    #[test]
+    fn test_topic_delivery() {
+        use crate::{pulse::topics::*, tibber::topics::*, topic::Delivery};
+
+        assert_eq!(TIBBER_PRICE_INFORMATION_TOPIC.delivery(), Delivery::STATE);
+        assert_eq!(TIBBER_PRICE_DETAILS_TOPIC.delivery(), Delivery::STATE);
+        assert_eq!(PULSE_CONSUMPTION_TOPIC.delivery(), Delivery::STREAM);
+    }
+
+    #[test]
+    fn test_metrics_price_level() {
+        let metrics = crate::metrics::Metrics::new("test");
+        metrics.set_price_level(&dto::PriceLevel::Cheap);
+        metrics.set_price_level(&dto::PriceLevel::Expensive);
+
+        let encoded = metrics.encode();
+        assert!(
+            encoded.contains(r#"energy_monitor_price_level{level="Expensive",daemon="test"} 1"#)
+        );
+        assert!(encoded.contains(r#"energy_monitor_price_level{level="Cheap",daemon="test"} 0"#));
+
+        // Levels this version does not know are counted together
+        metrics.set_price_level(&dto::PriceLevel::Other("ExtremelyCheap".to_string()));
+        let encoded = metrics.encode();
+        assert!(encoded.contains(r#"energy_monitor_price_level{level="Other",daemon="test"} 1"#));
+        assert!(
+            encoded.contains(r#"energy_monitor_price_level{level="Expensive",daemon="test"} 0"#)
+        );
     }
 }
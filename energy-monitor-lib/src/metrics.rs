@@ -0,0 +1,136 @@
+use crate::tibber::dto::PriceLevel;
+use log::{debug, error};
+use prometheus::{
+    Encoder, Gauge, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Labels of the price level gauge, unknown levels share `Other`
+const PRICE_LEVELS: [&str; 7] = [
+    "VeryCheap",
+    "Cheap",
+    "Normal",
+    "Expensive",
+    "VeryExpensive",
+    "None",
+    "Other",
+];
+
+/// Metrics both daemons export. Daemon specific metrics are registered
+/// in `registry` by the daemons themselves.
+#[derive(Clone)]
+pub struct Metrics {
+    pub registry: Registry,
+    /// Current grid power in W
+    pub consumption: IntGauge,
+    /// Current PV production in W
+    pub production: Gauge,
+    /// PV yield of the current day in Wh
+    pub yield_day: Gauge,
+    /// Total price incl. taxes per kWh
+    pub price_total: Gauge,
+    /// 1 for the current price level, 0 for all others
+    pub price_level: IntGaugeVec,
+    /// Failed publishes by topic
+    pub mqtt_publish_failures: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new(daemon: &str) -> Self {
+        let registry = Registry::new_custom(
+            Some("energy_monitor".to_string()),
+            Some([("daemon".to_string(), daemon.to_string())].into()),
+        )
+        .unwrap();
+
+        let metrics = Self {
+            consumption: IntGauge::new("consumption_watts", "Current grid power").unwrap(),
+            production: Gauge::new("production_watts", "Current PV production").unwrap(),
+            yield_day: Gauge::new("yield_day_watt_hours", "PV yield of the day").unwrap(),
+            price_total: Gauge::new("price_total", "Current price per kWh incl. taxes").unwrap(),
+            price_level: IntGaugeVec::new(
+                Opts::new("price_level", "Current price level"),
+                &["level"],
+            )
+            .unwrap(),
+            mqtt_publish_failures: IntCounterVec::new(
+                Opts::new("mqtt_publish_failures_total", "Failed MQTT publishes"),
+                &["topic"],
+            )
+            .unwrap(),
+            registry,
+        };
+
+        metrics.register(Box::new(metrics.consumption.clone()));
+        metrics.register(Box::new(metrics.production.clone()));
+        metrics.register(Box::new(metrics.yield_day.clone()));
+        metrics.register(Box::new(metrics.price_total.clone()));
+        metrics.register(Box::new(metrics.price_level.clone()));
+        metrics.register(Box::new(metrics.mqtt_publish_failures.clone()));
+        metrics
+    }
+
+    pub fn register(&self, collector: Box<dyn prometheus::core::Collector>) {
+        self.registry
+            .register(collector)
+            .expect("Metric registered twice");
+    }
+
+    pub fn set_price_level(&self, level: &PriceLevel) {
+        let current = match level {
+            PriceLevel::Other(_) => "Other",
+            level => level.name(),
+        };
+        for name in PRICE_LEVELS {
+            self.price_level
+                .with_label_values(&[name])
+                .set((name == current) as i64);
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+/// Serves `GET /metrics` in the Prometheus text format on `addr`
+pub async fn serve(addr: SocketAddr, metrics: Metrics) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        debug!("Metrics request from {peer}");
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(stream, &metrics).await {
+                error!("Failed to serve metrics: {:?}", e);
+            }
+        });
+    }
+}
+
+async fn handle_request(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut request = vec![0; 1024];
+    let len = stream.read(&mut request).await?;
+    let request = String::from_utf8_lossy(&request[..len]);
+
+    let response = if request.starts_with("GET /metrics ") {
+        let body = metrics.encode();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,279 @@
+//! Amounts of money as decimals together with their currency. Prices and
+//! costs are calculated without the rounding errors of binary floats and
+//! serialized without losing digits, e.g. `{"amount":"0.3012","currency":"EUR"}`.
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    fmt,
+    ops::{Div, Mul, Neg},
+    str::FromStr,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum MoneyError {
+    #[error("{0} is not an ISO 4217 currency code")]
+    InvalidCurrency(String),
+    #[error("{0} can not be represented as decimal")]
+    InvalidAmount(f64),
+    #[error("Amounts in {0} and {1} can not be combined")]
+    CurrencyMismatch(Currency, Currency),
+}
+
+/// ISO 4217 currency code like `EUR`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const EUR: Currency = Currency(*b"EUR");
+
+    pub fn code(&self) -> &str {
+        // Only ASCII letters are accepted when parsing
+        std::str::from_utf8(&self.0).unwrap()
+    }
+
+    /// Digits after the decimal point of the smallest unit, 2 for cents.
+    /// Covers the currencies Tibber and the day-ahead markets use.
+    pub fn minor_units(&self) -> u32 {
+        match &self.0 {
+            b"ISK" | b"JPY" | b"KRW" => 0,
+            _ => 2,
+        }
+    }
+}
+
+impl FromStr for Currency {
+    type Err = MoneyError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        match code.as_bytes() {
+            &[a, b, c] if code.bytes().all(|b| b.is_ascii_uppercase()) => Ok(Currency([a, b, c])),
+            _ => Err(MoneyError::InvalidCurrency(code.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = MoneyError;
+
+    fn try_from(code: String) -> Result<Self, Self::Error> {
+        code.parse()
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> Self {
+        currency.code().to_string()
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.code().fmt(f)
+    }
+}
+
+/// Converts a float the way it is printed, 0.1 becomes exactly 0.1 instead
+/// of the binary fraction closest to it
+pub fn decimal(value: f64) -> Result<Decimal, MoneyError> {
+    Decimal::from_str_exact(&value.to_string()).map_err(|_| MoneyError::InvalidAmount(value))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Money(Money),
+    Legacy(f64),
+}
+
+impl Stored {
+    fn money<E: serde::de::Error>(self) -> Result<Money, E> {
+        match self {
+            Stored::Money(money) => Ok(money),
+            Stored::Legacy(amount) => Money::from_f64(amount, Currency::EUR).map_err(E::custom),
+        }
+    }
+}
+
+/// Accepts the plain numbers in EUR written before amounts carried a
+/// currency, use with `#[serde(deserialize_with = "...")]`
+pub fn deserialize_legacy<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+    Stored::deserialize(deserializer)?.money()
+}
+
+/// [`deserialize_legacy`] for optional amounts
+pub fn deserialize_legacy_option<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Money>, D::Error> {
+    Option::<Stored>::deserialize(deserializer)?
+        .map(Stored::money)
+        .transpose()
+}
+
+/// An amount in a currency. Amounts are added and subtracted with
+/// [`Money::checked_add`] and [`Money::checked_sub`], which fail for
+/// different currencies instead of mixing them up.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Money {
+    pub amount: Decimal,
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: Decimal, currency: Currency) -> Self {
+        Self { amount, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Self::new(Decimal::ZERO, currency)
+    }
+
+    /// See [`decimal`]
+    pub fn from_f64(amount: f64, currency: Currency) -> Result<Self, MoneyError> {
+        Ok(Self::new(decimal(amount)?, currency))
+    }
+
+    /// For metrics and charts, which do not need exact amounts
+    pub fn to_f64(&self) -> f64 {
+        self.amount.try_into().unwrap_or(f64::NAN)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.amount.is_zero()
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        Ok(Self::new(self.amount + other.amount, self.currency))
+    }
+
+    pub fn checked_sub(self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        Ok(Self::new(self.amount - other.amount, self.currency))
+    }
+
+    /// Sum of the amounts, zero if there are none
+    pub fn sum(
+        currency: Currency,
+        amounts: impl IntoIterator<Item = Money>,
+    ) -> Result<Money, MoneyError> {
+        amounts
+            .into_iter()
+            .try_fold(Money::zero(currency), Money::checked_add)
+    }
+
+    /// Rounded half away from zero to the smallest unit of the currency, e.g. cents
+    pub fn round(&self) -> Money {
+        self.round_dp(self.currency.minor_units())
+    }
+
+    /// Rounded half away from zero to `dp` digits, e.g. 4 for prices per kWh
+    pub fn round_dp(&self, dp: u32) -> Money {
+        let amount = self
+            .amount
+            .round_dp_with_strategy(dp, RoundingStrategy::MidpointAwayFromZero);
+        Self::new(amount, self.currency)
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), MoneyError> {
+        if self.currency == other.currency {
+            Ok(())
+        } else {
+            Err(MoneyError::CurrencyMismatch(self.currency, other.currency))
+        }
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Self::new(-self.amount, self.currency)
+    }
+}
+
+/// A price per kWh times the energy
+impl Mul<Decimal> for Money {
+    type Output = Money;
+
+    fn mul(self, factor: Decimal) -> Money {
+        Self::new(self.amount * factor, self.currency)
+    }
+}
+
+impl Div<Decimal> for Money {
+    type Output = Money;
+
+    fn div(self, divisor: Decimal) -> Money {
+        Self::new(self.amount / divisor, self.currency)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match f.precision() {
+            Some(dp) => {
+                let amount = self.round_dp(dp as u32).amount;
+                write!(f, "{:.*} {}", dp, amount, self.currency)
+            }
+            None => write!(f, "{} {}", self.amount, self.currency),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::topic::{Decode, Encode};
+
+    fn eur(amount: &str) -> Money {
+        Money::new(amount.parse().unwrap(), Currency::EUR)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        // 0.1 + 0.2 is 0.30000000000000004 with floats
+        let sum = Money::from_f64(0.1, Currency::EUR)
+            .unwrap()
+            .checked_add(Money::from_f64(0.2, Currency::EUR).unwrap());
+        assert_eq!(sum, Ok(eur("0.3")));
+
+        // Cost of 0.6 kWh at 0.2837 per kWh
+        let cost = eur("0.2837") * decimal(0.6).unwrap();
+        assert_eq!(cost, eur("0.17022"));
+        assert_eq!(cost.round(), eur("0.17"));
+        assert_eq!(eur("0.125").round(), eur("0.13"));
+        assert_eq!(format!("{:.2}", eur("1.005")), "1.01 EUR");
+        assert_eq!(format!("{:.2}", eur("3")), "3.00 EUR");
+        assert_eq!(format!("{}", eur("0.2512")), "0.2512 EUR");
+        assert_eq!(
+            Money::sum(Currency::EUR, [eur("1"), eur("2.5")]),
+            Ok(eur("3.5"))
+        );
+        assert_eq!(Money::sum(Currency::EUR, []), Ok(eur("0")));
+
+        let nok = Money::zero("NOK".parse().unwrap());
+        assert!(matches!(
+            eur("1").checked_add(nok),
+            Err(MoneyError::CurrencyMismatch(..))
+        ));
+        assert!(eur("1").checked_sub(nok).is_err());
+        assert!(Money::sum(Currency::EUR, [eur("1"), nok]).is_err());
+        assert!(decimal(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_serialization() {
+        let price = eur("0.30120");
+        let encoded = Money::encode(&price);
+        assert_eq!(encoded, r#"{"amount":"0.30120","currency":"EUR"}"#);
+        assert_eq!(Money::decode(&encoded).unwrap(), price);
+
+        // Numbers are accepted as well
+        let decoded = Money::decode(r#"{"amount":0.25,"currency":"SEK"}"#).unwrap();
+        assert_eq!(decoded.amount, "0.25".parse().unwrap());
+        assert!(Money::decode(r#"{"amount":"1","currency":"euro"}"#).is_err());
+    }
+}
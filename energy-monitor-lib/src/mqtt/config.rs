@@ -0,0 +1,304 @@
+use bytes::Bytes;
+use rumqttc::{
+    v5::{
+        self,
+        mqttbytes::v5::{ConnectProperties, LastWill as LastWillV5},
+    },
+    LastWill, MqttOptions, QoS, TlsConfiguration, Transport,
+};
+use std::{env, fs, io, path::PathBuf, time::Duration};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MqttConfigError {
+    #[error("{name} is invalid: {value}")]
+    InvalidValue { name: String, value: String },
+    #[error("Failed to read {path}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("TLS requires a CA certificate, set {0}")]
+    MissingCa(String),
+    #[error("A client certificate requires a key, set {0}")]
+    MissingClientKey(String),
+    #[error("Websocket transport requires the websocket feature")]
+    WebsocketUnsupported,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    V4,
+    V5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportKind {
+    Tcp,
+    Tls,
+    /// Websocket, the broker is reached on `ws://host:port/path`
+    Ws,
+    Wss,
+}
+
+/// Certificates in PEM format
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsFiles {
+    pub ca: Vec<u8>,
+    /// Certificate and key authenticating the client
+    pub client_auth: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+/// How to reach the broker
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttConfig {
+    pub client_id: String,
+    pub host: String,
+    pub port: u16,
+    pub keep_alive: Duration,
+    pub credentials: Option<(String, String)>,
+    pub transport: TransportKind,
+    pub tls: Option<TlsFiles>,
+    pub websocket_path: String,
+    pub protocol: Protocol,
+    /// How long the broker keeps the session after the connection is lost,
+    /// MQTT v5 only
+    pub session_expiry: Option<Duration>,
+}
+
+impl MqttConfig {
+    /// Plain TCP without credentials
+    pub fn new(client_id: &str, host: &str, port: u16) -> Self {
+        Self {
+            client_id: client_id.to_string(),
+            host: host.to_string(),
+            port,
+            keep_alive: Duration::from_secs(5),
+            credentials: None,
+            transport: TransportKind::Tcp,
+            tls: None,
+            websocket_path: "/mqtt".to_string(),
+            protocol: Protocol::V4,
+            session_expiry: None,
+        }
+    }
+
+    /// Overrides the settings with the `<prefix>_MQTT_*` environment variables
+    pub fn load_env(&mut self, prefix: &str) -> Result<(), MqttConfigError> {
+        self.apply(prefix, |name| env::var(name).ok())
+    }
+
+    fn apply(
+        &mut self,
+        prefix: &str,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<(), MqttConfigError> {
+        let name = |suffix: &str| format!("{prefix}_MQTT_{suffix}");
+        let invalid = |suffix: &str, value: &str| MqttConfigError::InvalidValue {
+            name: name(suffix),
+            value: value.to_string(),
+        };
+        let read = |suffix: &str| -> Result<Option<Vec<u8>>, MqttConfigError> {
+            var(&name(suffix))
+                .map(|path| {
+                    fs::read(&path).map_err(|source| MqttConfigError::Read {
+                        path: path.into(),
+                        source,
+                    })
+                })
+                .transpose()
+        };
+
+        if let Some(host) = var(&name("HOST")) {
+            self.host = host;
+        }
+        if let Some(port) = var(&name("PORT")) {
+            self.port = port.parse().map_err(|_| invalid("PORT", &port))?;
+        }
+        if let Some(username) = var(&name("USERNAME")) {
+            let password = var(&name("PASSWORD")).unwrap_or_default();
+            self.credentials = Some((username, password));
+        }
+
+        let ca = read("CA_FILE")?;
+        if let Some(transport) = var(&name("TRANSPORT")) {
+            self.transport = match transport.as_str() {
+                "tcp" => TransportKind::Tcp,
+                "tls" => TransportKind::Tls,
+                "ws" => TransportKind::Ws,
+                "wss" => TransportKind::Wss,
+                _ => return Err(invalid("TRANSPORT", &transport)),
+            };
+        } else if ca.is_some() {
+            self.transport = TransportKind::Tls;
+        }
+        if let Some(ca) = ca {
+            let client_auth = match read("CLIENT_CERT")? {
+                Some(cert) => {
+                    let key = read("CLIENT_KEY")?
+                        .ok_or_else(|| MqttConfigError::MissingClientKey(name("CLIENT_KEY")))?;
+                    Some((cert, key))
+                }
+                None => None,
+            };
+            self.tls = Some(TlsFiles { ca, client_auth });
+        }
+        if matches!(self.transport, TransportKind::Tls | TransportKind::Wss) && self.tls.is_none() {
+            return Err(MqttConfigError::MissingCa(name("CA_FILE")));
+        }
+        if let Some(path) = var(&name("WS_PATH")) {
+            self.websocket_path = path;
+        }
+
+        if let Some(version) = var(&name("VERSION")) {
+            self.protocol = match version.as_str() {
+                "4" | "3.1.1" => Protocol::V4,
+                "5" => Protocol::V5,
+                _ => return Err(invalid("VERSION", &version)),
+            };
+        }
+        if let Some(expiry) = var(&name("SESSION_EXPIRY")) {
+            let seconds = expiry
+                .parse()
+                .map_err(|_| invalid("SESSION_EXPIRY", &expiry))?;
+            self.session_expiry = Some(Duration::from_secs(seconds));
+        }
+        Ok(())
+    }
+
+    /// Host passed to the client, websockets expect the whole URL
+    fn broker_host(&self) -> String {
+        match self.transport {
+            TransportKind::Tcp | TransportKind::Tls => self.host.clone(),
+            TransportKind::Ws => format!("ws://{}:{}{}", self.host, self.port, self.websocket_path),
+            TransportKind::Wss => {
+                format!("wss://{}:{}{}", self.host, self.port, self.websocket_path)
+            }
+        }
+    }
+
+    fn transport(&self) -> Result<Transport, MqttConfigError> {
+        let tls = || {
+            let tls = self.tls.clone().unwrap_or(TlsFiles {
+                ca: vec![],
+                client_auth: None,
+            });
+            TlsConfiguration::Simple {
+                ca: tls.ca,
+                alpn: None,
+                client_auth: tls.client_auth,
+            }
+        };
+        match self.transport {
+            TransportKind::Tcp => Ok(Transport::Tcp),
+            TransportKind::Tls => Ok(Transport::Tls(tls())),
+            #[cfg(feature = "websocket")]
+            TransportKind::Ws => Ok(Transport::Ws),
+            #[cfg(feature = "websocket")]
+            TransportKind::Wss => Ok(Transport::Wss(tls())),
+            #[cfg(not(feature = "websocket"))]
+            TransportKind::Ws | TransportKind::Wss => Err(MqttConfigError::WebsocketUnsupported),
+        }
+    }
+
+    /// Options of an MQTT 3.1.1 connection with `will` as last will
+    pub(super) fn v4_options(&self, will: (String, Bytes)) -> Result<MqttOptions, MqttConfigError> {
+        let mut options = MqttOptions::new(&self.client_id, self.broker_host(), self.port);
+        options
+            .set_keep_alive(self.keep_alive)
+            .set_transport(self.transport()?)
+            .set_last_will(LastWill::new(will.0, will.1, QoS::AtLeastOnce, true));
+        if let Some((username, password)) = &self.credentials {
+            options.set_credentials(username, password);
+        }
+        Ok(options)
+    }
+
+    pub(super) fn v5_options(
+        &self,
+        will: (String, Bytes),
+    ) -> Result<v5::MqttOptions, MqttConfigError> {
+        let mut options = v5::MqttOptions::new(&self.client_id, self.broker_host(), self.port);
+        options
+            .set_keep_alive(self.keep_alive)
+            .set_transport(self.transport()?)
+            .set_last_will(LastWillV5::new(
+                will.0,
+                will.1,
+                v5::mqttbytes::QoS::AtLeastOnce,
+                true,
+                None,
+            ));
+        if let Some((username, password)) = &self.credentials {
+            options.set_credentials(username, password);
+        }
+        if let Some(expiry) = self.session_expiry {
+            let mut properties = ConnectProperties::new();
+            properties.session_expiry_interval = Some(expiry.as_secs() as u32);
+            options.set_connect_properties(properties);
+            // The session is only kept if the client does not start a new one
+            options.set_clean_start(false);
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_apply_env() {
+        let ca = env::temp_dir().join(format!("mqtt-ca-{}.pem", std::process::id()));
+        fs::write(&ca, "ca").unwrap();
+        let vars = BTreeMap::from([
+            ("EMTEST_MQTT_HOST", "broker"),
+            ("EMTEST_MQTT_PORT", "8883"),
+            ("EMTEST_MQTT_USERNAME", "emtibberd"),
+            ("EMTEST_MQTT_PASSWORD", "secret"),
+            ("EMTEST_MQTT_CA_FILE", ca.to_str().unwrap()),
+            ("EMTEST_MQTT_VERSION", "5"),
+            ("EMTEST_MQTT_SESSION_EXPIRY", "3600"),
+        ]);
+        let var = |name: &str| vars.get(name).map(|value| value.to_string());
+
+        let mut config = MqttConfig::new("test", "rpiserver", 1883);
+        config.apply("EMTEST", var).unwrap();
+        assert_eq!(
+            config,
+            MqttConfig {
+                host: "broker".to_string(),
+                port: 8883,
+                credentials: Some(("emtibberd".to_string(), "secret".to_string())),
+                transport: TransportKind::Tls,
+                tls: Some(TlsFiles {
+                    ca: b"ca".to_vec(),
+                    client_auth: None,
+                }),
+                protocol: Protocol::V5,
+                session_expiry: Some(Duration::from_secs(3600)),
+                ..MqttConfig::new("test", "rpiserver", 1883)
+            }
+        );
+
+        // A client certificate needs its key, TLS needs a CA
+        let mut config = MqttConfig::new("test", "rpiserver", 1883);
+        let vars = BTreeMap::from([
+            ("EMTEST_MQTT_CA_FILE", ca.to_str().unwrap()),
+            ("EMTEST_MQTT_CLIENT_CERT", ca.to_str().unwrap()),
+        ]);
+        let var = |name: &str| vars.get(name).map(|value| value.to_string());
+        assert!(matches!(
+            config.apply("EMTEST", var),
+            Err(MqttConfigError::MissingClientKey(_))
+        ));
+        let var = |name: &str| (name == "EMTEST_MQTT_TRANSPORT").then(|| "tls".to_string());
+        assert!(matches!(
+            config.apply("EMTEST", var),
+            Err(MqttConfigError::MissingCa(_))
+        ));
+        fs::remove_file(ca).unwrap();
+    }
+}
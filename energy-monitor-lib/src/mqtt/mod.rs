@@ -0,0 +1,971 @@
+//! MQTT connection surviving broker restarts. The [`Supervisor`] drives the
+//! event loop and reconnects with an increasing delay, the [`MqttClient`]
+//! remembers the subscriptions and queues messages while disconnected.
+//! Both speak MQTT 3.1.1 or 5 depending on the [`MqttConfig`].
+
+use crate::{
+    service::{Availability, Daemon, DaemonStatus},
+    topic::{Delivery, Encode, SCHEMA_VERSION},
+};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use rumqttc::{
+    v5::{
+        self,
+        mqttbytes::v5::{Filter, PublishProperties},
+    },
+    AsyncClient, ConnAck, ConnectReturnCode, Event, EventLoop, Outgoing, Packet, Publish, QoS,
+    Request, Subscribe,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::{sync::watch, time::sleep};
+use tokio_util::sync::CancellationToken;
+
+pub mod config;
+
+pub use config::{MqttConfig, MqttConfigError, Protocol, TransportKind};
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+const REQUEST_CHANNEL_CAPACITY: usize = 10;
+/// Messages published while disconnected, the oldest are dropped first
+const OFFLINE_QUEUE_CAPACITY: usize = 100;
+
+#[derive(Debug, Error)]
+pub enum MqttError {
+    #[error(transparent)]
+    V4(#[from] rumqttc::ClientError),
+    #[error(transparent)]
+    V5(#[from] v5::ClientError),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// Published retained on the health topic of the daemon
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MqttHealth {
+    pub state: ConnectionState,
+    pub since: DateTime<Utc>,
+    pub reconnects: u64,
+    /// Messages lost because the offline queue was full
+    pub dropped: u64,
+}
+
+pub fn health_topic(daemon: &str) -> String {
+    format!("energy-monitor/{daemon}/health")
+}
+
+/// A message waiting to be sent
+#[derive(Debug, Clone)]
+struct Message {
+    topic: String,
+    delivery: Delivery,
+    payload: Bytes,
+    created: Instant,
+}
+
+impl Message {
+    fn new(topic: String, delivery: Delivery, payload: Bytes) -> Self {
+        Self {
+            topic,
+            delivery,
+            payload,
+            created: Instant::now(),
+        }
+    }
+
+    /// Time left until the message expires, `Some(ZERO)` if it did already
+    fn expiry(&self) -> Option<Duration> {
+        self.delivery
+            .expiry
+            .map(|expiry| expiry.saturating_sub(self.created.elapsed()))
+    }
+
+    fn to_v4(&self) -> Publish {
+        let mut publish = Publish::from_bytes(&self.topic, self.delivery.qos, self.payload.clone());
+        publish.retain = self.delivery.retain;
+        publish
+    }
+
+    fn to_v5(&self) -> v5::mqttbytes::v5::Publish {
+        let mut publish = v5::mqttbytes::v5::Publish::new(
+            &self.topic,
+            v5_qos(self.delivery.qos),
+            self.payload.clone(),
+            Some(publish_properties(self.expiry())),
+        );
+        publish.retain = self.delivery.retain;
+        publish
+    }
+}
+
+/// The schema version travels with every message, the expiry lets the
+/// broker drop messages nobody received in time
+fn publish_properties(expiry: Option<Duration>) -> PublishProperties {
+    PublishProperties {
+        message_expiry_interval: expiry.map(|expiry| expiry.as_secs().max(1) as u32),
+        user_properties: vec![("schema_version".to_string(), SCHEMA_VERSION.to_string())],
+        ..Default::default()
+    }
+}
+
+fn v5_qos(qos: QoS) -> v5::mqttbytes::QoS {
+    match qos {
+        QoS::AtMostOnce => v5::mqttbytes::QoS::AtMostOnce,
+        QoS::AtLeastOnce => v5::mqttbytes::QoS::AtLeastOnce,
+        QoS::ExactlyOnce => v5::mqttbytes::QoS::ExactlyOnce,
+    }
+}
+
+fn v4_qos(qos: v5::mqttbytes::QoS) -> QoS {
+    match qos {
+        v5::mqttbytes::QoS::AtMostOnce => QoS::AtMostOnce,
+        v5::mqttbytes::QoS::AtLeastOnce => QoS::AtLeastOnce,
+        v5::mqttbytes::QoS::ExactlyOnce => QoS::ExactlyOnce,
+    }
+}
+
+/// The daemons handle the MQTT 3.1.1 events, MQTT 5 events are converted
+fn from_v5(event: v5::Event) -> Option<Event> {
+    match event {
+        v5::Event::Incoming(v5::Incoming::ConnAck(connack)) => {
+            Some(Event::Incoming(Packet::ConnAck(ConnAck {
+                session_present: connack.session_present,
+                code: ConnectReturnCode::Success,
+            })))
+        }
+        v5::Event::Incoming(v5::Incoming::Publish(publish)) => {
+            let topic = String::from_utf8(publish.topic.to_vec()).ok()?;
+            let mut converted = Publish::from_bytes(topic, v4_qos(publish.qos), publish.payload);
+            converted.retain = publish.retain;
+            Some(Event::Incoming(Packet::Publish(converted)))
+        }
+        v5::Event::Outgoing(outgoing) => Some(Event::Outgoing(outgoing)),
+        v5::Event::Incoming(_) => None,
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    subscriptions: Vec<(String, QoS)>,
+    queue: VecDeque<Message>,
+    capacity: usize,
+    reconnects: u64,
+    dropped: u64,
+    /// Last time the supervisor handled an event, a scheduled reconnect
+    /// counts at the time it is due
+    last_activity: Instant,
+}
+
+impl Shared {
+    fn health(&self, state: ConnectionState) -> MqttHealth {
+        MqttHealth {
+            state,
+            since: Utc::now(),
+            reconnects: self.reconnects,
+            dropped: self.dropped,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Client {
+    V4(AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+enum Connection {
+    V4(Box<EventLoop>),
+    V5(Box<v5::EventLoop>),
+}
+
+/// Connects to the broker with the last will of `daemon` set to offline
+pub fn connect(
+    config: &MqttConfig,
+    daemon: &Daemon,
+) -> Result<(MqttClient, Supervisor), MqttConfigError> {
+    connect_with_capacity(config, daemon, OFFLINE_QUEUE_CAPACITY)
+}
+
+fn connect_with_capacity(
+    config: &MqttConfig,
+    daemon: &Daemon,
+    capacity: usize,
+) -> Result<(MqttClient, Supervisor), MqttConfigError> {
+    let will = (
+        daemon.status_topic(),
+        Bytes::from(Availability::Offline.payload()),
+    );
+    let (client, connection) = match config.protocol {
+        Protocol::V4 => {
+            let (client, eventloop) =
+                AsyncClient::new(config.v4_options(will)?, REQUEST_CHANNEL_CAPACITY);
+            (Client::V4(client), Connection::V4(Box::new(eventloop)))
+        }
+        Protocol::V5 => {
+            let (client, eventloop) =
+                v5::AsyncClient::new(config.v5_options(will)?, REQUEST_CHANNEL_CAPACITY);
+            (Client::V5(client), Connection::V5(Box::new(eventloop)))
+        }
+    };
+    let (state_tx, state) = watch::channel(ConnectionState::Connecting);
+    let shared = Arc::new(Mutex::new(Shared {
+        subscriptions: vec![],
+        queue: VecDeque::new(),
+        capacity,
+        reconnects: 0,
+        dropped: 0,
+        last_activity: Instant::now(),
+    }));
+    let stopping = CancellationToken::new();
+
+    let client = MqttClient {
+        client,
+        daemon: daemon.clone(),
+        shared: shared.clone(),
+        state,
+        stopping: stopping.clone(),
+    };
+    let supervisor = Supervisor {
+        connection,
+        daemon: daemon.clone(),
+        shared,
+        state: state_tx,
+        stopping,
+        delay: MIN_RECONNECT_DELAY,
+        connected_once: false,
+    };
+    Ok((client, supervisor))
+}
+
+#[derive(Clone)]
+pub struct MqttClient {
+    client: Client,
+    daemon: Daemon,
+    shared: Arc<Mutex<Shared>>,
+    state: watch::Receiver<ConnectionState>,
+    stopping: CancellationToken,
+}
+
+impl MqttClient {
+    /// Current connection state, changes can be awaited on the receiver
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Whether the supervisor handled an event or tried to reconnect within
+    /// `period`. With keep-alive pings it stops only if it is not polled.
+    pub fn is_active(&self, period: Duration) -> bool {
+        self.shared.lock().unwrap().last_activity + period >= Instant::now()
+    }
+
+    fn is_connected(&self) -> bool {
+        *self.state.borrow() == ConnectionState::Connected
+    }
+
+    /// Subscribes now and again after every reconnect
+    pub async fn subscribe(&self, topic: impl Into<String>, qos: QoS) -> Result<(), MqttError> {
+        let topic = topic.into();
+        self.shared
+            .lock()
+            .unwrap()
+            .subscriptions
+            .push((topic.clone(), qos));
+        if self.is_connected() {
+            match &self.client {
+                Client::V4(client) => client.subscribe(topic, qos).await?,
+                Client::V5(client) => client.subscribe(topic, v5_qos(qos)).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Publishes the payload, while disconnected it is queued until the
+    /// connection is back
+    pub async fn publish(
+        &self,
+        topic: impl Into<String>,
+        delivery: Delivery,
+        payload: Bytes,
+    ) -> Result<(), MqttError> {
+        let topic = topic.into();
+        if self.is_connected() {
+            let Delivery { qos, retain, .. } = delivery;
+            match &self.client {
+                Client::V4(client) => client.publish_bytes(topic, qos, retain, payload).await?,
+                Client::V5(client) => {
+                    client
+                        .publish_bytes_with_properties(
+                            topic,
+                            v5_qos(qos),
+                            retain,
+                            payload,
+                            publish_properties(delivery.expiry),
+                        )
+                        .await?
+                }
+            }
+            return Ok(());
+        }
+
+        let mut shared = self.shared.lock().unwrap();
+        if shared.queue.len() >= shared.capacity {
+            shared.queue.pop_front();
+            shared.dropped += 1;
+        }
+        shared
+            .queue
+            .push_back(Message::new(topic, delivery, payload));
+        Ok(())
+    }
+
+    /// Publishes the offline status and disconnects, the supervisor stops
+    /// once everything is sent
+    pub async fn disconnect(&self) -> Result<(), MqttError> {
+        self.stopping.cancel();
+        if !self.is_connected() {
+            return Ok(());
+        }
+        let health = self
+            .shared
+            .lock()
+            .unwrap()
+            .health(ConnectionState::Disconnected);
+        self.publish(
+            health_topic(&self.daemon.name),
+            Delivery::STATE,
+            MqttHealth::encode(&health),
+        )
+        .await?;
+        let offline = self.daemon.status(Availability::Offline);
+        self.publish(
+            self.daemon.info_topic(),
+            Delivery::STATE,
+            DaemonStatus::encode(&offline),
+        )
+        .await?;
+        self.publish(
+            self.daemon.status_topic(),
+            Delivery::STATE,
+            Availability::Offline.payload().into(),
+        )
+        .await?;
+        match &self.client {
+            Client::V4(client) => client.disconnect().await?,
+            Client::V5(client) => client.disconnect().await?,
+        }
+        Ok(())
+    }
+}
+
+/// Drives the event loop of the [`MqttClient`]
+pub struct Supervisor {
+    connection: Connection,
+    daemon: Daemon,
+    shared: Arc<Mutex<Shared>>,
+    state: watch::Sender<ConnectionState>,
+    stopping: CancellationToken,
+    delay: Duration,
+    connected_once: bool,
+}
+
+impl Supervisor {
+    /// Next event of the connection, reconnects after errors. Returns `None`
+    /// once [`MqttClient::disconnect`] was called and everything was sent.
+    pub async fn poll(&mut self) -> Option<Event> {
+        loop {
+            let connected = *self.state.borrow() == ConnectionState::Connected;
+            let stopping = self.stopping.clone();
+            let result = if connected {
+                self.next_event().await
+            } else {
+                tokio::select! {
+                    result = self.next_event() => result,
+                    _ = stopping.cancelled() => return None,
+                }
+            };
+
+            self.shared.lock().unwrap().last_activity = Instant::now();
+            match result {
+                Ok(Some(Event::Incoming(Packet::ConnAck(connack)))) => {
+                    self.connected();
+                    return Some(Event::Incoming(Packet::ConnAck(connack)));
+                }
+                Ok(Some(Event::Outgoing(Outgoing::Disconnect))) => {
+                    self.state.send_replace(ConnectionState::Disconnected);
+                    return None;
+                }
+                Ok(Some(event)) => return Some(event),
+                Ok(None) => {}
+                Err(e) => {
+                    if connected {
+                        warn!("Connection to the broker lost: {:?}", e);
+                        self.state.send_replace(ConnectionState::Disconnected);
+                    }
+                    if self.stopping.is_cancelled() {
+                        return None;
+                    }
+                    warn!("Reconnecting in {:?}: {:?}", self.delay, e);
+                    self.shared.lock().unwrap().last_activity = Instant::now() + self.delay;
+                    tokio::select! {
+                        _ = sleep(self.delay) => {}
+                        _ = self.stopping.cancelled() => return None,
+                    }
+                    self.delay = (self.delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        }
+    }
+
+    async fn next_event(&mut self) -> Result<Option<Event>, Box<dyn Error + Send + Sync>> {
+        match &mut self.connection {
+            Connection::V4(eventloop) => Ok(Some(eventloop.poll().await?)),
+            Connection::V5(eventloop) => Ok(from_v5(eventloop.poll().await?)),
+        }
+    }
+
+    /// Subscribes again and sends the status, the health and the queued
+    /// messages before anything else
+    fn connected(&mut self) {
+        self.delay = MIN_RECONNECT_DELAY;
+        let mut shared = self.shared.lock().unwrap();
+        if self.connected_once {
+            shared.reconnects += 1;
+        }
+        self.connected_once = true;
+        info!("Connected to the broker, {} reconnects", shared.reconnects);
+
+        let health = shared.health(ConnectionState::Connected);
+        let mut messages = vec![
+            Message::new(
+                self.daemon.status_topic(),
+                Delivery::STATE,
+                Availability::Online.payload().into(),
+            ),
+            Message::new(
+                self.daemon.info_topic(),
+                Delivery::STATE,
+                DaemonStatus::encode(&self.daemon.status(Availability::Online)),
+            ),
+            Message::new(
+                health_topic(&self.daemon.name),
+                Delivery::STATE,
+                MqttHealth::encode(&health),
+            ),
+        ];
+        messages.extend(
+            shared
+                .queue
+                .drain(..)
+                .filter(|message| message.expiry() != Some(Duration::ZERO)),
+        );
+
+        match &mut self.connection {
+            Connection::V4(eventloop) => {
+                for (topic, qos) in &shared.subscriptions {
+                    let subscribe = Subscribe::new(topic, *qos);
+                    eventloop.pending.push_back(Request::Subscribe(subscribe));
+                }
+                for message in &messages {
+                    eventloop
+                        .pending
+                        .push_back(Request::Publish(message.to_v4()));
+                }
+            }
+            Connection::V5(eventloop) => {
+                for (topic, qos) in &shared.subscriptions {
+                    let filter = Filter::new(topic, v5_qos(*qos));
+                    let subscribe = v5::mqttbytes::v5::Subscribe::new(filter, None);
+                    eventloop
+                        .pending
+                        .push_back(v5::Request::Subscribe(subscribe));
+                }
+                for message in &messages {
+                    eventloop
+                        .pending
+                        .push_back(v5::Request::Publish(message.to_v5()));
+                }
+            }
+        }
+        drop(shared);
+
+        self.state.send_replace(ConnectionState::Connected);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::BytesMut;
+    use rumqttc::{v5::mqttbytes::v5 as packet5, PingResp, PubAck, SubAck, SubscribeReasonCode};
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::mpsc,
+        time::timeout,
+    };
+    use tokio_rustls::TlsAcceptor;
+
+    /// What the broker received from clients speaking MQTT 5
+    #[derive(Debug, Default)]
+    struct V5Received {
+        session_expiry: Option<u32>,
+        /// Topic and properties of every publish
+        publishes: Vec<(String, PublishProperties)>,
+    }
+
+    /// Broker started by the tests. Speaks just enough MQTT 3.1.1 and 5 to
+    /// send every publish back to the client if it subscribed to the topic.
+    struct Broker {
+        port: u16,
+        received: Arc<Mutex<V5Received>>,
+    }
+
+    impl Broker {
+        async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let received = Arc::new(Mutex::new(V5Received::default()));
+            let shared = received.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, shared.clone()));
+                }
+            });
+            Self { port, received }
+        }
+    }
+
+    /// Protocol level of a CONNECT packet, after the fixed header and the
+    /// protocol name
+    fn protocol_level(packet: &[u8]) -> Option<u8> {
+        let len_len = packet.iter().skip(1).position(|byte| byte & 0x80 == 0)? + 1;
+        packet.get(1 + len_len + 6).copied()
+    }
+
+    /// Serves one client until it disconnects
+    async fn serve(mut stream: TcpStream, received: Arc<Mutex<V5Received>>) {
+        let mut buf = BytesMut::new();
+        let level = loop {
+            if let Some(level) = protocol_level(&buf) {
+                break level;
+            }
+            if stream.read_buf(&mut buf).await.unwrap_or(0) == 0 {
+                return;
+            }
+        };
+        let mut subscriptions = vec![];
+        loop {
+            let mut out = BytesMut::new();
+            let read = if level == 5 {
+                packet5::Packet::read(&mut buf, None)
+                    .map(|packet| reply_v5(packet, &mut subscriptions, &received, &mut out))
+                    .map_err(|e| matches!(e, v5::mqttbytes::Error::InsufficientBytes(_)))
+            } else {
+                rumqttc::mqttbytes::v4::read(&mut buf, 1 << 20)
+                    .map(|packet| reply_v4(packet, &mut subscriptions, &mut out))
+                    .map_err(|e| matches!(e, rumqttc::mqttbytes::Error::InsufficientBytes(_)))
+            };
+            match read {
+                Ok(true) => {
+                    if stream.write_all(&out).await.is_err() {
+                        return;
+                    }
+                }
+                Err(true) => {
+                    if stream.read_buf(&mut buf).await.unwrap_or(0) == 0 {
+                        return;
+                    }
+                }
+                // Disconnected or a malformed packet
+                Ok(false) | Err(false) => return,
+            }
+        }
+    }
+
+    /// Writes the reply to `packet`, false if the client disconnects
+    fn reply_v4(packet: Packet, subscriptions: &mut Vec<String>, out: &mut BytesMut) -> bool {
+        match packet {
+            Packet::Connect(_) => ConnAck::new(ConnectReturnCode::Success, false).write(out),
+            Packet::Subscribe(subscribe) => {
+                let codes = subscribe
+                    .filters
+                    .into_iter()
+                    .map(|filter| {
+                        subscriptions.push(filter.path);
+                        SubscribeReasonCode::Success(filter.qos)
+                    })
+                    .collect();
+                SubAck::new(subscribe.pkid, codes).write(out)
+            }
+            Packet::Publish(publish) => {
+                if publish.qos != QoS::AtMostOnce {
+                    PubAck::new(publish.pkid).write(out).unwrap();
+                }
+                if !subscriptions.contains(&publish.topic) {
+                    return true;
+                }
+                Publish::new(publish.topic, QoS::AtMostOnce, publish.payload).write(out)
+            }
+            Packet::PingReq => PingResp.write(out),
+            Packet::Disconnect => return false,
+            _ => return true,
+        }
+        .unwrap();
+        true
+    }
+
+    /// Writes the reply to `packet` and records the properties the client
+    /// sent, false if the client disconnects
+    fn reply_v5(
+        packet: packet5::Packet,
+        subscriptions: &mut Vec<String>,
+        received: &Mutex<V5Received>,
+        out: &mut BytesMut,
+    ) -> bool {
+        use packet5::Packet;
+
+        let reply = match packet {
+            Packet::Connect(connect, _, _) => {
+                received.lock().unwrap().session_expiry = connect
+                    .properties
+                    .and_then(|properties| properties.session_expiry_interval);
+                Packet::ConnAck(packet5::ConnAck {
+                    session_present: false,
+                    code: packet5::ConnectReturnCode::Success,
+                    properties: None,
+                })
+            }
+            Packet::Subscribe(subscribe) => {
+                let return_codes = subscribe
+                    .filters
+                    .into_iter()
+                    .map(|filter| {
+                        subscriptions.push(filter.path);
+                        packet5::SubscribeReasonCode::Success(filter.qos)
+                    })
+                    .collect();
+                Packet::SubAck(packet5::SubAck {
+                    pkid: subscribe.pkid,
+                    return_codes,
+                    properties: None,
+                })
+            }
+            Packet::Publish(publish) => {
+                let topic = String::from_utf8(publish.topic.to_vec()).unwrap();
+                let properties = publish.properties.clone().unwrap_or_default();
+                received
+                    .lock()
+                    .unwrap()
+                    .publishes
+                    .push((topic.clone(), properties));
+                if publish.qos != v5::mqttbytes::QoS::AtMostOnce {
+                    Packet::PubAck(packet5::PubAck::new(publish.pkid, None))
+                        .write(out)
+                        .unwrap();
+                }
+                if !subscriptions.contains(&topic) {
+                    return true;
+                }
+                Packet::Publish(packet5::Publish {
+                    qos: v5::mqttbytes::QoS::AtMostOnce,
+                    pkid: 0,
+                    ..publish
+                })
+            }
+            Packet::PingReq(_) => Packet::PingResp(packet5::PingResp),
+            Packet::Disconnect(_) => return false,
+            _ => return true,
+        };
+        reply.write(out).unwrap();
+        true
+    }
+
+    const CONNACK: [u8; 4] = [0x20, 0x02, 0x00, 0x00];
+
+    /// Reads one packet and describes it by type and topic
+    async fn read_packet(stream: &mut (impl AsyncRead + Unpin)) -> String {
+        let packet_type = stream.read_u8().await.unwrap() >> 4;
+        let mut len = 0;
+        for shift in (0..4).map(|i| i * 7) {
+            let byte = stream.read_u8().await.unwrap();
+            len |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await.unwrap();
+
+        let topic = |start: usize| {
+            let len = u16::from_be_bytes([body[start], body[start + 1]]) as usize;
+            String::from_utf8(body[start + 2..start + 2 + len].to_vec()).unwrap()
+        };
+        match packet_type {
+            1 => "CONNECT".to_string(),
+            3 => format!("PUBLISH {}", topic(0)),
+            8 => format!("SUBSCRIBE {}", topic(2)),
+            _ => format!("{packet_type}"),
+        }
+    }
+
+    async fn accept(listener: &TcpListener) -> TcpStream {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        connack(&mut stream).await;
+        stream
+    }
+
+    async fn connack(stream: &mut (impl AsyncRead + AsyncWrite + Unpin)) {
+        assert_eq!(read_packet(stream).await, "CONNECT");
+        stream.write_all(&CONNACK).await.unwrap();
+    }
+
+    async fn read_packets(stream: &mut (impl AsyncRead + Unpin), count: usize) -> Vec<String> {
+        let mut packets = vec![];
+        for _ in 0..count {
+            packets.push(read_packet(stream).await);
+        }
+        packets
+    }
+
+    #[tokio::test]
+    async fn test_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut config = MqttConfig::new("test", "127.0.0.1", port);
+        config.keep_alive = Duration::from_secs(60);
+        let (client, mut supervisor) =
+            connect_with_capacity(&config, &Daemon::new("test", "0.1.0"), 2).unwrap();
+
+        // Not connected yet, the oldest message is dropped
+        client.subscribe("source", QoS::AtMostOnce).await.unwrap();
+        for topic in ["first", "second", "third"] {
+            client
+                .publish(topic, Delivery::STREAM, Bytes::new())
+                .await
+                .unwrap();
+        }
+        tokio::spawn(async move { while supervisor.poll().await.is_some() {} });
+
+        let mut stream = accept(&listener).await;
+        let mut state = client.state();
+        state
+            .wait_for(|state| *state == ConnectionState::Connected)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_packets(&mut stream, 6).await,
+            [
+                "SUBSCRIBE source",
+                "PUBLISH energy-monitor/test/status",
+                "PUBLISH energy-monitor/test/info",
+                "PUBLISH energy-monitor/test/health",
+                "PUBLISH second",
+                "PUBLISH third",
+            ]
+        );
+        assert_eq!(client.shared.lock().unwrap().dropped, 1);
+
+        // The broker goes away, the supervisor reconnects and subscribes again
+        drop(stream);
+        state
+            .wait_for(|state| *state == ConnectionState::Disconnected)
+            .await
+            .unwrap();
+        let mut stream = timeout(Duration::from_secs(5), accept(&listener))
+            .await
+            .unwrap();
+        // Unacknowledged messages of the last session are sent first
+        while read_packet(&mut stream).await != "SUBSCRIBE source" {}
+        assert_eq!(
+            read_packets(&mut stream, 3).await,
+            [
+                "PUBLISH energy-monitor/test/status",
+                "PUBLISH energy-monitor/test/info",
+                "PUBLISH energy-monitor/test/health",
+            ]
+        );
+        assert_eq!(client.shared.lock().unwrap().reconnects, 1);
+    }
+
+    #[tokio::test]
+    async fn test_activity() {
+        // Nothing listens on the port once the listener is dropped
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let config = MqttConfig::new("test", "127.0.0.1", port);
+        let (client, mut supervisor) = connect(&config, &Daemon::new("test", "0.1.0")).unwrap();
+
+        // Not polled
+        sleep(Duration::from_millis(50)).await;
+        assert!(!client.is_active(Duration::from_millis(10)));
+
+        // Waiting for the next connection attempt
+        tokio::spawn(async move { while supervisor.poll().await.is_some() {} });
+        sleep(Duration::from_millis(200)).await;
+        assert!(client.is_active(Duration::from_millis(10)));
+    }
+
+    /// A CA and a certificate for `localhost` signed by it
+    fn certificates() -> (String, TlsAcceptor) {
+        use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+        use tokio_rustls::rustls::{
+            pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+            ServerConfig,
+        };
+
+        let mut ca = CertificateParams::new(vec![]);
+        ca.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = Certificate::from_params(ca).unwrap();
+        let server =
+            Certificate::from_params(CertificateParams::new(vec!["localhost".to_string()]))
+                .unwrap();
+
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(
+                    server.serialize_der_with_signer(&ca).unwrap(),
+                )],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(server.serialize_private_key_der())),
+            )
+            .unwrap();
+        (
+            ca.serialize_pem().unwrap(),
+            TlsAcceptor::from(Arc::new(config)),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_tls() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (ca, acceptor) = certificates();
+        let (other_ca, _) = certificates();
+
+        let connect = |ca: String| {
+            let mut config = MqttConfig::new("test", "localhost", port);
+            config.transport = TransportKind::Tls;
+            config.tls = Some(config::TlsFiles {
+                ca: ca.into_bytes(),
+                client_auth: None,
+            });
+            let (client, mut supervisor) = connect(&config, &Daemon::new("test", "0.1.0")).unwrap();
+            tokio::spawn(async move { while supervisor.poll().await.is_some() {} });
+            client
+        };
+
+        // The broker certificate is not signed by the CA the client trusts
+        let client = connect(other_ca);
+        let (stream, _) = listener.accept().await.unwrap();
+        assert!(acceptor.accept(stream).await.is_err());
+        assert_eq!(*client.state().borrow(), ConnectionState::Connecting);
+        client.disconnect().await.unwrap();
+
+        let client = connect(ca);
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = acceptor.accept(stream).await.unwrap();
+        connack(&mut stream).await;
+        client
+            .state()
+            .wait_for(|state| *state == ConnectionState::Connected)
+            .await
+            .unwrap();
+        client
+            .publish("test/tls", Delivery::STREAM, Bytes::from("42"))
+            .await
+            .unwrap();
+        assert_eq!(
+            read_packets(&mut stream, 4).await,
+            [
+                "PUBLISH energy-monitor/test/status",
+                "PUBLISH energy-monitor/test/info",
+                "PUBLISH energy-monitor/test/health",
+                "PUBLISH test/tls",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_broker_round_trip() {
+        let broker = Broker::start().await;
+
+        for protocol in [Protocol::V4, Protocol::V5] {
+            let mut config = MqttConfig::new("test", "127.0.0.1", broker.port);
+            config.protocol = protocol;
+            config.session_expiry = Some(Duration::from_secs(3600));
+            let (client, mut supervisor) = connect(&config, &Daemon::new("test", "0.1.0")).unwrap();
+            let (tx, mut rx) = mpsc::channel(10);
+            let events = tokio::spawn(async move {
+                while let Some(event) = supervisor.poll().await {
+                    let _ = tx.send(event).await;
+                }
+            });
+
+            client
+                .subscribe("test/echo", QoS::AtLeastOnce)
+                .await
+                .unwrap();
+            client
+                .state()
+                .wait_for(|state| *state == ConnectionState::Connected)
+                .await
+                .unwrap();
+            let delivery = Delivery {
+                expiry: Some(Duration::from_secs(60)),
+                ..Delivery::STREAM
+            };
+            client
+                .publish("test/echo", delivery, Bytes::from("42"))
+                .await
+                .unwrap();
+
+            let received = timeout(Duration::from_secs(5), async {
+                loop {
+                    if let Some(Event::Incoming(Packet::Publish(publish))) = rx.recv().await {
+                        return publish;
+                    }
+                }
+            })
+            .await
+            .unwrap();
+            assert_eq!(received.topic, "test/echo", "{protocol:?}");
+            assert_eq!(received.payload, Bytes::from("42"));
+
+            client.disconnect().await.unwrap();
+            timeout(Duration::from_secs(5), events)
+                .await
+                .unwrap()
+                .unwrap();
+        }
+
+        // Properties only MQTT 5 carries
+        let received = broker.received.lock().unwrap();
+        assert_eq!(received.session_expiry, Some(3600));
+        let (_, properties) = received
+            .publishes
+            .iter()
+            .find(|(topic, _)| topic == "test/echo")
+            .unwrap();
+        assert_eq!(properties.message_expiry_interval, Some(60));
+        assert_eq!(
+            properties.user_properties,
+            [("schema_version".to_string(), SCHEMA_VERSION.to_string())]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use crate::opendtu::topics::{AcField, DcField, InverterField};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum InverterDecodeError {
+    #[error("Payload is not valid UTF-8")]
+    InvalidUtf8(#[from] std::str::Utf8Error),
+
+    #[error("Payload is not a number: {0}")]
+    InvalidNumber(String),
+}
+
+/// AC side (channel 0) of an inverter
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct AcChannel {
+    /// W
+    pub power: f32,
+    /// V
+    pub voltage: f32,
+    /// A
+    pub current: f32,
+    /// Hz
+    pub frequency: f32,
+    /// °C
+    pub temperature: f32,
+    /// Wh
+    pub yield_day: f32,
+    /// kWh
+    pub yield_total: f32,
+}
+
+/// DC input (string) of an inverter
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct DcString {
+    /// W
+    pub power: f32,
+    /// V
+    pub voltage: f32,
+    /// A
+    pub current: f32,
+    /// Wh
+    pub yield_day: f32,
+    /// kWh
+    pub yield_total: f32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct InverterState {
+    pub serial: String,
+    pub name: Option<String>,
+    pub reachable: bool,
+    pub producing: bool,
+    /// Active power limit in % of the nominal power
+    pub limit_relative: Option<f32>,
+    /// Active power limit in W
+    pub limit_absolute: Option<f32>,
+    pub ac: AcChannel,
+    /// DC strings by channel number (starting at 1)
+    pub dc: BTreeMap<u8, DcString>,
+}
+
+impl InverterState {
+    pub fn new(serial: &str) -> Self {
+        Self {
+            serial: serial.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Updates a single value from the raw payload OpenDTU published for it
+    pub fn update(
+        &mut self,
+        field: InverterField,
+        payload: &[u8],
+    ) -> Result<(), InverterDecodeError> {
+        let payload = std::str::from_utf8(payload)?.trim();
+        let number = || {
+            payload
+                .parse::<f32>()
+                .map_err(|_| InverterDecodeError::InvalidNumber(payload.to_string()))
+        };
+
+        match field {
+            InverterField::Name => self.name = Some(payload.to_string()),
+            InverterField::Reachable => self.reachable = number()? != 0.0,
+            InverterField::Producing => self.producing = number()? != 0.0,
+            InverterField::LimitRelative => self.limit_relative = Some(number()?),
+            InverterField::LimitAbsolute => self.limit_absolute = Some(number()?),
+            InverterField::Ac(value) => {
+                let ac = &mut self.ac;
+                *match value {
+                    AcField::Power => &mut ac.power,
+                    AcField::Voltage => &mut ac.voltage,
+                    AcField::Current => &mut ac.current,
+                    AcField::Frequency => &mut ac.frequency,
+                    AcField::Temperature => &mut ac.temperature,
+                    AcField::YieldDay => &mut ac.yield_day,
+                    AcField::YieldTotal => &mut ac.yield_total,
+                } = number()?;
+            }
+            InverterField::Dc(channel, value) => {
+                let dc = self.dc.entry(channel).or_default();
+                *match value {
+                    DcField::Power => &mut dc.power,
+                    DcField::Voltage => &mut dc.voltage,
+                    DcField::Current => &mut dc.current,
+                    DcField::YieldDay => &mut dc.yield_day,
+                    DcField::YieldTotal => &mut dc.yield_total,
+                } = number()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sum of the power of all DC strings in W
+    pub fn dc_power(&self) -> f32 {
+        self.dc.values().map(|s| s.power).sum()
+    }
+}
+
+/// Sums over all known inverters
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct InverterAggregate {
+    /// W
+    pub power: f32,
+    /// W
+    pub dc_power: f32,
+    /// Wh
+    pub yield_day: f32,
+    /// kWh
+    pub yield_total: f32,
+    pub inverters: usize,
+    pub reachable: usize,
+    pub producing: usize,
+}
+
+/// All inverters seen on the broker, keyed by serial number
+#[derive(PartialEq, Debug, Default, Clone)]
+pub struct Inverters(BTreeMap<String, InverterState>);
+
+impl Inverters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entry(&mut self, serial: &str) -> &mut InverterState {
+        self.0
+            .entry(serial.to_string())
+            .or_insert_with(|| InverterState::new(serial))
+    }
+
+    pub fn get(&self, serial: &str) -> Option<&InverterState> {
+        self.0.get(serial)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &InverterState> {
+        self.0.values()
+    }
+
+    pub fn aggregate(&self) -> InverterAggregate {
+        self.iter()
+            .fold(InverterAggregate::default(), |mut sum, inverter| {
+                sum.power += inverter.ac.power;
+                sum.dc_power += inverter.dc_power();
+                sum.yield_day += inverter.ac.yield_day;
+                sum.yield_total += inverter.ac.yield_total;
+                sum.inverters += 1;
+                sum.reachable += inverter.reachable as usize;
+                sum.producing += inverter.producing as usize;
+                sum
+            })
+    }
+}
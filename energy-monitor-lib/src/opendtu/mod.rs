@@ -1 +1,46 @@
+pub mod dto;
 pub mod topics;
+
+#[cfg(test)]
+mod test {
+    use crate::opendtu::{dto::*, topics::*};
+
+    #[test]
+    fn test_parse_inverter_topic() {
+        let topic = InverterTopic::parse("OpenDTU/114182912345/2/power").unwrap();
+        assert_eq!(topic.serial, "114182912345");
+        assert_eq!(topic.field, InverterField::Dc(2, DcField::Power));
+        assert_eq!(topic.to_string(), "OpenDTU/114182912345/2/power");
+
+        assert_eq!(InverterTopic::parse("OpenDTU/ac/power"), None);
+        assert_eq!(
+            InverterTopic::parse("OpenDTU/114182912345/cmd/restart"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_aggregate_inverters() {
+        let mut inverters = Inverters::new();
+        for (topic, payload) in [
+            ("OpenDTU/1141/0/power", "310.5"),
+            ("OpenDTU/1141/0/yieldday", "1200"),
+            ("OpenDTU/1141/status/reachable", "1"),
+            ("OpenDTU/1141/1/power", "160"),
+            ("OpenDTU/1141/2/power", "165"),
+            ("OpenDTU/1164/0/power", "89.5"),
+            ("OpenDTU/1164/0/yieldday", "300"),
+            ("OpenDTU/1164/status/reachable", "0"),
+            ("OpenDTU/ac/power", "400"),
+        ] {
+            handle_inverter_message(&mut inverters, topic, payload.as_bytes()).unwrap();
+        }
+
+        let sum = inverters.aggregate();
+        assert_eq!(sum.power, 400.0);
+        assert_eq!(sum.dc_power, 325.0);
+        assert_eq!(sum.yield_day, 1500.0);
+        assert_eq!(sum.inverters, 2);
+        assert_eq!(sum.reachable, 1);
+    }
+}
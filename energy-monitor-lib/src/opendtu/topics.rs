@@ -1,5 +1,206 @@
+use crate::opendtu::dto::*;
 use crate::topic::Topic;
-#[rustfmt::skip]
+use std::fmt;
+
+pub const OPEN_DTU_TOPIC_PREFIX: &str = "OpenDTU";
 
+#[rustfmt::skip]
 pub const OPEN_DTU_AC_YIELD_DAY_TOPIC: Topic<f32> = Topic::new("OpenDTU/ac/yieldday");
+pub const OPEN_DTU_AC_YIELD_TOTAL_TOPIC: Topic<f32> = Topic::new("OpenDTU/ac/yieldtotal");
 pub const OPEN_DTU_AC_POWER_TOPIC: Topic<f32> = Topic::new("OpenDTU/ac/power");
+pub const OPEN_DTU_DC_POWER_TOPIC: Topic<f32> = Topic::new("OpenDTU/dc/power");
+
+/// Subscription filters matching the values of all inverters
+pub const OPEN_DTU_INVERTER_FILTER: &str = "OpenDTU/+/#";
+pub const OPEN_DTU_INVERTER_AC_POWER_FILTER: &str = "OpenDTU/+/0/power";
+pub const OPEN_DTU_INVERTER_AC_YIELD_DAY_FILTER: &str = "OpenDTU/+/0/yieldday";
+pub const OPEN_DTU_INVERTER_REACHABLE_FILTER: &str = "OpenDTU/+/status/reachable";
+pub const OPEN_DTU_INVERTER_PRODUCING_FILTER: &str = "OpenDTU/+/status/producing";
+
+/// Value of the AC side (channel 0) of an inverter
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum AcField {
+    Power,
+    Voltage,
+    Current,
+    Frequency,
+    Temperature,
+    YieldDay,
+    YieldTotal,
+}
+
+/// Value of a single DC input (string) of an inverter
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum DcField {
+    Power,
+    Voltage,
+    Current,
+    YieldDay,
+    YieldTotal,
+}
+
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum InverterField {
+    Name,
+    Reachable,
+    Producing,
+    LimitRelative,
+    LimitAbsolute,
+    Ac(AcField),
+    /// DC string, numbered from 1 like OpenDTU does
+    Dc(u8, DcField),
+}
+
+/// A per-inverter topic as published by OpenDTU, e.g. `OpenDTU/114182912345/1/power`
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct InverterTopic {
+    pub serial: String,
+    pub field: InverterField,
+}
+
+impl InverterTopic {
+    pub fn new(serial: &str, field: InverterField) -> Self {
+        Self {
+            serial: serial.to_string(),
+            field,
+        }
+    }
+
+    /// Parses a topic received from the broker. Returns `None` for topics
+    /// which are not per-inverter values (e.g. `OpenDTU/ac/power`) or which
+    /// are not modelled.
+    pub fn parse(topic: &str) -> Option<Self> {
+        let mut parts = topic.split('/');
+        if parts.next()? != OPEN_DTU_TOPIC_PREFIX {
+            return None;
+        }
+        let serial = parts.next()?;
+        if serial.is_empty() || !serial.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let field = match (parts.next()?, parts.next(), parts.next()) {
+            ("name", None, _) => InverterField::Name,
+            ("status", Some("reachable"), None) => InverterField::Reachable,
+            ("status", Some("producing"), None) => InverterField::Producing,
+            ("status", Some("limit_relative"), None) => InverterField::LimitRelative,
+            ("status", Some("limit_absolute"), None) => InverterField::LimitAbsolute,
+            ("0", Some(value), None) => InverterField::Ac(match value {
+                "power" => AcField::Power,
+                "voltage" => AcField::Voltage,
+                "current" => AcField::Current,
+                "frequency" => AcField::Frequency,
+                "temperature" => AcField::Temperature,
+                "yieldday" => AcField::YieldDay,
+                "yieldtotal" => AcField::YieldTotal,
+                _ => return None,
+            }),
+            (channel, Some(value), None) => {
+                let channel: u8 = channel.parse().ok().filter(|c| *c > 0)?;
+                InverterField::Dc(
+                    channel,
+                    match value {
+                        "power" => DcField::Power,
+                        "voltage" => DcField::Voltage,
+                        "current" => DcField::Current,
+                        "yieldday" => DcField::YieldDay,
+                        "yieldtotal" => DcField::YieldTotal,
+                        _ => return None,
+                    },
+                )
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            serial: serial.to_string(),
+            field,
+        })
+    }
+}
+
+impl fmt::Display for InverterTopic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/", OPEN_DTU_TOPIC_PREFIX, self.serial)?;
+        match self.field {
+            InverterField::Name => f.write_str("name"),
+            InverterField::Reachable => f.write_str("status/reachable"),
+            InverterField::Producing => f.write_str("status/producing"),
+            InverterField::LimitRelative => f.write_str("status/limit_relative"),
+            InverterField::LimitAbsolute => f.write_str("status/limit_absolute"),
+            InverterField::Ac(value) => write!(
+                f,
+                "0/{}",
+                match value {
+                    AcField::Power => "power",
+                    AcField::Voltage => "voltage",
+                    AcField::Current => "current",
+                    AcField::Frequency => "frequency",
+                    AcField::Temperature => "temperature",
+                    AcField::YieldDay => "yieldday",
+                    AcField::YieldTotal => "yieldtotal",
+                }
+            ),
+            InverterField::Dc(channel, value) => write!(
+                f,
+                "{}/{}",
+                channel,
+                match value {
+                    DcField::Power => "power",
+                    DcField::Voltage => "voltage",
+                    DcField::Current => "current",
+                    DcField::YieldDay => "yieldday",
+                    DcField::YieldTotal => "yieldtotal",
+                }
+            ),
+        }
+    }
+}
+
+/// Commands OpenDTU accepts below `OpenDTU/<serial>/cmd/`
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum InverterCommand {
+    /// Limit in % of the nominal power, lost on inverter restart
+    LimitNonPersistentRelative,
+    /// Limit in W, lost on inverter restart
+    LimitNonPersistentAbsolute,
+    /// Limit in % of the nominal power, stored in the inverter
+    LimitPersistentRelative,
+    /// Limit in W, stored in the inverter
+    LimitPersistentAbsolute,
+}
+
+impl InverterCommand {
+    pub fn topic(&self, serial: &str) -> String {
+        format!(
+            "{}/{}/cmd/{}",
+            OPEN_DTU_TOPIC_PREFIX,
+            serial,
+            match self {
+                InverterCommand::LimitNonPersistentRelative => "limit_nonpersistent_relative",
+                InverterCommand::LimitNonPersistentAbsolute => "limit_nonpersistent_absolute",
+                InverterCommand::LimitPersistentRelative => "limit_persistent_relative",
+                InverterCommand::LimitPersistentAbsolute => "limit_persistent_absolute",
+            }
+        )
+    }
+}
+
+/// Applies a message received on a per-inverter topic to the matching
+/// inverter in `inverters`. Returns `Ok(false)` if the topic is not a
+/// per-inverter topic.
+pub fn handle_inverter_message(
+    inverters: &mut Inverters,
+    topic: &str,
+    payload: &[u8],
+) -> Result<bool, InverterDecodeError> {
+    match InverterTopic::parse(topic) {
+        Some(topic) => {
+            inverters
+                .entry(&topic.serial)
+                .update(topic.field, payload)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
@@ -0,0 +1,330 @@
+//! Sources of day-ahead prices. Tibber provides end-customer prices with a
+//! level, the day-ahead markets only the spot price. Their prices become
+//! end-customer prices through a spot [`Tariff`](crate::tariff::Tariff) and
+//! get the level from a [`Classification`].
+
+use crate::tibber::dto::{PriceDetails, PriceLevel};
+use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::future::Future;
+use thiserror::Error;
+
+/// Prices are compared to the average of the 3 days before, like Tibber does
+pub const LEVEL_WINDOW: TimeDelta = TimeDelta::days(3);
+
+pub trait PriceProvider {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Prices of the periods starting between `from` and `to`, ordered by
+    /// their start. Spot prices are net prices per kWh without taxes, their
+    /// `energy` is the `total` and the level is [`PriceLevel::None`].
+    fn prices(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> impl Future<Output = Result<Vec<PriceDetails>, Self::Error>> + Send;
+}
+
+/// Limits between the levels: up to `very_cheap` is very cheap, up to
+/// `cheap` cheap, below `expensive` normal, below `very_expensive` expensive
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "UncheckedThresholds")]
+pub struct Thresholds {
+    pub very_cheap: Decimal,
+    pub cheap: Decimal,
+    pub expensive: Decimal,
+    pub very_expensive: Decimal,
+}
+
+#[derive(Debug, Error, PartialEq)]
+#[error("Thresholds must not decrease from very_cheap to very_expensive: {0:?}")]
+pub struct UnorderedThresholds(Thresholds);
+
+/// Thresholds as configured, before checking their order
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UncheckedThresholds {
+    very_cheap: Decimal,
+    cheap: Decimal,
+    expensive: Decimal,
+    very_expensive: Decimal,
+}
+
+impl TryFrom<UncheckedThresholds> for Thresholds {
+    type Error = UnorderedThresholds;
+
+    fn try_from(unchecked: UncheckedThresholds) -> Result<Self, Self::Error> {
+        let thresholds = Thresholds {
+            very_cheap: unchecked.very_cheap,
+            cheap: unchecked.cheap,
+            expensive: unchecked.expensive,
+            very_expensive: unchecked.very_expensive,
+        };
+        if thresholds.very_cheap <= thresholds.cheap
+            && thresholds.cheap <= thresholds.expensive
+            && thresholds.expensive <= thresholds.very_expensive
+        {
+            Ok(thresholds)
+        } else {
+            Err(UnorderedThresholds(thresholds))
+        }
+    }
+}
+
+impl Thresholds {
+    /// Ratios to the trailing average Tibber uses
+    pub const TIBBER: Thresholds = Thresholds {
+        very_cheap: Decimal::from_parts(60, 0, 0, false, 2),
+        cheap: Decimal::from_parts(90, 0, 0, false, 2),
+        expensive: Decimal::from_parts(115, 0, 0, false, 2),
+        very_expensive: Decimal::from_parts(140, 0, 0, false, 2),
+    };
+
+    /// The cheapest and most expensive 10 and 25 percent
+    pub const PERCENTILES: Thresholds = Thresholds {
+        very_cheap: Decimal::from_parts(10, 0, 0, false, 2),
+        cheap: Decimal::from_parts(25, 0, 0, false, 2),
+        expensive: Decimal::from_parts(75, 0, 0, false, 2),
+        very_expensive: Decimal::from_parts(90, 0, 0, false, 2),
+    };
+
+    pub fn level(&self, value: Decimal) -> PriceLevel {
+        if value <= self.very_cheap {
+            PriceLevel::VeryCheap
+        } else if value <= self.cheap {
+            PriceLevel::Cheap
+        } else if value < self.expensive {
+            PriceLevel::Normal
+        } else if value < self.very_expensive {
+            PriceLevel::Expensive
+        } else {
+            PriceLevel::VeryExpensive
+        }
+    }
+}
+
+/// How the level of a price is derived from the prices around it
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "method", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Classification {
+    /// Tibber's definition, the ratio of the price to the average of the
+    /// `window` hours up to it weighted by how long each price applies
+    Average {
+        #[serde(default = "default_window")]
+        window: u32,
+        thresholds: Option<Thresholds>,
+    },
+    /// The percentile rank of the price among the prices of its local day,
+    /// e.g. up to 0.25 for the cheapest quarter
+    Percentile { thresholds: Option<Thresholds> },
+}
+
+fn default_window() -> u32 {
+    LEVEL_WINDOW.num_hours() as u32
+}
+
+impl Default for Classification {
+    fn default() -> Self {
+        Classification::Average {
+            window: default_window(),
+            thresholds: None,
+        }
+    }
+}
+
+impl Classification {
+    /// How far back prices are needed to classify the current one
+    pub fn history(&self) -> TimeDelta {
+        match self {
+            Classification::Average { window, .. } => TimeDelta::hours((*window).into()),
+            Classification::Percentile { .. } => TimeDelta::days(1),
+        }
+    }
+
+    /// Sets the levels of the prices ordered by their start. Prices are
+    /// compared to the history available, so the first ones of an average
+    /// have a shorter window. Prices in a window averaging zero or below
+    /// get no level.
+    pub fn classify(&self, prices: &mut [PriceDetails]) {
+        let levels: Vec<_> = (0..prices.len())
+            .map(|i| self.level(prices, i).unwrap_or(PriceLevel::None))
+            .collect();
+        for (price, level) in prices.iter_mut().zip(levels) {
+            price.level = level;
+        }
+    }
+
+    fn level(&self, prices: &[PriceDetails], i: usize) -> Option<PriceLevel> {
+        let price = &prices[i];
+        match self {
+            Classification::Average { window, thresholds } => {
+                let window = TimeDelta::hours((*window).into());
+                let (sum, seconds) = prices[..=i]
+                    .iter()
+                    .filter(|other| other.starts_at > price.starts_at - window)
+                    .fold((Decimal::ZERO, Decimal::ZERO), |(sum, seconds), other| {
+                        let duration = Decimal::from(other.duration);
+                        (sum + other.total.amount * duration, seconds + duration)
+                    });
+                let ratio = (sum > Decimal::ZERO).then(|| price.total.amount * seconds / sum)?;
+                Some(thresholds.unwrap_or(Thresholds::TIBBER).level(ratio))
+            }
+            Classification::Percentile { thresholds } => {
+                let day = price.starts_at.date_naive();
+                let (mut count, mut below, mut equal) = (0, 0, 0);
+                for other in prices
+                    .iter()
+                    .filter(|other| other.starts_at.date_naive() == day)
+                {
+                    count += 1;
+                    match other.total.amount.cmp(&price.total.amount) {
+                        std::cmp::Ordering::Less => below += 1,
+                        std::cmp::Ordering::Equal => equal += 1,
+                        std::cmp::Ordering::Greater => {}
+                    }
+                }
+                // Equal prices share the rank in the middle of them
+                let rank = (Decimal::from(2 * below + equal)) / Decimal::from(2 * count);
+                Some(thresholds.unwrap_or(Thresholds::PERCENTILES).level(rank))
+            }
+        }
+    }
+}
+
+/// Price applying at `time`
+pub fn current(prices: &[PriceDetails], time: DateTime<FixedOffset>) -> Option<&PriceDetails> {
+    prices
+        .iter()
+        .find(|price| price.starts_at <= time && time < price.ends_at())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::money::{Currency, Money};
+
+    fn spot(starts_at: DateTime<FixedOffset>, amount: i64) -> PriceDetails {
+        let price = Money::new(Decimal::new(amount, 2), Currency::EUR);
+        PriceDetails {
+            total: price,
+            energy: price,
+            tax: Money::zero(Currency::EUR),
+            level: PriceLevel::None,
+            starts_at,
+            duration: 3600,
+        }
+    }
+
+    fn levels(prices: &[PriceDetails]) -> Vec<PriceLevel> {
+        prices.iter().map(|price| price.level.clone()).collect()
+    }
+
+    #[test]
+    fn test_average() {
+        let start: DateTime<FixedOffset> = "2024-06-18T00:00:00+02:00".parse().unwrap();
+        // 3 days at 0.30, then a cheap and an expensive hour
+        let mut prices: Vec<_> = (0..72)
+            .map(|hour| spot(start + TimeDelta::hours(hour), 30))
+            .collect();
+        prices.push(spot(start + TimeDelta::hours(72), 15));
+        prices.push(spot(start + TimeDelta::hours(73), 45));
+        prices[1].level = PriceLevel::Expensive;
+        Classification::default().classify(&mut prices);
+
+        assert_eq!(prices[0].level, PriceLevel::Normal);
+        // Levels of the provider are replaced
+        assert_eq!(prices[1].level, PriceLevel::Normal);
+        assert_eq!(prices[72].level, PriceLevel::VeryCheap);
+        assert_eq!(prices[73].level, PriceLevel::VeryExpensive);
+
+        let time = start + TimeDelta::minutes(72 * 60 + 30);
+        assert_eq!(current(&prices, time), Some(&prices[72]));
+        assert_eq!(current(&prices, start + TimeDelta::hours(74)), None);
+
+        // Within a window of an hour every price is its own average
+        let hourly: Classification =
+            serde_json::from_str(r#"{"method": "average", "window": 1}"#).unwrap();
+        assert_eq!(hourly.history(), TimeDelta::hours(1));
+        hourly.classify(&mut prices);
+        assert_eq!(prices[73].level, PriceLevel::Normal);
+
+        // Stricter thresholds
+        let strict: Classification = serde_json::from_str(
+            r#"{"method": "average", "thresholds": {"very_cheap": "0.4", "cheap": "0.55", "expensive": "1.6", "very_expensive": "2"}}"#,
+        )
+        .unwrap();
+        strict.classify(&mut prices);
+        assert_eq!(prices[72].level, PriceLevel::Cheap);
+        assert_eq!(prices[73].level, PriceLevel::Normal);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let start: DateTime<FixedOffset> = "2024-06-21T00:00:00+02:00".parse().unwrap();
+        // 0.10 to 0.33 over the day, the next day starts at 0.50
+        let mut prices: Vec<_> = (0..25)
+            .map(|hour| spot(start + TimeDelta::hours(hour), 10 + hour + hour / 24 * 16))
+            .collect();
+        let percentile: Classification =
+            serde_json::from_str(r#"{"method": "percentile"}"#).unwrap();
+        percentile.classify(&mut prices);
+
+        let count = |level| {
+            levels(&prices[..24])
+                .iter()
+                .filter(|l| **l == level)
+                .count()
+        };
+        // The cheapest 6 hours are a quarter of the day
+        assert_eq!(count(PriceLevel::VeryCheap) + count(PriceLevel::Cheap), 6);
+        assert_eq!(count(PriceLevel::VeryCheap), 2);
+        assert_eq!(
+            count(PriceLevel::Expensive) + count(PriceLevel::VeryExpensive),
+            6
+        );
+        assert_eq!(count(PriceLevel::VeryExpensive), 2);
+        // Alone on its day
+        assert_eq!(prices[24].level, PriceLevel::Normal);
+
+        // Equal prices share their level
+        let mut flat: Vec<_> = (0..4)
+            .map(|hour| spot(start + TimeDelta::hours(hour), 30))
+            .collect();
+        percentile.classify(&mut flat);
+        assert_eq!(levels(&flat), vec![PriceLevel::Normal; 4]);
+
+        assert!(serde_json::from_str::<Classification>(r#"{"method": "median"}"#).is_err());
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let thresholds = |json| serde_json::from_str::<Thresholds>(json);
+        assert_eq!(
+            thresholds(
+                r#"{"very_cheap": 0.6, "cheap": 0.9, "expensive": 1.15, "very_expensive": 1.4}"#
+            )
+            .unwrap(),
+            Thresholds::TIBBER
+        );
+        // Equal limits leave a level out
+        assert!(thresholds(
+            r#"{"very_cheap": 0.9, "cheap": 0.9, "expensive": 1, "very_expensive": 1}"#
+        )
+        .is_ok());
+
+        let swapped = thresholds(
+            r#"{"very_cheap": 0.9, "cheap": 0.6, "expensive": 1.15, "very_expensive": 1.4}"#,
+        )
+        .unwrap_err();
+        assert!(swapped.to_string().contains("must not decrease"));
+        assert!(thresholds(
+            r#"{"very_cheap": 0.6, "cheap": 0.9, "expensive": 1.4, "very_expensive": 1.15}"#
+        )
+        .is_err());
+        assert!(thresholds(
+            r#"{"very_cheap": 0.6, "cheap": 0.9, "expensive": 1.15, "very_expensive": 1.4, "free": 0}"#
+        )
+        .is_err());
+    }
+}
@@ -4,3 +4,16 @@ use serde::{Deserialize, Serialize};
 pub struct Consumption {
     pub consumption: i32,
 }
+
+/// Values read from the meter through the Pulse Bridge
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MeterReading {
+    /// Server ID of the meter, hex encoded
+    pub meter_id: String,
+    /// Current power in W, negative when exporting
+    pub power: i32,
+    /// Counter of the energy taken from the grid (1.8.0) in Wh
+    pub import_wh: Option<f64>,
+    /// Counter of the energy fed into the grid (2.8.0) in Wh
+    pub export_wh: Option<f64>,
+}
@@ -0,0 +1,216 @@
+//! Pieces shared by the daemons to run as systemd services: waiting for
+//! SIGTERM, the retained online/offline status and the `sd_notify` protocol.
+
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{env, io, os::unix::net::UnixDatagram, time::Duration};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    task::JoinHandle,
+    time::interval,
+};
+use tokio_util::sync::CancellationToken;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Availability {
+    Online,
+    Offline,
+    /// States of newer versions, never published
+    #[serde(other)]
+    Unknown,
+}
+
+impl Availability {
+    /// Plain payload of the status topic
+    pub fn payload(&self) -> &'static str {
+        match self {
+            Availability::Online => "online",
+            Availability::Offline => "offline",
+            Availability::Unknown => "unknown",
+        }
+    }
+
+    pub fn from_payload(payload: &[u8]) -> Self {
+        match payload {
+            b"online" => Availability::Online,
+            b"offline" => Availability::Offline,
+            _ => Availability::Unknown,
+        }
+    }
+}
+
+/// Published retained on the info topic when connecting and disconnecting.
+/// Only the status topic is set as last will, so the state here is stale
+/// after the connection is lost.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DaemonStatus {
+    pub state: Availability,
+    pub version: String,
+    pub started_at: DateTime<Utc>,
+}
+
+/// Retained topic telling whether the daemon is running, `online` or `offline`
+pub fn status_topic(daemon: &str) -> String {
+    format!("energy-monitor/{daemon}/status")
+}
+
+/// Retained topic with the [`DaemonStatus`] of the daemon
+pub fn info_topic(daemon: &str) -> String {
+    format!("energy-monitor/{daemon}/info")
+}
+
+/// Name and version a daemon announces itself with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Daemon {
+    pub name: String,
+    pub version: String,
+    pub started_at: DateTime<Utc>,
+}
+
+impl Daemon {
+    /// Started now, the version is usually `env!("CARGO_PKG_VERSION")`
+    pub fn new(name: &str, version: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            started_at: Utc::now(),
+        }
+    }
+
+    pub fn status_topic(&self) -> String {
+        status_topic(&self.name)
+    }
+
+    pub fn info_topic(&self) -> String {
+        info_topic(&self.name)
+    }
+
+    pub fn status(&self, state: Availability) -> DaemonStatus {
+        DaemonStatus {
+            state,
+            version: self.version.clone(),
+            started_at: self.started_at,
+        }
+    }
+}
+
+/// Completes on SIGTERM or SIGINT
+pub async fn shutdown_signal() -> io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = terminate.recv() => debug!("Received SIGTERM"),
+        _ = interrupt.recv() => debug!("Received SIGINT"),
+    }
+    Ok(())
+}
+
+/// Sends a state like `READY=1` to systemd, does nothing if the daemon
+/// is not started by systemd
+pub fn notify(state: &str) {
+    let Ok(path) = env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send_notification(&path, state) {
+        warn!("Failed to notify systemd ({state}): {:?}", e);
+    }
+}
+
+fn send_notification(path: &str, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    match path.strip_prefix('@') {
+        Some(name) => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            let address = SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &address)?;
+        }
+        None => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Interval systemd expects keep-alive pings in, see `WatchdogSec`
+fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid != std::process::id().to_string() {
+            return None;
+        }
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec))
+}
+
+/// Pings the systemd watchdog at half its interval until `token` is
+/// cancelled. The ping is skipped while `alive` returns false for the
+/// interval, e.g. [`MqttClient::is_active`](crate::mqtt::MqttClient::is_active),
+/// so systemd restarts a daemon which stopped handling its events.
+pub fn spawn_watchdog(
+    token: CancellationToken,
+    alive: impl Fn(Duration) -> bool + Send + 'static,
+) -> Option<JoinHandle<()>> {
+    let watchdog = watchdog_interval()?;
+    let period = watchdog / 2;
+    debug!("Pinging the watchdog every {:?}", period);
+    Some(tokio::spawn(async move {
+        let mut ticks = interval(period);
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {
+                    if alive(watchdog) {
+                        notify("WATCHDOG=1");
+                    } else {
+                        warn!("No events handled for {:?}, skipping the watchdog", watchdog);
+                    }
+                }
+                _ = token.cancelled() => break,
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::topic::{Decode, Encode};
+
+    #[test]
+    fn test_daemon_status() {
+        let daemon = Daemon {
+            started_at: "2024-05-01T12:00:00Z".parse().unwrap(),
+            ..Daemon::new("emtibberd", "0.3.0")
+        };
+        assert_eq!(daemon.status_topic(), "energy-monitor/emtibberd/status");
+        assert_eq!(daemon.info_topic(), "energy-monitor/emtibberd/info");
+
+        let status = daemon.status(Availability::Offline);
+        let payload = DaemonStatus::encode(&status);
+        assert_eq!(
+            payload,
+            r#"{"state":"offline","version":"0.3.0","started_at":"2024-05-01T12:00:00Z"}"#
+        );
+        assert_eq!(DaemonStatus::decode(payload).unwrap(), status);
+
+        assert_eq!(Availability::Offline.payload(), "offline");
+        assert_eq!(Availability::from_payload(b"online"), Availability::Online);
+        assert_eq!(
+            Availability::from_payload(b"degraded"),
+            Availability::Unknown
+        );
+    }
+
+    #[test]
+    fn test_send_notification() {
+        let path = env::temp_dir().join(format!("notify-{}.sock", std::process::id()));
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        send_notification(path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buffer = [0; 16];
+        let len = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"READY=1");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+//! Electricity contracts the price is calculated from: fixed prices,
+//! day/night tariffs, Tibber and spot prices with surcharges. Every tariff
+//! yields [`PriceDetails`], so the price topics look the same whether the
+//! price comes from the Tibber API or not.
+
+use crate::{
+    money::{Currency, Money, MoneyError},
+    price::Classification,
+    tibber::dto::{PriceDetails, PriceLevel},
+};
+use chrono::{DateTime, NaiveDateTime, NaiveTime, TimeDelta, TimeZone};
+use rust_decimal::Decimal;
+use serde::{de, Deserialize, Deserializer};
+
+/// A contract, amounts are per kWh in `currency`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Tariff {
+    #[serde(default = "default_currency")]
+    pub currency: Currency,
+    pub pricing: Pricing,
+    /// Fee per month independent of the consumption
+    pub base_fee: Option<Decimal>,
+    /// Remuneration per kWh fed into the grid
+    pub feed_in: Option<Decimal>,
+    /// Levels of market prices computed locally instead of Tibber's levels.
+    /// Day-ahead prices without a level always use the Tibber definition
+    /// if this is not set.
+    pub levels: Option<Classification>,
+}
+
+fn default_currency() -> Currency {
+    Currency::EUR
+}
+
+impl Default for Tariff {
+    /// Tibber without base fee and feed-in remuneration, with Tibber's levels
+    fn default() -> Self {
+        Self {
+            currency: default_currency(),
+            pricing: Pricing::Tibber,
+            base_fee: None,
+            feed_in: None,
+            levels: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Pricing {
+    /// The same price around the clock
+    Fixed { price: Decimal },
+    /// Prices depending on the local time, e.g. a day and a night tariff.
+    /// Every price applies from its start until the next one starts.
+    TimeOfUse { periods: Vec<TimeOfUse> },
+    /// The prices of the Tibber API as they are
+    Tibber,
+    /// The energy part of the market price plus a surcharge for fees and
+    /// margin, then `vat` percent on top
+    Spot {
+        surcharge: Decimal,
+        #[serde(default)]
+        vat: Decimal,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TimeOfUse {
+    /// Local time, e.g. "06:00"
+    #[serde(deserialize_with = "deserialize_time")]
+    pub start: NaiveTime,
+    pub price: Decimal,
+}
+
+fn deserialize_time<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&text, "%H:%M")
+        .map_err(|_| de::Error::custom(format!("Invalid time {text}, expected HH:MM")))
+}
+
+impl Tariff {
+    /// The price is taken from Tibber or a day-ahead market
+    pub fn needs_market_price(&self) -> bool {
+        matches!(self.pricing, Pricing::Tibber | Pricing::Spot { .. })
+    }
+
+    pub fn base_fee(&self) -> Option<Money> {
+        self.base_fee.map(|fee| Money::new(fee, self.currency))
+    }
+
+    pub fn feed_in(&self) -> Option<Money> {
+        self.feed_in.map(|price| Money::new(price, self.currency))
+    }
+
+    /// Price of the period containing `time` if the tariff defines its own
+    /// prices. They have no breakdown, `energy` is the whole price.
+    pub fn price_at<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> Option<PriceDetails> {
+        let local = time.naive_local();
+        let (starts_at, price, ends_at, level) = match &self.pricing {
+            Pricing::Fixed { price } => {
+                let midnight = local.date().and_time(NaiveTime::MIN);
+                let next_day = midnight + TimeDelta::days(1);
+                (midnight, *price, next_day, PriceLevel::Normal)
+            }
+            Pricing::TimeOfUse { periods } => {
+                let (starts_at, price, ends_at) = window(periods, local)?;
+                // Free periods only, e.g. a promotion, are all normal
+                let level = price
+                    .checked_div(average(periods)?)
+                    .map_or(PriceLevel::Normal, PriceLevel::from_ratio);
+                (starts_at, price, ends_at, level)
+            }
+            Pricing::Tibber | Pricing::Spot { .. } => return None,
+        };
+
+        let tz = time.timezone();
+        let starts_at = localize(&tz, starts_at);
+        let duration = localize(&tz, ends_at) - starts_at;
+        let price = Money::new(price, self.currency);
+        Some(PriceDetails {
+            total: price,
+            energy: price,
+            tax: Money::zero(self.currency),
+            level,
+            starts_at,
+            duration: u32::try_from(duration.num_seconds()).ok()?,
+        })
+    }
+
+    /// Turns the market price into the price of the contract, the level of
+    /// the market price is kept
+    pub fn apply(&self, market: PriceDetails) -> Result<PriceDetails, MoneyError> {
+        match &self.pricing {
+            Pricing::Spot { surcharge, vat } => {
+                let net = market
+                    .energy
+                    .checked_add(Money::new(*surcharge, self.currency))?;
+                let total = net * (Decimal::ONE + vat / Decimal::ONE_HUNDRED);
+                Ok(PriceDetails {
+                    total,
+                    tax: total.checked_sub(market.energy)?,
+                    ..market
+                })
+            }
+            _ => Ok(market),
+        }
+    }
+}
+
+/// Start, price and end of the period containing `local`
+fn window(
+    periods: &[TimeOfUse],
+    local: NaiveDateTime,
+) -> Option<(NaiveDateTime, Decimal, NaiveDateTime)> {
+    let starts = starts(periods, local);
+    let i = starts.iter().rposition(|(start, _)| *start <= local)?;
+    let (start, price) = starts[i];
+    let (end, _) = starts.get(i + 1)?;
+    Some((start, price, *end))
+}
+
+/// Average price of a day weighted by how long each price applies
+fn average(periods: &[TimeOfUse]) -> Option<Decimal> {
+    let day = NaiveDateTime::default();
+    let starts = starts(periods, day);
+    let sum: Decimal = starts
+        .windows(2)
+        .filter(|pair| pair[0].0.date() == day.date())
+        .map(|pair| pair[0].1 * Decimal::from((pair[1].0 - pair[0].0).num_minutes()))
+        .sum();
+    (!periods.is_empty()).then(|| sum / Decimal::from(24 * 60))
+}
+
+/// Starts of the periods on the day before, the day of and the day after `local`
+fn starts(periods: &[TimeOfUse], local: NaiveDateTime) -> Vec<(NaiveDateTime, Decimal)> {
+    let mut starts: Vec<_> = [-1, 0, 1]
+        .into_iter()
+        .map(|days| local.date() + TimeDelta::days(days))
+        .flat_map(|date| {
+            periods
+                .iter()
+                .map(move |period| (date.and_time(period.start), period.price))
+        })
+        .collect();
+    starts.sort_by_key(|(start, _)| *start);
+    starts
+}
+
+/// Local time in the time zone, times skipped when daylight saving time
+/// starts are moved by an hour
+fn localize<Tz: TimeZone>(tz: &Tz, local: NaiveDateTime) -> DateTime<chrono::FixedOffset> {
+    tz.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(local + TimeDelta::hours(1)))
+                .earliest()
+        })
+        .map(|time| time.fixed_offset())
+        .unwrap_or_else(|| local.and_utc().fixed_offset())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono_tz::Europe::Berlin;
+
+    fn tariff(json: &str) -> Tariff {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn eur(amount: &str) -> Money {
+        Money::new(amount.parse().unwrap(), Currency::EUR)
+    }
+
+    #[test]
+    fn test_time_of_use() {
+        let tariff = tariff(
+            r#"{
+                "pricing": {
+                    "type": "time_of_use",
+                    "periods": [
+                        {"start": "22:00", "price": "0.24"},
+                        {"start": "06:00", "price": "0.36"}
+                    ]
+                },
+                "base_fee": "12.50",
+                "feed_in": "0.082"
+            }"#,
+        );
+        assert!(!tariff.needs_market_price());
+        assert_eq!(tariff.base_fee(), Some(eur("12.50")));
+
+        // 16 hours at 0.36 and 8 at 0.24, the average is 0.32
+        let night = Berlin.with_ymd_and_hms(2024, 6, 21, 23, 30, 0).unwrap();
+        let price = tariff.price_at(&night).unwrap();
+        assert_eq!(price.total, eur("0.24"));
+        assert_eq!(price.level, PriceLevel::Cheap);
+        assert_eq!(price.starts_at.to_rfc3339(), "2024-06-21T22:00:00+02:00");
+        assert_eq!(price.duration, 8 * 3600);
+
+        let morning = Berlin.with_ymd_and_hms(2024, 6, 22, 5, 59, 0).unwrap();
+        assert_eq!(
+            tariff.price_at(&morning).unwrap().starts_at,
+            price.starts_at
+        );
+        let day = Berlin.with_ymd_and_hms(2024, 6, 22, 6, 0, 0).unwrap();
+        let price = tariff.price_at(&day).unwrap();
+        assert_eq!(price.total, eur("0.36"));
+        assert_eq!(price.level, PriceLevel::Normal);
+        assert_eq!(price.ends_at().to_rfc3339(), "2024-06-22T22:00:00+02:00");
+
+        // The night daylight saving time ends lasts an hour longer
+        let night = Berlin.with_ymd_and_hms(2024, 10, 26, 23, 0, 0).unwrap();
+        assert_eq!(tariff.price_at(&night).unwrap().duration, 9 * 3600);
+    }
+
+    #[test]
+    fn test_free_time_of_use() {
+        // An average of zero does not divide by zero
+        let tariff = tariff(
+            r#"{
+                "pricing": {
+                    "type": "time_of_use",
+                    "periods": [
+                        {"start": "22:00", "price": "0"},
+                        {"start": "06:00", "price": "0"}
+                    ]
+                }
+            }"#,
+        );
+        let day = Berlin.with_ymd_and_hms(2024, 6, 22, 6, 0, 0).unwrap();
+        let price = tariff.price_at(&day).unwrap();
+        assert_eq!(price.total, eur("0"));
+        assert_eq!(price.level, PriceLevel::Normal);
+    }
+
+    #[test]
+    fn test_fixed_and_spot() {
+        let fixed = tariff(r#"{"pricing": {"type": "fixed", "price": "0.31"}}"#);
+        let noon = Berlin.with_ymd_and_hms(2024, 6, 21, 12, 0, 0).unwrap();
+        let price = fixed.price_at(&noon).unwrap();
+        assert_eq!(price.total, eur("0.31"));
+        assert_eq!(price.level, PriceLevel::Normal);
+        assert_eq!(price.duration, 24 * 3600);
+        assert!(Tariff::default().price_at(&noon).is_none());
+
+        let spot = tariff(r#"{"pricing": {"type": "spot", "surcharge": "0.15", "vat": "19"}}"#);
+        assert!(spot.needs_market_price());
+        let market = PriceDetails {
+            total: eur("0.30"),
+            energy: eur("0.10"),
+            tax: eur("0.20"),
+            level: PriceLevel::Cheap,
+            starts_at: noon.fixed_offset(),
+            duration: 900,
+        };
+        let price = spot.apply(market.clone()).unwrap();
+        // (0.10 + 0.15) * 1.19
+        assert_eq!(price.total, eur("0.2975"));
+        assert_eq!(price.energy, eur("0.10"));
+        assert_eq!(price.tax, eur("0.1975"));
+        assert_eq!(price.level, PriceLevel::Cheap);
+        assert_eq!(Tariff::default().apply(market.clone()).unwrap(), market);
+
+        let nok = Tariff {
+            currency: "NOK".parse().unwrap(),
+            ..spot
+        };
+        assert!(nok.apply(market).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+//! Helpers for the tests of the daemons, enabled with the `testing` feature
+
+use std::sync::{
+    atomic::{AtomicU16, Ordering},
+    Arc, Mutex,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+/// Request received by an [`HttpStub`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    /// e.g. `POST /api/custom?name=power HTTP/1.1`
+    pub line: String,
+    pub body: String,
+}
+
+/// Minimal HTTP server answering every request with the same status and an
+/// empty body, it records the requests
+pub struct HttpStub {
+    pub url: String,
+    status: Arc<AtomicU16>,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl HttpStub {
+    pub async fn start(status: u16) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let stub = Self {
+            url,
+            status: Arc::new(AtomicU16::new(status)),
+            requests: Arc::new(Mutex::new(vec![])),
+        };
+
+        let status = stub.status.clone();
+        let requests = stub.requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).await.unwrap();
+                    if let Some(len) = header.to_lowercase().strip_prefix("content-length:") {
+                        content_length = len.trim().parse().unwrap();
+                    }
+                    if header == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).await.unwrap();
+                requests.lock().unwrap().push(Request {
+                    line: line.trim_end().to_string(),
+                    body: String::from_utf8(body).unwrap(),
+                });
+
+                let status = status.load(Ordering::SeqCst);
+                reader
+                    .get_mut()
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 {status} Stub\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                        )
+                        .as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+            }
+        });
+        stub
+    }
+
+    /// Status of the following responses
+    pub fn set_status(&self, status: u16) {
+        self.status.store(status, Ordering::SeqCst);
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
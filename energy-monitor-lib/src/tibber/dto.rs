@@ -1,12 +1,113 @@
+use crate::{
+    money::{Currency, Money, MoneyError},
+    price::Thresholds,
+};
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
+/// The current price and its level, the breakdown is published in
+/// [`PriceDetails`]
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(try_from = "AnySchemaPriceInformation")]
 pub struct PriceInformation {
-    pub total: f32,
+    /// Price per kWh including taxes
+    pub total: Money,
     pub level: PriceLevel,
+    /// Start of the price period, missing in messages of older versions
+    #[serde(default)]
+    pub starts_at: Option<DateTime<FixedOffset>>,
+    /// Seconds the price applies, 3600 for hourly and 900 for quarter-hourly prices
+    #[serde(default)]
+    pub duration: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+/// [`PriceInformation`] as published by all schema versions. Before schema 3
+/// the total was a plain number with the currency next to it, EUR if missing.
+#[derive(Deserialize)]
+struct AnySchemaPriceInformation {
+    total: Total,
+    #[serde(default)]
+    currency: Option<Currency>,
+    level: PriceLevel,
+    #[serde(default)]
+    starts_at: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    duration: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Total {
+    Money(Money),
+    Number(f64),
+}
+
+impl TryFrom<AnySchemaPriceInformation> for PriceInformation {
+    type Error = MoneyError;
+
+    fn try_from(price: AnySchemaPriceInformation) -> Result<Self, Self::Error> {
+        let total = match price.total {
+            Total::Money(total) => total,
+            Total::Number(amount) => {
+                Money::from_f64(amount, price.currency.unwrap_or(Currency::EUR))?
+            }
+        };
+        Ok(Self {
+            total,
+            level: price.level,
+            starts_at: price.starts_at,
+            duration: price.duration,
+        })
+    }
+}
+
+impl PriceInformation {
+    /// Start of the next price period
+    pub fn ends_at(&self) -> Option<DateTime<FixedOffset>> {
+        let duration = TimeDelta::seconds(self.duration?.into());
+        Some(self.starts_at? + duration)
+    }
+}
+
+/// Price of the current period split into its components, all per kWh
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PriceDetails {
+    /// Energy plus tax, the price paid per kWh
+    pub total: Money,
+    /// Spot price
+    pub energy: Money,
+    /// Taxes, grid fees and VAT
+    pub tax: Money,
+    pub level: PriceLevel,
+    pub starts_at: DateTime<FixedOffset>,
+    /// Seconds the price applies
+    pub duration: u32,
+}
+
+impl PriceDetails {
+    /// Start of the next price period
+    pub fn ends_at(&self) -> DateTime<FixedOffset> {
+        self.starts_at + TimeDelta::seconds(self.duration.into())
+    }
+
+    /// The short form published on `Tibber/price_information`
+    pub fn information(&self) -> PriceInformation {
+        PriceInformation {
+            total: self.total,
+            level: self.level.clone(),
+            starts_at: Some(self.starts_at),
+            duration: Some(self.duration),
+        }
+    }
+}
+
+/// Serialized as the name of the variant, e.g. `"VeryCheap"`. Known names
+/// are decoded in any case and with underscores, e.g. `"VERY_CHEAP"`. Levels
+/// added by Tibber or by newer versions are kept as [`PriceLevel::Other`], so
+/// decoding never fails on them and they are published as received.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(from = "String")]
 pub enum PriceLevel {
     Cheap,
     Expensive,
@@ -14,6 +115,53 @@ pub enum PriceLevel {
     VeryCheap,
     VeryExpensive,
     None,
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl PriceLevel {
+    /// The name as serialized
+    pub fn name(&self) -> &str {
+        match self {
+            PriceLevel::Cheap => "Cheap",
+            PriceLevel::Expensive => "Expensive",
+            PriceLevel::Normal => "Normal",
+            PriceLevel::VeryCheap => "VeryCheap",
+            PriceLevel::VeryExpensive => "VeryExpensive",
+            PriceLevel::None => "None",
+            PriceLevel::Other(name) => name,
+        }
+    }
+
+    /// Level of a price relative to the average price with the thresholds
+    /// Tibber uses, e.g. 0.6 and below is very cheap
+    pub fn from_ratio(ratio: Decimal) -> Self {
+        Thresholds::TIBBER.level(ratio)
+    }
+}
+
+impl From<&str> for PriceLevel {
+    fn from(name: &str) -> Self {
+        let known = [
+            PriceLevel::Cheap,
+            PriceLevel::Expensive,
+            PriceLevel::Normal,
+            PriceLevel::VeryCheap,
+            PriceLevel::VeryExpensive,
+            PriceLevel::None,
+        ];
+        let normalized = name.replace('_', "");
+        known
+            .into_iter()
+            .find(|level| level.name().eq_ignore_ascii_case(&normalized))
+            .unwrap_or_else(|| PriceLevel::Other(name.to_string()))
+    }
+}
+
+impl From<String> for PriceLevel {
+    fn from(name: String) -> Self {
+        name.as_str().into()
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -1,7 +1,12 @@
 use crate::tibber::dto::*;
-use crate::topic::Topic;
+use crate::topic::{Delivery, Topic};
 #[rustfmt::skip]
 pub const TIBBER_PRICE_INFORMATION_TOPIC: Topic<PriceInformation> =
-    Topic::new("Tibber/price_information");
+    Topic::with_delivery("Tibber/price_information", Delivery::STATE);
+
+/// Current price with energy, tax and currency
+#[rustfmt::skip]
+pub const TIBBER_PRICE_DETAILS_TOPIC: Topic<PriceDetails> =
+    Topic::with_delivery("Tibber/price_details", Delivery::STATE);
 
 pub const TIBBER_CONSUMPTION_TOPIC: Topic<Consumption> = Topic::new("Tibber/consumption");
@@ -1,6 +1,11 @@
+use crate::mqtt::{MqttClient, MqttError};
 use bytes::Bytes;
+use rumqttc::QoS;
 use serde::{Deserialize, Serialize};
-use std::{fmt, marker::PhantomData};
+use std::{fmt, marker::PhantomData, time::Duration};
+
+/// Version of the JSON payloads, sent as user property with MQTT 5
+pub const SCHEMA_VERSION: &str = "3";
 
 pub trait Encode {
     fn encode(message: &Self) -> Bytes;
@@ -13,15 +18,47 @@ pub trait Decode {
     fn decode<T: AsRef<[u8]>>(payload: T) -> Result<Self::Output, Self::DecodeError>;
 }
 
-#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
-pub struct Topic<M>(&'static str, PhantomData<M>);
+/// How messages of a topic are published
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Delivery {
+    pub qos: QoS,
+    /// The broker keeps the last message and hands it to new subscribers
+    pub retain: bool,
+    /// Messages not delivered in time are dropped, by the broker with MQTT 5
+    /// and from the offline queue
+    pub expiry: Option<Duration>,
+}
+
+impl Delivery {
+    /// Values published frequently, a lost message is replaced by the next one
+    pub const STREAM: Delivery = Delivery {
+        qos: QoS::AtMostOnce,
+        retain: false,
+        expiry: None,
+    };
+
+    /// Values changing rarely, subscribers get the current one right away
+    pub const STATE: Delivery = Delivery {
+        qos: QoS::AtLeastOnce,
+        retain: true,
+        expiry: None,
+    };
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Topic<M>(&'static str, Delivery, PhantomData<M>);
 
 impl<M> Topic<M>
 where
     M: Encode + Decode,
 {
+    /// Topic published as [`Delivery::STREAM`]
     pub const fn new(topic: &'static str) -> Self {
-        Self(topic, PhantomData {})
+        Self(topic, Delivery::STREAM, PhantomData {})
+    }
+
+    pub const fn with_delivery(topic: &'static str, delivery: Delivery) -> Self {
+        Self(topic, delivery, PhantomData {})
     }
 
     pub fn encode(&self, message: &M) -> Bytes {
@@ -38,6 +75,15 @@ where
     pub const fn name(&self) -> &'static str {
         self.0
     }
+
+    pub const fn delivery(&self) -> Delivery {
+        self.1
+    }
+
+    /// Publishes the message with the QoS and retain flag of the topic
+    pub async fn publish(&self, client: &MqttClient, message: &M) -> Result<(), MqttError> {
+        client.publish(self.0, self.1, self.encode(message)).await
+    }
 }
 
 impl<T> Encode for T
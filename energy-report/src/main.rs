@@ -0,0 +1,56 @@
+use anyhow::{anyhow, Context, Result};
+use record::read_records;
+use render::{render, Format};
+use report::{summarize, Period};
+use std::{fs::File, io::Write};
+
+mod record;
+mod render;
+mod report;
+
+const USAGE: &str =
+    "Usage: emreport [--period day|month] [--format csv|markdown|html] [--output FILE] RECORDS.csv";
+
+fn main() -> Result<()> {
+    let mut period = Period::Month;
+    let mut format = Format::Markdown;
+    let mut output = None;
+    let mut input = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("{arg} needs a value\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--period" => {
+                period = match value()?.as_str() {
+                    "day" => Period::Day,
+                    "month" => Period::Month,
+                    other => return Err(anyhow!("Unknown period {other}\n{USAGE}")),
+                }
+            }
+            "--format" => format = value()?.parse()?,
+            "--output" => output = Some(value()?),
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => input = Some(arg),
+        }
+    }
+
+    let input = input.ok_or_else(|| anyhow!(USAGE))?;
+    let records =
+        read_records(File::open(&input).with_context(|| format!("Failed to open {input}"))?)?;
+    let report = render(&summarize(&records, period)?, format)?;
+
+    match output {
+        Some(path) => File::create(&path)
+            .with_context(|| format!("Failed to create {path}"))?
+            .write_all(report.as_bytes())?,
+        None => print!("{report}"),
+    }
+    Ok(())
+}
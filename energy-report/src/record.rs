@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use energy_monitor_lib::money::{Currency, Money};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::io::Read;
+use tibber_loader::gql::queries::{PriceInfo, PriceLevel, Resolution};
+
+/// Energy flows of one price period (an hour or 15 minutes) together with the
+/// Tibber price which applied during it
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub price: PriceInfo,
+    /// Energy taken from the grid in Wh
+    pub grid_wh: f64,
+    /// Energy produced by the PV system in Wh
+    pub pv_wh: f64,
+    /// Energy fed into the grid in Wh
+    pub export_wh: f64,
+}
+
+impl Record {
+    pub fn grid_kwh(&self) -> f64 {
+        self.grid_wh / 1000.0
+    }
+
+    pub fn pv_kwh(&self) -> f64 {
+        self.pv_wh / 1000.0
+    }
+
+    /// PV energy used in the house instead of being exported
+    pub fn self_consumed_kwh(&self) -> f64 {
+        (self.pv_wh - self.export_wh).max(0.0) / 1000.0
+    }
+}
+
+/// One line of the records `emtibberd` writes to `EMTIBBERD_RECORDS_PATH`, e.g.
+/// `2024-06-01T13:00:00+02:00,120.0,850.0,310.0,0.0712,0.1688,0.2400,EUR,Cheap`
+#[derive(Debug, Deserialize)]
+struct Row {
+    starts_at: String,
+    grid_wh: f64,
+    pv_wh: f64,
+    export_wh: f64,
+    energy: Decimal,
+    tax: Decimal,
+    total: Decimal,
+    currency: Currency,
+    level: String,
+}
+
+pub fn read_records<R: Read>(reader: R) -> Result<Vec<Record>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+
+    let mut records: Vec<Record> = reader
+        .deserialize::<Row>()
+        .enumerate()
+        .map(|(line, row)| {
+            let row = row.with_context(|| format!("Invalid record in line {}", line + 2))?;
+            Ok(Record {
+                price: PriceInfo {
+                    total: Money::new(row.total, row.currency),
+                    energy: Money::new(row.energy, row.currency),
+                    tax: Money::new(row.tax, row.currency),
+                    starts_at: chrono::DateTime::parse_from_rfc3339(&row.starts_at)
+                        .with_context(|| format!("Invalid start time {}", row.starts_at))?,
+                    duration: Resolution::Hourly.duration(),
+                    level: PriceLevel::from(row.level.as_str()),
+                },
+                grid_wh: row.grid_wh,
+                pv_wh: row.pv_wh,
+                export_wh: row.export_wh,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    // Quarter-hourly records are recognized by the gaps between them
+    let mut prices: Vec<PriceInfo> = records.iter().map(|record| record.price.clone()).collect();
+    PriceInfo::set_durations(&mut prices);
+    for (record, price) in records.iter_mut().zip(prices) {
+        record.price.duration = price.duration;
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_records() {
+        // As written by emtibberd, quarter-hourly from 14:00
+        let records = "\
+starts_at,grid_wh,pv_wh,export_wh,energy,tax,total,currency,level
+2024-06-01T13:00:00+02:00,120.0,850.0,310.0,0.0712,0.1688,0.2400,EUR,Cheap
+2024-06-01T14:00:00+02:00,30.5,200.0,80.0,0.1012,0.1688,0.2700,EUR,VeryExpensive
+2024-06-01T14:15:00+02:00,31.0,210.0,85.0,0.1012,0.1688,0.2700,EUR,VeryExpensive
+";
+        let records = read_records(records.as_bytes()).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[0].price.total,
+            Money::new("0.24".parse().unwrap(), Currency::EUR)
+        );
+        assert!(matches!(records[0].price.level, PriceLevel::Cheap));
+        assert!(matches!(records[1].price.level, PriceLevel::VeryExpensive));
+        assert_eq!(records[0].price.duration, Resolution::Hourly.duration());
+        assert_eq!(
+            records[1].price.duration,
+            Resolution::QuarterHourly.duration()
+        );
+        assert!((records[0].self_consumed_kwh() - 0.54).abs() < 1e-9);
+    }
+}
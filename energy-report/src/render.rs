@@ -0,0 +1,103 @@
+use crate::report::Summary;
+use anyhow::{anyhow, Result};
+use energy_monitor_lib::money::Money;
+use std::{fmt::Write, str::FromStr};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Markdown,
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "md" | "markdown" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            _ => Err(anyhow!(
+                "Unknown format {s}, expected csv, markdown or html"
+            )),
+        }
+    }
+}
+
+const COLUMNS: [&str; 11] = [
+    "Period",
+    "Grid kWh",
+    "Energy cost",
+    "Taxes and fees",
+    "Cost",
+    "PV kWh",
+    "Self consumed kWh",
+    "Savings",
+    "Avg. price paid",
+    "Avg. market price",
+    "Currency",
+];
+
+fn cells(summary: &Summary) -> [String; 11] {
+    // The currency has its own column
+    let amount = |m: Money, dp: usize| format!("{:.*}", dp, m.round_dp(dp as u32).amount);
+    let price = |p: Option<Money>| p.map(|p| amount(p, 4)).unwrap_or_default();
+    [
+        summary.period.clone(),
+        format!("{:.3}", summary.grid_kwh),
+        amount(summary.energy_cost, 2),
+        amount(summary.tax_cost, 2),
+        amount(summary.cost, 2),
+        format!("{:.3}", summary.pv_kwh),
+        format!("{:.3}", summary.self_consumed_kwh),
+        amount(summary.savings, 2),
+        price(summary.average_price_paid()),
+        price(summary.average_market_price()),
+        summary.currency.to_string(),
+    ]
+}
+
+pub fn render(summaries: &[Summary], format: Format) -> Result<String> {
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.write_record(COLUMNS)?;
+            for summary in summaries {
+                writer.write_record(cells(summary))?;
+            }
+            Ok(String::from_utf8(writer.into_inner()?)?)
+        }
+        Format::Markdown => {
+            let mut out = String::new();
+            writeln!(out, "| {} |", COLUMNS.join(" | "))?;
+            writeln!(out, "|{}", "---|".repeat(COLUMNS.len()))?;
+            for summary in summaries {
+                writeln!(out, "| {} |", cells(summary).join(" | "))?;
+            }
+            Ok(out)
+        }
+        Format::Html => {
+            let mut out = String::from("<table>\n<tr>");
+            for column in COLUMNS {
+                write!(out, "<th>{column}</th>")?;
+            }
+            out.push_str("</tr>\n");
+            for summary in summaries {
+                out.push_str("<tr>");
+                for cell in cells(summary) {
+                    write!(out, "<td>{}</td>", escape_html(&cell))?;
+                }
+                out.push_str("</tr>\n");
+            }
+            out.push_str("</table>\n");
+            Ok(out)
+        }
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,202 @@
+use crate::record::Record;
+use anyhow::{Context, Result};
+use energy_monitor_lib::money::{decimal, Currency, Money, MoneyError};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Period {
+    Day,
+    Month,
+}
+
+impl Period {
+    fn key(&self, record: &Record) -> String {
+        // Use the offset of the price, so days start at local midnight
+        match self {
+            Period::Day => record.price.starts_at.format("%Y-%m-%d").to_string(),
+            Period::Month => record.price.starts_at.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+/// Totals for a day or a month
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub period: String,
+    pub currency: Currency,
+    /// Number of price periods covered
+    pub intervals: usize,
+    pub grid_kwh: f64,
+    /// Cost of the grid energy at the spot price part of the tariff
+    pub energy_cost: Money,
+    /// Cost of the grid energy at the tax and fee part of the tariff
+    pub tax_cost: Money,
+    /// Cost of the grid energy at the total price
+    pub cost: Money,
+    pub pv_kwh: f64,
+    pub self_consumed_kwh: f64,
+    /// What the self consumed PV energy would have cost from the grid
+    pub savings: Money,
+    /// Spot prices multiplied by the seconds they applied
+    market_price_seconds: Money,
+    seconds: i64,
+}
+
+impl Summary {
+    fn new(period: String, currency: Currency) -> Self {
+        Self {
+            period,
+            currency,
+            intervals: 0,
+            grid_kwh: 0.0,
+            energy_cost: Money::zero(currency),
+            tax_cost: Money::zero(currency),
+            cost: Money::zero(currency),
+            pv_kwh: 0.0,
+            self_consumed_kwh: 0.0,
+            savings: Money::zero(currency),
+            market_price_seconds: Money::zero(currency),
+            seconds: 0,
+        }
+    }
+
+    /// Fails without changing the totals if the price is in another currency
+    fn add(&mut self, record: &Record) -> Result<(), MoneyError> {
+        let price = &record.price;
+        // Energy is measured, only the money needs to be exact
+        let grid_kwh = decimal(record.grid_kwh()).unwrap_or_default();
+        let self_consumed_kwh = decimal(record.self_consumed_kwh()).unwrap_or_default();
+
+        let energy_cost = self.energy_cost.checked_add(price.energy * grid_kwh)?;
+        let tax_cost = self.tax_cost.checked_add(price.tax * grid_kwh)?;
+        let cost = self.cost.checked_add(price.total * grid_kwh)?;
+        let savings = self.savings.checked_add(price.total * self_consumed_kwh)?;
+        let seconds = price.duration.num_seconds();
+        let market_price_seconds = self
+            .market_price_seconds
+            .checked_add(price.energy * Decimal::from(seconds))?;
+
+        self.intervals += 1;
+        self.grid_kwh += record.grid_kwh();
+        self.energy_cost = energy_cost;
+        self.tax_cost = tax_cost;
+        self.cost = cost;
+        self.pv_kwh += record.pv_kwh();
+        self.self_consumed_kwh += record.self_consumed_kwh();
+        self.savings = savings;
+        self.market_price_seconds = market_price_seconds;
+        self.seconds += seconds;
+        Ok(())
+    }
+
+    /// Average price per kWh we paid for the grid energy
+    pub fn average_price_paid(&self) -> Option<Money> {
+        let grid_kwh = decimal(self.grid_kwh).ok()?;
+        (!grid_kwh.is_zero()).then(|| self.cost / grid_kwh)
+    }
+
+    /// Average spot price over the period weighted by how long each price
+    /// applied, independent of when we consumed
+    pub fn average_market_price(&self) -> Option<Money> {
+        (self.seconds > 0).then(|| self.market_price_seconds / Decimal::from(self.seconds))
+    }
+}
+
+/// Sums up the records per day or month in chronological order. The
+/// currency of a period is the one of its first record, a period mixing
+/// currencies can not be summed up.
+pub fn summarize(records: &[Record], period: Period) -> Result<Vec<Summary>> {
+    let mut summaries: BTreeMap<String, Summary> = BTreeMap::new();
+
+    for record in records {
+        let key = period.key(record);
+        summaries
+            .entry(key.clone())
+            .or_insert_with(|| Summary::new(key, record.price.total.currency))
+            .add(record)
+            .with_context(|| format!("Invalid record starting at {}", record.price.starts_at))?;
+    }
+
+    Ok(summaries.into_values().collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::record::read_records;
+
+    const RECORDS: &str = "\
+starts_at,grid_wh,pv_wh,export_wh,energy,tax,total,currency,level
+2024-05-31T23:00:00+02:00,400,0,0,0.10,0.20,0.30,EUR,NORMAL
+2024-06-01T00:00:00+02:00,500,0,0,0.08,0.20,0.28,EUR,CHEAP
+2024-06-01T13:00:00+02:00,100,1000,400,0.02,0.18,0.20,EUR,VERY_CHEAP
+";
+
+    #[test]
+    fn test_summarize() {
+        let records = read_records(RECORDS.as_bytes()).unwrap();
+
+        let days = summarize(&records, Period::Day).unwrap();
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].period, "2024-05-31");
+
+        let day = &days[1];
+        assert_eq!(day.period, "2024-06-01");
+        assert!((day.grid_kwh - 0.6).abs() < 1e-9);
+        let eur = |amount: &str| Money::new(amount.parse().unwrap(), Currency::EUR);
+        // 0.5 kWh at 0.28 and 0.1 kWh at 0.20, exact to the cent
+        assert_eq!(day.cost, eur("0.16"));
+        assert_eq!(day.energy_cost.checked_add(day.tax_cost), Ok(day.cost));
+        assert!((day.self_consumed_kwh - 0.6).abs() < 1e-9);
+        assert_eq!(day.savings, eur("0.12"));
+        assert_eq!(day.average_market_price(), Some(eur("0.05")));
+        assert_eq!(day.average_price_paid().unwrap().round_dp(4), eur("0.2667"));
+
+        let months = summarize(&records, Period::Month).unwrap();
+        assert_eq!(months.len(), 2);
+        assert_eq!(months[1].period, "2024-06");
+    }
+
+    #[test]
+    fn test_average_market_price() {
+        // An hour, four quarter hours and another hour
+        let records = read_records(
+            "\
+starts_at,grid_wh,pv_wh,export_wh,energy,tax,total,currency,level
+2024-06-01T12:00:00+02:00,100,0,0,0.10,0.20,0.30,EUR,NORMAL
+2024-06-01T13:00:00+02:00,25,0,0,0.02,0.20,0.22,EUR,CHEAP
+2024-06-01T13:15:00+02:00,25,0,0,0.02,0.20,0.22,EUR,CHEAP
+2024-06-01T13:30:00+02:00,25,0,0,0.02,0.20,0.22,EUR,CHEAP
+2024-06-01T13:45:00+02:00,25,0,0,0.02,0.20,0.22,EUR,CHEAP
+2024-06-01T14:00:00+02:00,100,0,0,0.30,0.20,0.50,EUR,EXPENSIVE
+"
+            .as_bytes(),
+        )
+        .unwrap();
+        let days = summarize(&records, Period::Day).unwrap();
+        let day = &days[0];
+        assert_eq!(day.intervals, 6);
+        // Each hour counts once, whatever its resolution, and only the spot
+        // price is averaged
+        assert_eq!(
+            day.average_market_price().unwrap().round_dp(4),
+            Money::new("0.14".parse().unwrap(), Currency::EUR)
+        );
+    }
+
+    #[test]
+    fn test_mixed_currencies() {
+        let records = read_records(
+            format!("{RECORDS}2024-06-01T14:00:00+02:00,100,0,0,1.0,2.0,3.0,SEK,NORMAL\n")
+                .as_bytes(),
+        )
+        .unwrap();
+        let error = summarize(&records, Period::Day).unwrap_err();
+        assert!(error.to_string().contains("2024-06-01 14:00:00 +02:00"));
+        assert!(matches!(
+            error.downcast_ref::<MoneyError>(),
+            Some(MoneyError::CurrencyMismatch(..))
+        ));
+    }
+}
@@ -0,0 +1,201 @@
+use std::time::{Duration, Instant};
+
+pub const FULL_LIMIT: f32 = 100.0;
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Nominal AC power of the controlled inverter in W
+    pub inverter_power: f32,
+    /// Maximum power in W we are allowed to feed into the grid
+    pub max_export: f32,
+    /// Changes of the limit smaller than this (in W) are not sent to the inverter
+    pub deadband: f32,
+    /// The inverter does not go below this limit (in %)
+    pub min_limit: f32,
+    /// Minimum time between two limit commands
+    pub command_interval: Duration,
+    /// Grid readings older than this trigger the failsafe
+    pub stale_timeout: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            inverter_power: 800.0,
+            max_export: 0.0,
+            deadband: 20.0,
+            min_limit: 2.0,
+            command_interval: Duration::from_secs(10),
+            stale_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Closed loop controller which keeps the grid export below `Settings::max_export`
+/// by adjusting the relative power limit of a single inverter.
+///
+/// Grid power is positive when importing and negative when exporting.
+#[derive(Debug)]
+pub struct Controller {
+    settings: Settings,
+    /// Limit in % which was last commanded
+    limit: f32,
+    last_command: Option<Instant>,
+    last_grid_power: Option<(Instant, f32)>,
+    production: Option<f32>,
+}
+
+impl Controller {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            settings,
+            limit: FULL_LIMIT,
+            last_command: None,
+            last_grid_power: None,
+            production: None,
+        }
+    }
+
+    pub fn set_grid_power(&mut self, now: Instant, watts: f32) {
+        self.last_grid_power = Some((now, watts));
+    }
+
+    pub fn set_production(&mut self, watts: f32) {
+        self.production = Some(watts);
+    }
+
+    /// The limit the inverter reports. Used to pick up the state after a restart.
+    pub fn set_reported_limit(&mut self, percent: f32) {
+        if self.last_command.is_none() {
+            self.limit = percent;
+        }
+    }
+
+    /// Returns the new limit in % if a command needs to be sent
+    pub fn poll(&mut self, now: Instant) -> Option<f32> {
+        let grid_power = match self.last_grid_power {
+            Some((at, watts)) if now.duration_since(at) <= self.settings.stale_timeout => watts,
+            _ => return self.failsafe(now),
+        };
+
+        if self
+            .last_command
+            .is_some_and(|at| now.duration_since(at) < self.settings.command_interval)
+        {
+            return None;
+        }
+
+        let current_limit_watts = self.limit / FULL_LIMIT * self.settings.inverter_power;
+        let production = self.production.unwrap_or(current_limit_watts);
+
+        // Whatever the house consumes plus the allowed export can be produced
+        let target_watts = grid_power + production + self.settings.max_export;
+        let target = (target_watts / self.settings.inverter_power * FULL_LIMIT)
+            .clamp(self.settings.min_limit, FULL_LIMIT)
+            .round();
+
+        let change_watts = (target - self.limit).abs() / FULL_LIMIT * self.settings.inverter_power;
+        if change_watts < self.settings.deadband && target != FULL_LIMIT {
+            return None;
+        }
+        if target == self.limit {
+            return None;
+        }
+
+        self.command(now, target)
+    }
+
+    pub fn command_interval(&self) -> Duration {
+        self.settings.command_interval
+    }
+
+    /// Returns the full limit if the inverter is limited, for when the
+    /// controller stops
+    pub fn release(&mut self) -> Option<f32> {
+        (self.limit != FULL_LIMIT).then(|| self.command(Instant::now(), FULL_LIMIT))?
+    }
+
+    fn failsafe(&mut self, now: Instant) -> Option<f32> {
+        if self.limit == FULL_LIMIT {
+            None
+        } else {
+            log::warn!("Grid power data is stale, restoring inverter limit to 100%");
+            self.command(now, FULL_LIMIT)
+        }
+    }
+
+    fn command(&mut self, now: Instant, limit: f32) -> Option<f32> {
+        self.limit = limit;
+        self.last_command = Some(now);
+        Some(limit)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::simulation::SimulatedHouse;
+
+    fn settings() -> Settings {
+        Settings {
+            inverter_power: 800.0,
+            max_export: 100.0,
+            deadband: 20.0,
+            min_limit: 2.0,
+            command_interval: Duration::from_secs(10),
+            stale_timeout: Duration::from_secs(30),
+        }
+    }
+
+    #[test]
+    fn test_limits_export() {
+        let mut house = SimulatedHouse::new(800.0, 200.0, 750.0);
+        let mut controller = Controller::new(settings());
+        let start = Instant::now();
+
+        for step in 0..30 {
+            let now = start + Duration::from_secs(step * 5);
+            controller.set_grid_power(now, house.grid_power());
+            controller.set_production(house.production());
+            if let Some(limit) = controller.poll(now) {
+                house.set_limit(limit);
+            }
+        }
+
+        assert!(-house.grid_power() <= 100.0 + 20.0);
+        assert!(-house.grid_power() >= 100.0 - 20.0);
+    }
+
+    #[test]
+    fn test_rate_limit_and_failsafe() {
+        let mut controller = Controller::new(settings());
+        let start = Instant::now();
+
+        controller.set_grid_power(start, -500.0);
+        controller.set_production(600.0);
+        assert_eq!(controller.poll(start), Some(25.0));
+
+        controller.set_grid_power(start + Duration::from_secs(5), -300.0);
+        assert_eq!(controller.poll(start + Duration::from_secs(5)), None);
+
+        // No data for longer than the stale timeout
+        assert_eq!(
+            controller.poll(start + Duration::from_secs(60)),
+            Some(FULL_LIMIT)
+        );
+        assert_eq!(controller.poll(start + Duration::from_secs(70)), None);
+    }
+
+    #[test]
+    fn test_release() {
+        let mut controller = Controller::new(settings());
+        assert_eq!(controller.release(), None);
+
+        let start = Instant::now();
+        controller.set_grid_power(start, -500.0);
+        controller.set_production(600.0);
+        assert_eq!(controller.poll(start), Some(25.0));
+        assert_eq!(controller.release(), Some(FULL_LIMIT));
+        assert_eq!(controller.release(), None);
+    }
+}
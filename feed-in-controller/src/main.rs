@@ -0,0 +1,279 @@
+use anyhow::{anyhow, Context, Result};
+use controller::{Controller, Settings};
+use energy_monitor_lib::{
+    mqtt::{self, MqttClient, MqttConfig, Supervisor},
+    opendtu::topics::{AcField, InverterCommand, InverterField, InverterTopic},
+    pulse::topics::PULSE_CONSUMPTION_TOPIC,
+    service::{self, Daemon},
+    topic::Delivery,
+};
+use log::{debug, error, info, warn};
+use rumqttc::{Event, Packet, QoS};
+use simulation::SimulatedHouse;
+use std::{str::FromStr, time::Instant};
+use syslog::{Facility, Formatter3164};
+use tokio::{
+    sync::mpsc,
+    time::{self, timeout, Duration},
+};
+use tokio_util::sync::CancellationToken;
+
+mod controller;
+mod simulation;
+
+const MQTT_CLIENT_NAME: &str = "feed-in-controller";
+const MQTT_BROKER_ADDRESS: &str = "rpiserver";
+const MQTT_BROKER_PORT: u16 = 1883;
+const CONTROL_INTERVAL: Duration = Duration::from_secs(2);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+const DAEMON_NAME: &str = "emlimitd";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let formatter = Formatter3164 {
+        facility: Facility::LOG_DAEMON,
+        hostname: None,
+        process: DAEMON_NAME.into(),
+        pid: 0,
+    };
+
+    env_logger::init();
+    syslog::unix(formatter).expect("Failed to initialize syslog");
+
+    println!(
+        "Starting Feed-in Controller (emlimitd) v{}",
+        env!("CARGO_PKG_VERSION")
+    );
+
+    let settings = settings_from_env()?;
+    info!("Using {:?}", settings);
+
+    if std::env::args().any(|arg| arg == "--simulate") {
+        simulate(settings);
+        return Ok(());
+    }
+
+    let serial =
+        std::env::var("EMLIMITD_INVERTER_SERIAL").context("EMLIMITD_INVERTER_SERIAL is not set")?;
+    let topics = Topics {
+        power: InverterTopic::new(&serial, InverterField::Ac(AcField::Power)).to_string(),
+        limit: InverterTopic::new(&serial, InverterField::LimitRelative).to_string(),
+        command: InverterCommand::LimitNonPersistentRelative.topic(&serial),
+    };
+
+    // The broker, credentials and TLS can be changed through EMLIMITD_MQTT_*
+    let mut mqtt_config = MqttConfig::new(MQTT_CLIENT_NAME, MQTT_BROKER_ADDRESS, MQTT_BROKER_PORT);
+    mqtt_config.keep_alive = Duration::from_secs(5);
+    mqtt_config.load_env("EMLIMITD")?;
+    let daemon = Daemon::new(DAEMON_NAME, env!("CARGO_PKG_VERSION"));
+    let (client, supervisor) = mqtt::connect(&mqtt_config, &daemon)?;
+
+    // Subscribed again after every reconnect
+    for topic in [PULSE_CONSUMPTION_TOPIC.name(), &topics.power, &topics.limit] {
+        client.subscribe(topic, QoS::AtMostOnce).await?;
+    }
+
+    let token = CancellationToken::new();
+    let (tx, rx) = mpsc::channel(10);
+    let event_loop = tokio::spawn(process_event_loop(supervisor, tx));
+    let control = tokio::spawn(control(
+        Controller::new(settings),
+        client.clone(),
+        topics,
+        rx,
+        token.clone(),
+    ));
+    // Stalled message handling blocks the event loop and stops the pings
+    let watchdog_client = client.clone();
+    service::spawn_watchdog(token.clone(), move |period| {
+        watchdog_client.is_active(period)
+    });
+
+    tokio::select! {
+        result = service::shutdown_signal() => {
+            result?;
+            info!("Shutting down");
+        }
+        _ = token.cancelled() => {}
+    }
+    service::notify("STOPPING=1");
+    token.cancel();
+
+    // The limit is released before going offline
+    let result = control.await?;
+    if let Err(e) = client.disconnect().await {
+        error!("Failed to disconnect: {:?}", e);
+    }
+    if timeout(SHUTDOWN_TIMEOUT, event_loop).await.is_err() {
+        error!("Timeout flushing the MQTT messages");
+    }
+    result
+}
+
+/// Topics of the controlled inverter
+struct Topics {
+    power: String,
+    limit: String,
+    command: String,
+}
+
+/// Forwards the MQTT events until the client disconnected, the supervisor
+/// reconnects if the broker goes away
+async fn process_event_loop(mut supervisor: Supervisor, tx: mpsc::Sender<Event>) {
+    while let Some(notification) = supervisor.poll().await {
+        let _ = tx.send(notification).await;
+    }
+}
+
+/// Adjusts the limit until shutdown, then sets the inverter back to its full
+/// power so it is not left throttled while nothing controls it
+async fn control(
+    mut controller: Controller,
+    client: MqttClient,
+    topics: Topics,
+    mut rx: mpsc::Receiver<Event>,
+    token: CancellationToken,
+) -> Result<()> {
+    // A command queued while disconnected is outdated by the time the
+    // connection is back
+    let command = Delivery {
+        qos: QoS::AtLeastOnce,
+        retain: false,
+        expiry: Some(controller.command_interval()),
+    };
+    let mut interval = time::interval(CONTROL_INTERVAL);
+
+    loop {
+        tokio::select! {
+            notification = rx.recv() => {
+                let Some(notification) = notification else {
+                    break;
+                };
+                match notification {
+                    Event::Incoming(Packet::Publish(publish)) => {
+                        debug!("Received = {:?}", publish);
+                        if let Err(e) = handle_publish(&mut controller, &publish, &topics.power, &topics.limit) {
+                            warn!("Failed to handle message on {}: {:?}", publish.topic, e);
+                        }
+                    }
+                    Event::Incoming(Packet::ConnAck(_)) => service::notify("READY=1"),
+                    _ => {}
+                }
+            }
+            _ = interval.tick() => {
+                if let Some(limit) = controller.poll(Instant::now()) {
+                    info!("Setting inverter limit to {limit}%");
+                    if let Err(e) = client.publish(&topics.command, command, limit.to_string().into()).await {
+                        error!("Failed to publish limit command: {:?}", e);
+                    }
+                }
+            }
+            _ = token.cancelled() => break,
+        }
+    }
+
+    token.cancel();
+    if let Some(limit) = controller.release() {
+        info!("Setting inverter limit to {limit}%");
+        client
+            .publish(&topics.command, command, limit.to_string().into())
+            .await
+            .context("Failed to publish limit command")?;
+    }
+    Ok(())
+}
+
+fn handle_publish(
+    controller: &mut Controller,
+    publish: &rumqttc::Publish,
+    power_topic: &str,
+    limit_topic: &str,
+) -> Result<()> {
+    match publish.topic.as_str() {
+        topic if topic == PULSE_CONSUMPTION_TOPIC.name() => {
+            let consumption = PULSE_CONSUMPTION_TOPIC
+                .decode(&publish.payload)?
+                .consumption;
+            controller.set_grid_power(Instant::now(), consumption as f32);
+        }
+        topic if topic == power_topic => {
+            controller.set_production(parse_number(&publish.payload)?);
+        }
+        topic if topic == limit_topic => {
+            controller.set_reported_limit(parse_number(&publish.payload)?);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn parse_number(payload: &[u8]) -> Result<f32> {
+    let payload = std::str::from_utf8(payload)?;
+    payload
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Not a number: {payload}"))
+}
+
+fn settings_from_env() -> Result<Settings> {
+    fn var<T: FromStr>(name: &str, default: T) -> Result<T> {
+        match std::env::var(name) {
+            Ok(value) => value
+                .parse()
+                .map_err(|_| anyhow!("{name} has an invalid value: {value}")),
+            Err(_) => Ok(default),
+        }
+    }
+
+    let default = Settings::default();
+    Ok(Settings {
+        inverter_power: var("EMLIMITD_INVERTER_POWER", default.inverter_power)?,
+        max_export: var("EMLIMITD_MAX_EXPORT", default.max_export)?,
+        deadband: var("EMLIMITD_DEADBAND", default.deadband)?,
+        min_limit: var("EMLIMITD_MIN_LIMIT", default.min_limit)?,
+        command_interval: Duration::from_secs(var(
+            "EMLIMITD_COMMAND_INTERVAL",
+            default.command_interval.as_secs(),
+        )?),
+        stale_timeout: Duration::from_secs(var(
+            "EMLIMITD_STALE_TIMEOUT",
+            default.stale_timeout.as_secs(),
+        )?),
+    })
+}
+
+/// Runs the controller against a simulated house instead of the broker.
+/// The simulated clock advances by `CONTROL_INTERVAL` per step.
+fn simulate(settings: Settings) {
+    let mut house = SimulatedHouse::new(settings.inverter_power, 300.0, 700.0);
+    let stale_after = 120;
+    let mut controller = Controller::new(settings);
+    let start = Instant::now();
+
+    for step in 0..150u32 {
+        let now = start + CONTROL_INTERVAL * step;
+        match step {
+            40 => house.load = 900.0,
+            80 => house.pv_available = 250.0,
+            100 => house.pv_available = 700.0,
+            _ => {}
+        }
+
+        // Stop feeding data to exercise the failsafe
+        if step < stale_after {
+            controller.set_grid_power(now, house.grid_power());
+            controller.set_production(house.production());
+        }
+
+        if let Some(limit) = controller.poll(now) {
+            house.set_limit(limit);
+            println!(
+                "t={:>4}s load={:>4.0}W pv={:>4.0}W grid={:>5.0}W -> limit {limit}%",
+                (now - start).as_secs(),
+                house.load,
+                house.production(),
+                house.grid_power(),
+            );
+        }
+    }
+}
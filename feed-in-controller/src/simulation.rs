@@ -0,0 +1,39 @@
+use crate::controller::FULL_LIMIT;
+
+/// Simple model of a house with a single limited inverter. Used by the
+/// simulation mode and by the tests in place of the Pulse and OpenDTU.
+#[derive(Debug, Clone)]
+pub struct SimulatedHouse {
+    inverter_power: f32,
+    /// W consumed by the house
+    pub load: f32,
+    /// W the panels could produce without limit
+    pub pv_available: f32,
+    limit: f32,
+}
+
+impl SimulatedHouse {
+    pub fn new(inverter_power: f32, load: f32, pv_available: f32) -> Self {
+        Self {
+            inverter_power,
+            load,
+            pv_available,
+            limit: FULL_LIMIT,
+        }
+    }
+
+    pub fn set_limit(&mut self, percent: f32) {
+        self.limit = percent;
+    }
+
+    pub fn production(&self) -> f32 {
+        self.pv_available
+            .min(self.inverter_power)
+            .min(self.limit / FULL_LIMIT * self.inverter_power)
+    }
+
+    /// Positive when importing, negative when exporting
+    pub fn grid_power(&self) -> f32 {
+        self.load - self.production()
+    }
+}
@@ -0,0 +1,97 @@
+use crate::{awtrix3::Color, display::Screen};
+use energy_monitor_lib::service::{status_topic, Availability};
+use serde::Deserialize;
+
+/// Warning shown on the displays while a daemon providing the values is offline
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AvailabilityConfig {
+    /// Daemons whose `energy-monitor/<daemon>/status` topic is watched
+    #[serde(default = "default_daemons")]
+    pub daemons: Vec<String>,
+    #[serde(default = "default_icon")]
+    pub icon: String,
+    #[serde(default = "default_color")]
+    pub color: Color,
+}
+
+fn default_daemons() -> Vec<String> {
+    vec!["emtibberd".to_string()]
+}
+
+fn default_icon() -> String {
+    "warning".to_string()
+}
+
+fn default_color() -> Color {
+    Color::new(0xFF, 0x00, 0x00)
+}
+
+impl Default for AvailabilityConfig {
+    fn default() -> Self {
+        Self {
+            daemons: default_daemons(),
+            icon: default_icon(),
+            color: default_color(),
+        }
+    }
+}
+
+impl AvailabilityConfig {
+    pub fn topics(&self) -> impl Iterator<Item = String> + '_ {
+        self.daemons.iter().map(|daemon| status_topic(daemon))
+    }
+
+    /// Daemon publishing on the status topic
+    pub fn daemon(&self, topic: &str) -> Option<&str> {
+        self.daemons
+            .iter()
+            .find(|daemon| status_topic(daemon) == topic)
+            .map(String::as_str)
+    }
+
+    /// Name of the warning screen of the daemon
+    pub fn screen_name(daemon: &str) -> String {
+        format!("{daemon}_offline")
+    }
+
+    /// Warning screen if the daemon is offline
+    pub fn render(&self, daemon: &str, state: Availability) -> Option<Screen> {
+        (state == Availability::Offline).then(|| Screen {
+            name: Self::screen_name(daemon),
+            text: format!("{daemon} offline"),
+            icon: Some(self.icon.clone()),
+            color: Some(self.color),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let config = AvailabilityConfig::default();
+        assert_eq!(
+            config.daemon("energy-monitor/emtibberd/status"),
+            Some("emtibberd")
+        );
+        assert_eq!(config.daemon("energy-monitor/emdisplayd/status"), None);
+
+        assert_eq!(config.render("emtibberd", Availability::Online), None);
+        let screen = config
+            .render("emtibberd", Availability::from_payload(b"offline"))
+            .unwrap();
+        assert_eq!(screen.name, "emtibberd_offline");
+        assert_eq!(screen.text, "emtibberd offline");
+        assert_eq!(screen.icon.as_deref(), Some("warning"));
+
+        // States of newer versions show no warning
+        assert_eq!(
+            config.render("emtibberd", Availability::from_payload(b"degraded")),
+            None
+        );
+    }
+}
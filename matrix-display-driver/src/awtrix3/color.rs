@@ -0,0 +1,212 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Hue in degrees (0..360), saturation and value in 0..1
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hsv {
+    pub h: f32,
+    pub s: f32,
+    pub v: f32,
+}
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    pub fn to_hsv(self) -> Hsv {
+        let r = self.r as f32 / 255.0;
+        let g = self.g as f32 / 255.0;
+        let b = self.b as f32 / 255.0;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+
+        let h = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let s = if max == 0.0 { 0.0 } else { delta / max };
+
+        Hsv { h, s, v: max }
+    }
+
+    /// Mixes two colors channel by channel, `t` = 0 gives `self`, `t` = 1 gives `other`
+    pub fn blend(self, other: Color, t: f32) -> Color {
+        let t = t.clamp(0.0, 1.0);
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Color::new(
+            mix(self.r, other.r),
+            mix(self.g, other.g),
+            mix(self.b, other.b),
+        )
+    }
+
+    /// Blends two colors in the HSV space, `t` = 0 gives `self`, `t` = 1 gives `other`.
+    /// The hue takes the shorter way around the color wheel, so green to red passes yellow.
+    pub fn interpolate(self, other: Color, t: f32) -> Color {
+        let t = t.clamp(0.0, 1.0);
+        let (from, to) = (self.to_hsv(), other.to_hsv());
+
+        // Grey has no hue, keep the hue of the other color
+        let (from_h, to_h) = match (from.s == 0.0, to.s == 0.0) {
+            (true, false) => (to.h, to.h),
+            (false, true) => (from.h, from.h),
+            _ => (from.h, to.h),
+        };
+        let mut dh = to_h - from_h;
+        if dh > 180.0 {
+            dh -= 360.0;
+        } else if dh < -180.0 {
+            dh += 360.0;
+        }
+
+        Hsv {
+            h: (from_h + dh * t).rem_euclid(360.0),
+            s: from.s + (to.s - from.s) * t,
+            v: from.v + (to.v - from.v) * t,
+        }
+        .to_color()
+    }
+}
+
+impl Hsv {
+    pub fn to_color(self) -> Color {
+        let c = self.v * self.s;
+        let h = self.h.rem_euclid(360.0) / 60.0;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = self.v - c;
+        let channel = |v: f32| ((v + m) * 255.0).round() as u8;
+        Color::new(channel(r), channel(g), channel(b))
+    }
+}
+
+const NAMED_COLORS: [(&str, Color); 13] = [
+    ("black", Color::new(0x00, 0x00, 0x00)),
+    ("white", Color::new(0xFF, 0xFF, 0xFF)),
+    ("red", Color::new(0xFF, 0x00, 0x00)),
+    ("green", Color::new(0x00, 0xFF, 0x00)),
+    ("blue", Color::new(0x00, 0x00, 0xFF)),
+    ("yellow", Color::new(0xFF, 0xFF, 0x00)),
+    ("cyan", Color::new(0x00, 0xFF, 0xFF)),
+    ("magenta", Color::new(0xFF, 0x00, 0xFF)),
+    ("orange", Color::new(0xFF, 0xA5, 0x00)),
+    ("purple", Color::new(0x80, 0x00, 0x80)),
+    ("gold", Color::new(0xFF, 0xD7, 0x00)),
+    ("grey", Color::new(0x80, 0x80, 0x80)),
+    ("gray", Color::new(0x80, 0x80, 0x80)),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidColor(pub String);
+
+impl fmt::Display for InvalidColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid color {}, expected #RRGGBB, [r, g, b] or a color name",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidColor {}
+
+impl FromStr for Color {
+    type Err = InvalidColor;
+
+    /// Parses `#RRGGBB` or a color name like `orange`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, color)) = NAMED_COLORS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        {
+            return Ok(*color);
+        }
+
+        let hex = s
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6 && hex.is_ascii())
+            .ok_or_else(|| InvalidColor(s.to_string()))?;
+        let channel = |i: usize| {
+            u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| InvalidColor(s.to_string()))
+        };
+        Ok(Color::new(channel(0)?, channel(2)?, channel(4)?))
+    }
+}
+
+impl From<[u8; 3]> for Color {
+    fn from([r, g, b]: [u8; 3]) -> Self {
+        Color::new(r, g, b)
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+    }
+}
+
+/// Awtrix takes colors as `#RRGGBB`
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Text(String),
+            Array([u8; 3]),
+        }
+
+        match Repr::deserialize(deserializer) {
+            Ok(Repr::Text(text)) => text.parse().map_err(de::Error::custom),
+            Ok(Repr::Array(rgb)) => Ok(rgb.into()),
+            Err(_) => Err(de::Error::custom(
+                "expected #RRGGBB, [r, g, b] or a color name",
+            )),
+        }
+    }
+}
+
+/// Colors evenly spread from the start (0) to the end (1) of a range
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct ColorScale(pub Vec<Color>);
+
+impl ColorScale {
+    pub fn color_at(&self, fraction: f64) -> Option<Color> {
+        let stops = &self.0;
+        if stops.len() < 2 {
+            return stops.first().copied();
+        }
+
+        let position = fraction.clamp(0.0, 1.0) * (stops.len() - 1) as f64;
+        let index = (position.floor() as usize).min(stops.len() - 2);
+        Some(stops[index].interpolate(stops[index + 1], (position - index as f64) as f32))
+    }
+}
@@ -1,29 +1,136 @@
+use crate::{awtrix3::Color, display::Screen, schedule::DisplayState};
 use serde::{Deserialize, Serialize};
 
 #[serde_with::skip_serializing_none]
 #[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
 pub struct CustomApplication {
     pub text: String,
-    #[serde(rename(serialize = "textCase"))]
+    #[serde(rename = "textCase")]
     pub text_case: Option<i32>,
-    #[serde(rename(serialize = "topText"))]
+    #[serde(rename = "topText")]
     pub top_text: Option<bool>,
-    #[serde(rename(serialize = "textOffset"))]
+    #[serde(rename = "textOffset")]
     pub text_offset: Option<i32>,
     pub center: Option<bool>,
-    pub color: Option<String>, // ToDo: check for better color support
-    pub gradient: Option<String>,
-    #[serde(rename(serialize = "blinkText"))]
+    pub color: Option<Color>,
+    /// Awtrix fades the text from the first to the second color
+    pub gradient: Option<Vec<Color>>,
+    #[serde(rename = "blinkText")]
     pub blink_text: Option<i32>,
-    #[serde(rename(serialize = "fadeText"))]
+    #[serde(rename = "fadeText")]
     pub fade_text: Option<i32>,
-    pub background: Option<String>, // ToDo: check for better color support
+    pub background: Option<Color>,
     pub rainbow: Option<bool>,
     pub icon: Option<String>,
-    #[serde(rename(serialize = "pushIcon"))]
+    #[serde(rename = "pushIcon")]
     pub push_icon: Option<i32>,
     pub repeat: Option<i32>,
     pub duration: Option<i32>,
-    #[serde(rename(serialize = "lifeTime"))]
+    #[serde(rename = "lifeTime")]
     pub life_time: Option<i32>,
+    /// Progress bar from 0 to 100
+    pub progress: Option<i32>,
+    #[serde(rename = "progressC")]
+    pub progress_color: Option<Color>,
+    #[serde(rename = "progressBC")]
+    pub progress_background: Option<Color>,
+}
+
+/// Payload of `matrixdisplay/settings`, only the given settings are changed
+#[serde_with::skip_serializing_none]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+pub struct Settings {
+    /// Brightness from 0 to 255
+    #[serde(rename = "BRI")]
+    pub brightness: Option<u8>,
+    #[serde(rename = "ABRI")]
+    pub auto_brightness: Option<bool>,
+}
+
+/// Payload of `matrixdisplay/power`
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Power {
+    pub power: bool,
+}
+
+impl From<&Screen> for CustomApplication {
+    fn from(screen: &Screen) -> Self {
+        Self {
+            text: screen.text.clone(),
+            icon: screen.icon.clone(),
+            color: screen.color,
+            gradient: screen.gradient.clone(),
+            background: screen.background,
+            progress: screen.progress,
+            progress_color: screen.progress_color,
+            progress_background: screen.progress_background,
+            duration: screen.duration,
+            life_time: screen.life_time,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&DisplayState> for Settings {
+    /// Without a brightness the clock uses its automatic brightness
+    fn from(state: &DisplayState) -> Self {
+        Self {
+            brightness: state.brightness,
+            auto_brightness: Some(state.brightness.is_none()),
+        }
+    }
+}
+
+/// Published by the clock on `matrixdisplay/stats` every few seconds
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+pub struct Stats {
+    /// Battery level in percent
+    #[serde(rename = "bat")]
+    pub battery: Option<u8>,
+    /// Ambient light
+    pub lux: Option<f64>,
+    /// Current brightness from 0 to 255
+    #[serde(rename = "bri")]
+    pub brightness: Option<u8>,
+    /// Temperature in °C
+    #[serde(rename = "temp")]
+    pub temperature: Option<f64>,
+    /// Relative humidity in percent
+    #[serde(rename = "hum")]
+    pub humidity: Option<f64>,
+    /// Seconds since the clock started
+    pub uptime: Option<u64>,
+    pub wifi_signal: Option<i32>,
+    pub version: Option<String>,
+    /// Name of the app currently shown
+    #[serde(rename = "app")]
+    pub current_app: Option<String>,
+}
+
+/// The buttons on top of the clock
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Button {
+    Left,
+    Select,
+    Right,
+}
+
+impl Button {
+    pub const ALL: [Button; 3] = [Button::Left, Button::Select, Button::Right];
+
+    /// Name used in the topic, e.g. `buttonLeft`
+    pub fn topic_name(self) -> &'static str {
+        match self {
+            Button::Left => "buttonLeft",
+            Button::Select => "buttonSelect",
+            Button::Right => "buttonRight",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ButtonEvent {
+    pub button: Button,
+    /// The clock publishes `1` when the button is pressed and `0` when it is released
+    pub pressed: bool,
 }
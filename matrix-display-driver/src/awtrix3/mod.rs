@@ -1,9 +1,13 @@
+pub mod color;
 pub mod dto;
 pub mod topics;
 
+pub use color::Color;
+
 #[cfg(test)]
 mod test {
-    use crate::awtrix3::dto::CustomApplication;
+    use crate::awtrix3::{color::*, dto::*, topics::*};
+    use energy_monitor_lib::topic::{Decode, Encode};
 
     #[test]
     fn test_default_custom_application() {
@@ -11,4 +15,79 @@ mod test {
 
         assert_eq!(custom_application.icon, None);
     }
+
+    #[test]
+    fn test_color_scale() {
+        let green: Color = "#00FF00".parse().unwrap();
+        let red: Color = "#FF0000".parse().unwrap();
+        assert_eq!(green.to_string(), "#00FF00");
+        assert!("00FF00".parse::<Color>().is_err());
+
+        let scale = ColorScale(vec![green, red]);
+        assert_eq!(scale.color_at(-1.0), Some(green));
+        assert_eq!(scale.color_at(0.5), Some(Color::new(255, 255, 0)));
+        assert_eq!(scale.color_at(2.0), Some(red));
+    }
+
+    #[test]
+    fn test_parse_colors() {
+        let orange = Color::new(0xFF, 0xA5, 0x00);
+        assert_eq!("Orange".parse(), Ok(orange));
+        assert_eq!(
+            serde_json::from_str::<Color>("[255, 165, 0]").unwrap(),
+            orange
+        );
+        assert_eq!(
+            serde_json::from_str::<Color>("\"#FFA500\"").unwrap(),
+            orange
+        );
+        assert!(serde_json::from_str::<Color>("[256, 0, 0]").is_err());
+        assert!(serde_json::from_str::<Color>("\"#FFA5\"").is_err());
+        assert!(serde_json::from_str::<Color>("\"chartreuse\"").is_err());
+
+        let black = Color::new(0, 0, 0);
+        let white = Color::new(0xFF, 0xFF, 0xFF);
+        assert_eq!(black.blend(white, 0.5), Color::new(0x80, 0x80, 0x80));
+    }
+
+    #[test]
+    fn test_custom_application_roundtrip() {
+        let app = CustomApplication {
+            text: "1.2".to_string(),
+            life_time: Some(10),
+            gradient: Some(vec![Color::new(0xFF, 0, 0), Color::new(0, 0xFF, 0)]),
+            progress_color: Some(Color::new(0, 0, 0xFF)),
+            ..Default::default()
+        };
+
+        let encoded = CustomApplication::encode(&app);
+        assert_eq!(
+            encoded,
+            r##"{"text":"1.2","gradient":["#FF0000","#00FF00"],"lifeTime":10,"progressC":"#0000FF"}"##
+        );
+        assert_eq!(CustomApplication::decode(&encoded).unwrap(), app);
+    }
+
+    #[test]
+    fn test_stats_and_buttons() {
+        let stats = Stats::decode(
+            r#"{"bat":94,"bat_raw":651,"type":0,"lux":120.5,"ldr_raw":417,"ram":142352,"bri":80,"temp":24.6,"hum":39,"uptime":3261,"wifi_signal":-61,"messages":12,"version":"0.96","indicator1":false,"app":"power","uid":"awtrix_1a2b3c","matrix":true}"#,
+        )
+        .unwrap();
+        assert_eq!(stats.battery, Some(94));
+        assert_eq!(stats.lux, Some(120.5));
+        assert_eq!(stats.current_app.as_deref(), Some("power"));
+
+        assert_eq!(
+            parse_button_event("matrixdisplay", "matrixdisplay/stats/buttonRight", b"1"),
+            Some(ButtonEvent {
+                button: Button::Right,
+                pressed: true
+            })
+        );
+        assert_eq!(
+            parse_button_event("matrixdisplay", "matrixdisplay/stats/currentApp", b"power"),
+            None
+        );
+    }
 }
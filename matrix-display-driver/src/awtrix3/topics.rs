@@ -1,12 +1,45 @@
-use crate::awtrix3::dto::*;
-use energy_monitor_lib::topic::Topic;
-
-#[rustfmt::skip]
-
-pub const MATRIX_DISPLAY_APP_YIELD_DAY_TOPIC: Topic<CustomApplication> = Topic::new("matrixdisplay/custom/yieldday");
-pub const MATRIX_DISPLAY_APP_CURRENT_PRODUCTION_TOPIC: Topic<CustomApplication> =
-    Topic::new("matrixdisplay/custom/power");
-pub const MATRIX_DISPLAY_APP_CURRENT_CONSUMPTION_TOPIC: Topic<CustomApplication> =
-    Topic::new("matrixdisplay/custom/consumption");
-pub const MATRIX_DISPLAY_APP_CURRENT_PRICE_TOPIC: Topic<CustomApplication> =
-    Topic::new("matrixdisplay/custom/tibberprice");
+use crate::awtrix3::dto::{Button, ButtonEvent};
+
+/// Prefix the Awtrix firmware uses by default
+pub const MATRIX_DISPLAY_TOPIC_PREFIX: &str = "matrixdisplay";
+
+/// Topic of the custom app `name`, e.g. `matrixdisplay/custom/power`
+pub fn custom_app_topic(prefix: &str, name: &str) -> String {
+    format!("{prefix}/custom/{name}")
+}
+
+/// Topic taking a [`CustomApplication`](super::dto::CustomApplication) shown once
+pub fn notify_topic(prefix: &str) -> String {
+    format!("{prefix}/notify")
+}
+
+/// Topic taking [`Settings`](super::dto::Settings), e.g. `matrixdisplay/settings`
+pub fn settings_topic(prefix: &str) -> String {
+    format!("{prefix}/settings")
+}
+
+/// Topic taking [`Power`](super::dto::Power), e.g. `matrixdisplay/power`
+pub fn power_topic(prefix: &str) -> String {
+    format!("{prefix}/power")
+}
+
+/// Topic the clock publishes [`Stats`](super::dto::Stats) on, e.g. `matrixdisplay/stats`
+pub fn stats_topic(prefix: &str) -> String {
+    format!("{prefix}/stats")
+}
+
+/// Topic the clock publishes the state of the button on, e.g. `matrixdisplay/stats/buttonLeft`
+pub fn button_topic(prefix: &str, button: Button) -> String {
+    format!("{prefix}/stats/{}", button.topic_name())
+}
+
+/// Returns the event if `topic` is a button topic of the clock on `prefix`
+pub fn parse_button_event(prefix: &str, topic: &str, payload: &[u8]) -> Option<ButtonEvent> {
+    let button = Button::ALL
+        .into_iter()
+        .find(|button| button_topic(prefix, *button) == topic)?;
+    Some(ButtonEvent {
+        button,
+        pressed: payload.trim_ascii() == b"1",
+    })
+}
@@ -0,0 +1,68 @@
+use crate::{
+    availability::AvailabilityConfig,
+    display::{default_displays, DisplayConfig},
+    schedule::ScheduleConfig,
+    templates::{default_templates, AppTemplate},
+};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+pub const CONFIG_PATH_ENV: &str = "EMDISPLAYD_CONFIG";
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/emdisplayd.toml";
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Displays showing the apps, every app is shown on all of them
+    #[serde(default = "default_displays", rename = "display")]
+    pub displays: Vec<DisplayConfig>,
+    /// Apps shown on the display, in the order they are listed
+    #[serde(default = "default_templates", rename = "app")]
+    pub apps: Vec<AppTemplate>,
+    /// Brightness, power and daylight only apps are left alone if not configured
+    pub schedule: Option<ScheduleConfig>,
+    /// Warning shown while a daemon providing the values is offline
+    #[serde(default)]
+    pub availability: AvailabilityConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            displays: default_displays(),
+            apps: default_templates(),
+            schedule: None,
+            availability: AvailabilityConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the file named by `EMDISPLAYD_CONFIG` or the default location.
+    /// If the file does not exist the default configuration is used.
+    pub fn load() -> Result<Self> {
+        let path = std::env::var(CONFIG_PATH_ENV).unwrap_or(DEFAULT_CONFIG_PATH.to_string());
+        let path = Path::new(&path);
+        if !path.exists() {
+            log::info!("{} not found, using default configuration", path.display());
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Invalid configuration {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_example_config_matches_defaults() {
+        let config: Config = toml::from_str(include_str!("../emdisplayd.example.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::{
+    awtrix3::dto::*,
+    display::{DisplaySink, Screen},
+    metrics::METRICS,
+    schedule::DisplayState,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use energy_monitor_lib::topic::Encode;
+use log::{debug, info, warn};
+use reqwest::{header::CONTENT_TYPE, Client, Url};
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Tracks whether the clock is reachable and when to check again
+#[derive(Debug)]
+struct Health {
+    online: bool,
+    retry_at: Instant,
+    retry_delay: Duration,
+}
+
+impl Health {
+    fn new() -> Self {
+        Self {
+            online: true,
+            retry_at: Instant::now(),
+            retry_delay: MIN_RETRY_DELAY,
+        }
+    }
+
+    /// Schedules the next health check, the delay doubles with every failure
+    fn failed(&mut self, now: Instant) {
+        if self.online {
+            self.online = false;
+            self.retry_delay = MIN_RETRY_DELAY;
+        } else {
+            self.retry_delay = (self.retry_delay * 2).min(MAX_RETRY_DELAY);
+        }
+        self.retry_at = now + self.retry_delay;
+    }
+
+    fn recovered(&mut self) {
+        self.online = true;
+        self.retry_delay = MIN_RETRY_DELAY;
+    }
+
+    fn should_check(&self, now: Instant) -> bool {
+        !self.online && now >= self.retry_at
+    }
+}
+
+/// Awtrix clock driven directly through its HTTP API. While the clock is
+/// offline requests are skipped and `/api/stats` is polled with an increasing
+/// delay. Once it answers again the current screens and settings are resent.
+pub struct AwtrixHttpSink {
+    client: Client,
+    url: Url,
+    health: Mutex<Health>,
+    screens: Mutex<BTreeMap<String, Screen>>,
+    state: Mutex<Option<DisplayState>>,
+}
+
+impl AwtrixHttpSink {
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            client: Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            url: url
+                .parse()
+                .with_context(|| format!("Invalid display url {url}"))?,
+            health: Mutex::new(Health::new()),
+            screens: Mutex::new(BTreeMap::new()),
+            state: Mutex::new(None),
+        })
+    }
+
+    async fn post(&self, path: &str, query: &[(&str, &str)], body: Bytes) -> Result<()> {
+        let url = self.url.join(path)?;
+        self.client
+            .post(url.clone())
+            .query(query)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Request to {url} failed"))?;
+        Ok(())
+    }
+
+    /// The clock answers on `/api/stats` if it is up
+    async fn check_health(&self) -> Result<()> {
+        let url = self.url.join("/api/stats")?;
+        self.client
+            .get(url.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Health check {url} failed"))?;
+        Ok(())
+    }
+
+    /// Returns whether requests should be sent, checks the health of an
+    /// offline clock once the retry delay passed
+    async fn ready(&self) -> bool {
+        {
+            let health = self.health.lock().unwrap();
+            if health.online {
+                return true;
+            }
+            if !health.should_check(Instant::now()) {
+                return false;
+            }
+        }
+
+        if let Err(e) = self.check_health().await {
+            let mut health = self.health.lock().unwrap();
+            health.failed(Instant::now());
+            debug!(
+                "{} still offline, retry in {:?}: {:?}",
+                self.url, health.retry_delay, e
+            );
+            return false;
+        }
+
+        info!("{} is online again", self.url);
+        self.health.lock().unwrap().recovered();
+        self.set_online_metric(true);
+        if let Err(e) = self.resend().await {
+            self.failed(&e);
+            return false;
+        }
+        true
+    }
+
+    fn failed(&self, error: &anyhow::Error) {
+        let mut health = self.health.lock().unwrap();
+        if health.online {
+            warn!("{} went offline: {:?}", self.url, error);
+            self.set_online_metric(false);
+        }
+        health.failed(Instant::now());
+    }
+
+    fn set_online_metric(&self, online: bool) {
+        METRICS
+            .display_online
+            .with_label_values(&[self.url.as_str()])
+            .set(online as i64);
+    }
+
+    /// Sends everything the clock may have lost while it was offline
+    async fn resend(&self) -> Result<()> {
+        let state = *self.state.lock().unwrap();
+        if let Some(state) = state {
+            self.send_state(&state).await?;
+        }
+        let screens: Vec<Screen> = self.screens.lock().unwrap().values().cloned().collect();
+        for screen in &screens {
+            self.send_screen(screen).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_screen(&self, screen: &Screen) -> Result<()> {
+        let app = CustomApplication::from(screen);
+        self.post(
+            "/api/custom",
+            &[("name", &screen.name)],
+            CustomApplication::encode(&app),
+        )
+        .await
+    }
+
+    async fn send_state(&self, state: &DisplayState) -> Result<()> {
+        let settings = Settings::from(state);
+        self.post("/api/settings", &[], Settings::encode(&settings))
+            .await?;
+        let power = Power { power: state.power };
+        self.post("/api/power", &[], Power::encode(&power)).await
+    }
+
+    /// Sends the request if the clock is online and marks it offline on errors
+    async fn send<F>(&self, request: F) -> Result<()>
+    where
+        F: std::future::Future<Output = Result<()>>,
+    {
+        if !self.ready().await {
+            return Ok(());
+        }
+        request
+            .await
+            .inspect(|_| self.set_online_metric(true))
+            .inspect_err(|e| self.failed(e))
+    }
+}
+
+#[async_trait]
+impl DisplaySink for AwtrixHttpSink {
+    fn name(&self) -> &str {
+        self.url.as_str()
+    }
+
+    async fn show(&self, screen: &Screen) -> Result<()> {
+        self.screens
+            .lock()
+            .unwrap()
+            .insert(screen.name.clone(), screen.clone());
+        self.send(self.send_screen(screen)).await
+    }
+
+    async fn notify(&self, screen: &Screen) -> Result<()> {
+        let app = CustomApplication::from(screen);
+        self.send(self.post("/api/notify", &[], CustomApplication::encode(&app)))
+            .await
+    }
+
+    async fn remove(&self, name: &str) -> Result<()> {
+        self.screens.lock().unwrap().remove(name);
+        // An empty body removes the app
+        self.send(self.post("/api/custom", &[("name", name)], Bytes::new()))
+            .await
+    }
+
+    async fn apply(&self, state: &DisplayState) -> Result<()> {
+        *self.state.lock().unwrap() = Some(*state);
+        self.send(self.send_state(state)).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use energy_monitor_lib::testing::HttpStub;
+
+    fn online_metric(url: &str) -> i64 {
+        METRICS.display_online.with_label_values(&[url]).get()
+    }
+
+    #[test]
+    fn test_backoff() {
+        let now = Instant::now();
+        let mut health = Health::new();
+        health.failed(now);
+        assert_eq!(health.retry_delay, MIN_RETRY_DELAY);
+        assert!(!health.should_check(now));
+        assert!(health.should_check(now + MIN_RETRY_DELAY));
+
+        for _ in 0..20 {
+            health.failed(now);
+        }
+        assert_eq!(health.retry_delay, MAX_RETRY_DELAY);
+
+        health.recovered();
+        health.failed(now);
+        assert_eq!(health.retry_delay, MIN_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_resend_when_back_online() {
+        let stub = HttpStub::start(503).await;
+        let sink = AwtrixHttpSink::new(&stub.url).unwrap();
+        let screen = Screen {
+            name: "power".to_string(),
+            text: "523".to_string(),
+            ..Default::default()
+        };
+
+        // The clock is offline, the first request fails and the next one is skipped
+        assert!(sink.show(&screen).await.is_err());
+        assert!(sink.show(&screen).await.is_ok());
+        assert_eq!(stub.requests().len(), 1);
+        assert_eq!(online_metric(sink.name()), 0);
+
+        // After the retry delay the health check succeeds and the screen is resent
+        stub.set_status(200);
+        sink.health.lock().unwrap().retry_at = Instant::now();
+        sink.remove("yieldday").await.unwrap();
+        let lines: Vec<_> = stub.requests().into_iter().map(|r| r.line).collect();
+        assert_eq!(
+            lines,
+            [
+                "POST /api/custom?name=power HTTP/1.1",
+                "GET /api/stats HTTP/1.1",
+                "POST /api/custom?name=power HTTP/1.1",
+                "POST /api/custom?name=yieldday HTTP/1.1",
+            ]
+        );
+        assert_eq!(online_metric(sink.name()), 1);
+    }
+
+    #[tokio::test]
+    async fn test_online_when_first_reached() {
+        let stub = HttpStub::start(200).await;
+        let sink = AwtrixHttpSink::new(&stub.url).unwrap();
+        sink.remove("power").await.unwrap();
+        assert_eq!(online_metric(sink.name()), 1);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{
+    awtrix3::{dto::*, topics::*},
+    display::{DisplaySink, Screen},
+    metrics::record_publish_failure,
+    schedule::DisplayState,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use energy_monitor_lib::{
+    mqtt::MqttClient,
+    topic::{Delivery, Encode},
+};
+use std::time::Duration;
+
+/// Awtrix clock receiving its apps through the MQTT broker
+pub struct AwtrixMqttSink {
+    client: MqttClient,
+    prefix: String,
+    name: String,
+}
+
+impl AwtrixMqttSink {
+    pub fn new(client: MqttClient, prefix: &str) -> Self {
+        Self {
+            client,
+            prefix: prefix.to_string(),
+            name: format!("mqtt:{prefix}"),
+        }
+    }
+
+    async fn publish(&self, topic: &str, delivery: Delivery, payload: Bytes) -> Result<()> {
+        self.client
+            .publish(topic, delivery, payload)
+            .await
+            .inspect_err(|_| record_publish_failure(topic))?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DisplaySink for AwtrixMqttSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// With MQTT 5 the broker drops updates older than the lifetime of the app
+    async fn show(&self, screen: &Screen) -> Result<()> {
+        let app = CustomApplication::from(screen);
+        let delivery = Delivery {
+            expiry: screen
+                .life_time
+                .and_then(|seconds| u64::try_from(seconds).ok())
+                .map(Duration::from_secs),
+            ..Delivery::STREAM
+        };
+        self.publish(
+            &custom_app_topic(&self.prefix, &screen.name),
+            delivery,
+            CustomApplication::encode(&app),
+        )
+        .await
+    }
+
+    async fn notify(&self, screen: &Screen) -> Result<()> {
+        let app = CustomApplication::from(screen);
+        self.publish(
+            &notify_topic(&self.prefix),
+            Delivery::STREAM,
+            CustomApplication::encode(&app),
+        )
+        .await
+    }
+
+    async fn remove(&self, name: &str) -> Result<()> {
+        // An empty payload removes the app
+        self.publish(
+            &custom_app_topic(&self.prefix, name),
+            Delivery::STREAM,
+            Bytes::new(),
+        )
+        .await
+    }
+
+    /// Settings and power are retained, so a restarted clock picks them up
+    async fn apply(&self, state: &DisplayState) -> Result<()> {
+        let settings = Settings::from(state);
+        self.publish(
+            &settings_topic(&self.prefix),
+            Delivery::STATE,
+            Settings::encode(&settings),
+        )
+        .await?;
+        let power = Power { power: state.power };
+        self.publish(
+            &power_topic(&self.prefix),
+            Delivery::STATE,
+            Power::encode(&power),
+        )
+        .await
+    }
+}
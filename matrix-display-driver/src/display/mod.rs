@@ -0,0 +1,121 @@
+use crate::{awtrix3::Color, schedule::DisplayState};
+use anyhow::Result;
+use async_trait::async_trait;
+use energy_monitor_lib::mqtt::MqttClient;
+use log::error;
+use serde::Deserialize;
+
+pub mod awtrix_http;
+pub mod awtrix_mqtt;
+pub mod terminal;
+
+/// A rendered app, independent of the display it is shown on
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Screen {
+    /// Identifies the screen on the display, an update replaces the screen with the same name
+    pub name: String,
+    pub text: String,
+    pub icon: Option<String>,
+    pub color: Option<Color>,
+    pub gradient: Option<Vec<Color>>,
+    pub background: Option<Color>,
+    /// Progress bar from 0 to 100
+    pub progress: Option<i32>,
+    pub progress_color: Option<Color>,
+    pub progress_background: Option<Color>,
+    /// Seconds the screen is shown
+    pub duration: Option<i32>,
+    /// Seconds after which the screen is removed if no update was received
+    pub life_time: Option<i32>,
+}
+
+#[async_trait]
+pub trait DisplaySink: Send + Sync {
+    /// Used in log messages
+    fn name(&self) -> &str;
+
+    /// Shows the screen or replaces the screen with the same name
+    async fn show(&self, screen: &Screen) -> Result<()>;
+
+    /// Shows the screen once, in front of the other screens
+    async fn notify(&self, screen: &Screen) -> Result<()>;
+
+    /// Removes the screen with the name
+    async fn remove(&self, name: &str) -> Result<()>;
+
+    /// Applies brightness and power
+    async fn apply(&self, state: &DisplayState) -> Result<()>;
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum DisplayConfig {
+    /// Awtrix clock subscribed to `<prefix>/custom/<name>`, `<prefix>/settings` and `<prefix>/power`
+    Mqtt { prefix: String },
+    /// Awtrix clock reached through its HTTP API, e.g. `http://192.168.1.50`
+    Http { url: String },
+    /// Prints the screens to stdout, for development
+    Terminal,
+}
+
+pub fn default_displays() -> Vec<DisplayConfig> {
+    vec![DisplayConfig::Mqtt {
+        prefix: crate::awtrix3::topics::MATRIX_DISPLAY_TOPIC_PREFIX.to_string(),
+    }]
+}
+
+/// All configured displays, errors of one display don't affect the others
+pub struct Displays(Vec<Box<dyn DisplaySink>>);
+
+impl Displays {
+    pub fn new(configs: &[DisplayConfig], client: &MqttClient) -> Result<Self> {
+        let mut sinks: Vec<Box<dyn DisplaySink>> = Vec::new();
+        for config in configs {
+            sinks.push(match config {
+                DisplayConfig::Mqtt { prefix } => {
+                    Box::new(awtrix_mqtt::AwtrixMqttSink::new(client.clone(), prefix))
+                }
+                DisplayConfig::Http { url } => Box::new(awtrix_http::AwtrixHttpSink::new(url)?),
+                DisplayConfig::Terminal => Box::new(terminal::TerminalSink),
+            });
+        }
+        Ok(Self(sinks))
+    }
+
+    pub async fn show(&self, screen: &Screen) {
+        for sink in &self.0 {
+            if let Err(e) = sink.show(screen).await {
+                error!("Error showing {} on {}: {:?}", screen.name, sink.name(), e);
+            }
+        }
+    }
+
+    pub async fn notify(&self, screen: &Screen) {
+        for sink in &self.0 {
+            if let Err(e) = sink.notify(screen).await {
+                error!(
+                    "Error notifying {} on {}: {:?}",
+                    screen.name,
+                    sink.name(),
+                    e
+                );
+            }
+        }
+    }
+
+    pub async fn remove(&self, name: &str) {
+        for sink in &self.0 {
+            if let Err(e) = sink.remove(name).await {
+                error!("Error removing {} from {}: {:?}", name, sink.name(), e);
+            }
+        }
+    }
+
+    pub async fn apply(&self, state: &DisplayState) {
+        for sink in &self.0 {
+            if let Err(e) = sink.apply(state).await {
+                error!("Error applying {:?} to {}: {:?}", state, sink.name(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{
+    awtrix3::Color,
+    display::{DisplaySink, Screen},
+    schedule::DisplayState,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+
+const PROGRESS_WIDTH: usize = 20;
+const RESET: &str = "\x1b[0m";
+
+/// Prints every screen as a line with ANSI colors, useful during development
+pub struct TerminalSink;
+
+fn foreground(color: Color) -> String {
+    format!("\x1b[38;2;{};{};{}m", color.r, color.g, color.b)
+}
+
+fn background(color: Color) -> String {
+    format!("\x1b[48;2;{};{};{}m", color.r, color.g, color.b)
+}
+
+/// Renders the screen as `name [icon] text |progress|`
+pub fn render(screen: &Screen) -> String {
+    let mut line = format!("{:<12}", screen.name);
+    if let Some(icon) = &screen.icon {
+        line.push_str(&format!(" [{icon}]"));
+    }
+
+    line.push(' ');
+    if let Some(color) = screen.background {
+        line.push_str(&background(color));
+    }
+    match screen.color {
+        Some(color) => line.push_str(&format!("{}{}{RESET}", foreground(color), screen.text)),
+        None => line.push_str(&format!("{}{RESET}", screen.text)),
+    }
+
+    if let Some(progress) = screen.progress {
+        let filled = (progress.clamp(0, 100) as usize * PROGRESS_WIDTH).div_ceil(100);
+        let bar_color = screen
+            .progress_color
+            .unwrap_or(Color::new(0x00, 0xFF, 0x00));
+        let empty_color = screen
+            .progress_background
+            .unwrap_or(Color::new(0x80, 0x80, 0x80));
+        line.push_str(&format!(
+            " |{}{}{}{}{RESET}|",
+            foreground(bar_color),
+            "█".repeat(filled),
+            foreground(empty_color),
+            "░".repeat(PROGRESS_WIDTH - filled),
+        ));
+    }
+    line
+}
+
+#[async_trait]
+impl DisplaySink for TerminalSink {
+    fn name(&self) -> &str {
+        "terminal"
+    }
+
+    async fn show(&self, screen: &Screen) -> Result<()> {
+        println!("{}", render(screen));
+        Ok(())
+    }
+
+    async fn notify(&self, screen: &Screen) -> Result<()> {
+        println!("{} !", render(screen));
+        Ok(())
+    }
+
+    async fn remove(&self, name: &str) -> Result<()> {
+        println!("{name:<12} removed");
+        Ok(())
+    }
+
+    async fn apply(&self, state: &DisplayState) -> Result<()> {
+        println!(
+            "display      power {}, brightness {}",
+            if state.power { "on" } else { "off" },
+            state
+                .brightness
+                .map_or("auto".to_string(), |brightness| brightness.to_string())
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let screen = Screen {
+            name: "power".to_string(),
+            text: "523".to_string(),
+            icon: Some("37515".to_string()),
+            color: Some(Color::new(0xFF, 0xD7, 0x00)),
+            progress: Some(50),
+            ..Default::default()
+        };
+        let line = render(&screen);
+        assert!(line.starts_with("power        [37515] \x1b[38;2;255;215;0m523\x1b[0m |"));
+        assert_eq!(line.matches('█').count(), 10);
+        assert_eq!(line.matches('░').count(), 10);
+    }
+}
@@ -1,30 +1,49 @@
 use anyhow::{Context, Result};
-use awtrix3::{dto::*, topics::*};
+use config::Config;
+use display::Displays;
 use energy_monitor_lib::{
+    mqtt::{self, MqttConfig, Supervisor},
     opendtu::topics::{OPEN_DTU_AC_POWER_TOPIC, OPEN_DTU_AC_YIELD_DAY_TOPIC},
     pulse::topics::PULSE_CONSUMPTION_TOPIC,
-    tibber::{
-        dto::{PriceInformation, PriceLevel},
-        topics::TIBBER_PRICE_INFORMATION_TOPIC,
-    },
+    service::{self, Daemon},
+    tibber::topics::TIBBER_PRICE_INFORMATION_TOPIC,
 };
 use log::{debug, error, info};
-use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Publish, QoS};
-use std::time::Duration;
+use metrics::METRICS;
+use relay::Relay;
+use rumqttc::{Event, Packet, Publish, QoS};
+use std::{net::SocketAddr, time::Duration};
 use syslog::{Facility, Formatter3164};
-use tokio::{sync::mpsc, time::sleep};
+use tokio::{
+    sync::mpsc,
+    time::{interval, timeout},
+};
+use tokio_util::sync::CancellationToken;
+mod availability;
 mod awtrix3;
+mod config;
+mod display;
+mod metrics;
+mod relay;
+mod schedule;
+mod sun;
+mod templates;
+mod views;
 
 const MQTT_CLIENT_NAME: &str = "matrix-display-updater";
 const MQTT_BROKER_ADDRESS: &str = "rpiserver";
 const MQTT_BROKER_PORT: u16 = 1883;
+const METRICS_ADDRESS_ENV: &str = "EMDISPLAYD_METRICS_ADDRESS";
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(60);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+const DAEMON_NAME: &str = "emdisplayd";
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let formatter = Formatter3164 {
         facility: Facility::LOG_DAEMON,
         hostname: None,
-        process: "emdisplayd".into(),
+        process: DAEMON_NAME.into(),
         pid: 0,
     };
 
@@ -36,203 +55,148 @@ async fn main() -> Result<()> {
         env!("CARGO_PKG_VERSION")
     );
 
-    let mut mqttoptions = MqttOptions::new(MQTT_CLIENT_NAME, MQTT_BROKER_ADDRESS, MQTT_BROKER_PORT);
-    mqttoptions.set_keep_alive(Duration::from_secs(5));
+    // The metrics endpoint is only started if an address is configured
+    if let Ok(address) = std::env::var(METRICS_ADDRESS_ENV) {
+        let address: SocketAddr = address
+            .parse()
+            .with_context(|| format!("{METRICS_ADDRESS_ENV} is invalid"))?;
+        info!("Serving metrics on http://{address}/metrics");
+        tokio::spawn(async move {
+            if let Err(e) =
+                energy_monitor_lib::metrics::serve(address, METRICS.common.clone()).await
+            {
+                error!("Metrics endpoint failed: {:?}", e);
+            }
+        });
+    }
+
+    // The broker, credentials and TLS can be changed through EMDISPLAYD_MQTT_*
+    let mut mqtt_config = MqttConfig::new(MQTT_CLIENT_NAME, MQTT_BROKER_ADDRESS, MQTT_BROKER_PORT);
+    mqtt_config.keep_alive = Duration::from_secs(5);
+    mqtt_config.load_env("EMDISPLAYD")?;
+    let daemon = Daemon::new(DAEMON_NAME, env!("CARGO_PKG_VERSION"));
+    let (client, supervisor) = mqtt::connect(&mqtt_config, &daemon)?;
 
-    let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
+    let config = Config::load()?;
+    let displays = Displays::new(&config.displays, &client)?;
+    let relay = Relay::new(config, displays);
 
-    for topic in [
-        OPEN_DTU_AC_POWER_TOPIC.name(),
-        OPEN_DTU_AC_YIELD_DAY_TOPIC.name(),
-        PULSE_CONSUMPTION_TOPIC.name(),
-        TIBBER_PRICE_INFORMATION_TOPIC.name(),
-    ] {
+    for topic in relay.topics() {
         client.subscribe(topic, QoS::AtMostOnce).await?;
     }
 
-    let (mut tx, mut rx) = mpsc::channel(10);
-
-    tokio::spawn(async move {
-        if let Err(e) = process_event_loop(&mut eventloop, &mut tx).await {
-            error!("Error processing event loop = {:?}", e);
-            std::process::exit(1);
-        }
+    let token = CancellationToken::new();
+    let (tx, rx) = mpsc::channel(10);
+    let event_loop = tokio::spawn(process_event_loop(supervisor, tx));
+    let messages = tokio::spawn(handle_messages(relay, rx, token.clone()));
+    // Stalled message handling blocks the event loop and stops the pings
+    let watchdog_client = client.clone();
+    service::spawn_watchdog(token.clone(), move |period| {
+        watchdog_client.is_active(period)
     });
 
-    // Clone the client to use in the publishing task
-    let publish_client = client.clone();
-    tokio::spawn(async move {
-        if let Err(e) = handle_messages(publish_client, &mut rx).await {
-            error!("Error handling messages = {:?}", e);
-            std::process::exit(1);
+    tokio::select! {
+        result = service::shutdown_signal() => {
+            result?;
+            info!("Shutting down");
         }
-    });
+        _ = token.cancelled() => {}
+    }
+    service::notify("STOPPING=1");
+    token.cancel();
 
-    loop {
-        sleep(Duration::from_secs(1)).await;
+    // The apps are removed before going offline
+    messages.await?;
+    if let Err(e) = client.disconnect().await {
+        error!("Failed to disconnect: {:?}", e);
     }
+    if timeout(SHUTDOWN_TIMEOUT, event_loop).await.is_err() {
+        error!("Timeout flushing the MQTT messages");
+    }
+    Ok(())
 }
 
-async fn process_event_loop(
-    eventloop: &mut EventLoop,
-    tx: &mut mpsc::Sender<Event>,
-) -> Result<(), anyhow::Error> {
-    loop {
-        match eventloop
-            .poll()
-            .await
-            .context("Error polling notfication from mqtt event loop")
-        {
-            Ok(notification) => {
-                tx.send(notification)
-                    .await
-                    .context("Error sending message on channel")?;
-            }
-            Err(e) => {
-                error!("Error polling event loop = {:?}", e);
-                return Err(e);
-            }
-        }
+/// Forwards the MQTT events until the client disconnected, the supervisor
+/// reconnects if the broker goes away
+async fn process_event_loop(mut supervisor: Supervisor, tx: mpsc::Sender<Event>) {
+    while let Some(notification) = supervisor.poll().await {
+        // Once shutting down the messages are not handled anymore, but the
+        // loop keeps running to send the remaining ones
+        let _ = tx.send(notification).await;
     }
 }
 
+/// Relays the messages until shutdown and removes the apps from the displays
 async fn handle_messages(
-    client: AsyncClient,
+    mut relay: Relay,
+    mut rx: mpsc::Receiver<Event>,
+    token: CancellationToken,
+) {
+    relay_messages(&mut relay, &mut rx, &token).await;
+    token.cancel();
+    rx.close();
+    relay.clear().await;
+}
+
+async fn relay_messages(
+    relay: &mut Relay,
     rx: &mut mpsc::Receiver<Event>,
-) -> Result<(), anyhow::Error> {
-    while let Some(notification) = rx.recv().await {
-        debug!("Received = {:?}", notification);
-        if let rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish)) = notification {
-            match publish.topic.as_str() {
-                topic if topic == OPEN_DTU_AC_YIELD_DAY_TOPIC.name() => {
-                    publish_yield_day(&client, &publish)
-                        .await
-                        .context("Error publishing yield day")?;
-                }
-                topic if topic == OPEN_DTU_AC_POWER_TOPIC.name() => {
-                    publish_current_production(&client, &publish)
-                        .await
-                        .context("Error publishing current production")?;
-                }
-                topic if topic == PULSE_CONSUMPTION_TOPIC.name() => {
-                    publish_current_consumption(&client, &publish)
-                        .await
-                        .context("Error publishing current consumption")?;
-                }
-                topic if topic == TIBBER_PRICE_INFORMATION_TOPIC.name() => {
-                    publish_current_price(&client, &publish)
-                        .await
-                        .context("Error publishing current price")?;
+    token: &CancellationToken,
+) {
+    let mut schedule_interval = interval(SCHEDULE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            notification = rx.recv() => {
+                let Some(notification) = notification else {
+                    break;
+                };
+                debug!("Received = {:?}", notification);
+                match notification {
+                    Event::Incoming(Packet::Publish(publish)) => {
+                        update_metrics(&publish);
+                        relay.handle_publish(&publish).await;
+                    }
+                    Event::Incoming(Packet::ConnAck(_)) => {
+                        service::notify("READY=1");
+                        relay.reconnected().await;
+                    }
+                    _ => {}
                 }
-                _ => {}
             }
+            _ = schedule_interval.tick() => {}
+            _ = token.cancelled() => break,
         }
-    }
-    Ok(())
-}
-
-async fn publish_yield_day(client: &AsyncClient, publish: &Publish) -> Result<(), anyhow::Error> {
-    let yield_day = OPEN_DTU_AC_YIELD_DAY_TOPIC.decode(&publish.payload)?;
-
-    info!("yield today: {}W", yield_day);
-    client
-        .publish(
-            MATRIX_DISPLAY_APP_YIELD_DAY_TOPIC.name(),
-            QoS::AtMostOnce,
-            false,
-            MATRIX_DISPLAY_APP_YIELD_DAY_TOPIC.encode(&CustomApplication {
-                text: yield_day.to_string(),
-                duration: Some(5),
-                icon: Some(52455.to_string()),
-                ..Default::default()
-            }),
-        )
-        .await?;
-    Ok(())
-}
-
-async fn publish_current_production(
-    client: &AsyncClient,
-    publish: &Publish,
-) -> Result<(), anyhow::Error> {
-    let current_power = OPEN_DTU_AC_POWER_TOPIC.decode(&publish.payload)?;
-
-    info!("Current production: {:0.0}W", current_power);
-    client
-        .publish(
-            MATRIX_DISPLAY_APP_CURRENT_PRODUCTION_TOPIC.name(),
-            QoS::AtMostOnce,
-            false,
-            MATRIX_DISPLAY_APP_CURRENT_PRODUCTION_TOPIC.encode(&CustomApplication {
-                text: format!("{:0.0}", current_power),
-                duration: Some(5),
-                icon: Some(37515.to_string()),
-                ..Default::default()
-            }),
-        )
-        .await?;
-    Ok(())
-}
-
-async fn publish_current_consumption(
-    client: &AsyncClient,
-    publish: &Publish,
-) -> Result<(), anyhow::Error> {
-    let consumption = PULSE_CONSUMPTION_TOPIC
-        .decode(&publish.payload)?
-        .consumption;
-
-    info!("Current consumption: {}W", consumption);
-    client
-        .publish(
-            MATRIX_DISPLAY_APP_CURRENT_CONSUMPTION_TOPIC.name(),
-            QoS::AtMostOnce,
-            false,
-            MATRIX_DISPLAY_APP_CURRENT_CONSUMPTION_TOPIC.encode(&CustomApplication {
-                text: format!("{:0.1}", consumption as f32 / 1000.0),
-                duration: Some(5),
-                icon: Some(55888.to_string()),
-                life_time: Some(10), // if no update within 10 seconds remove
-                ..Default::default()
-            }),
-        )
-        .await?;
-    Ok(())
-}
 
-async fn publish_current_price(
-    client: &AsyncClient,
-    publish: &Publish,
-) -> Result<(), anyhow::Error> {
-    let price_information: PriceInformation =
-        TIBBER_PRICE_INFORMATION_TOPIC.decode(&publish.payload)?;
-
-    info!("Current price: {} Euro", price_information.total);
-    client
-        .publish(
-            MATRIX_DISPLAY_APP_CURRENT_PRICE_TOPIC.name(),
-            QoS::AtMostOnce,
-            false,
-            MATRIX_DISPLAY_APP_CURRENT_PRICE_TOPIC.encode(&CustomApplication {
-                text: format!("{:0.2}", price_information.total),
-                duration: Some(2),
-                icon: Some(54231.to_string()),
-                color: Some(color_from_price_level(price_information.level).to_string()),
-                life_time: Some(60 * 62), // 1 hour and 2 minutes to make sure the price is updated
-                ..Default::default()
-            }),
-        )
-        .await?;
-    Ok(())
+        relay.apply_schedule().await;
+    }
 }
 
-fn color_from_price_level(level: PriceLevel) -> &'static str {
-    match level {
-        PriceLevel::Cheap => "#66FF00",
-        PriceLevel::Expensive => "#FF0800",
-        PriceLevel::Normal => "#ED872D",
-        PriceLevel::VeryCheap => "#66FF00",
-        PriceLevel::VeryExpensive => "#FF0800",
-        PriceLevel::None => "#FF00FF",
+/// Keeps the gauges of the values we know about up to date
+fn update_metrics(publish: &Publish) {
+    let metrics = &METRICS.common;
+    match publish.topic.as_str() {
+        topic if topic == OPEN_DTU_AC_YIELD_DAY_TOPIC.name() => {
+            if let Ok(yield_day) = OPEN_DTU_AC_YIELD_DAY_TOPIC.decode(&publish.payload) {
+                metrics.yield_day.set(yield_day as f64);
+            }
+        }
+        topic if topic == OPEN_DTU_AC_POWER_TOPIC.name() => {
+            if let Ok(power) = OPEN_DTU_AC_POWER_TOPIC.decode(&publish.payload) {
+                metrics.production.set(power as f64);
+            }
+        }
+        topic if topic == PULSE_CONSUMPTION_TOPIC.name() => {
+            if let Ok(consumption) = PULSE_CONSUMPTION_TOPIC.decode(&publish.payload) {
+                metrics.consumption.set(consumption.consumption as i64);
+            }
+        }
+        topic if topic == TIBBER_PRICE_INFORMATION_TOPIC.name() => {
+            if let Ok(price_information) = TIBBER_PRICE_INFORMATION_TOPIC.decode(&publish.payload) {
+                metrics.price_total.set(price_information.total.to_f64());
+                metrics.set_price_level(&price_information.level);
+            }
+        }
+        _ => {}
     }
 }
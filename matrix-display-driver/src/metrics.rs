@@ -0,0 +1,47 @@
+use energy_monitor_lib::metrics::Metrics;
+use prometheus::{IntCounterVec, IntGaugeVec, Opts};
+use std::sync::LazyLock;
+
+pub struct DisplayMetrics {
+    pub common: Metrics,
+    /// Messages turned into a display app by source topic
+    pub messages_relayed: IntCounterVec,
+    /// 1 if a display driven through HTTP is reachable
+    pub display_online: IntGaugeVec,
+}
+
+pub static METRICS: LazyLock<DisplayMetrics> = LazyLock::new(|| {
+    let common = Metrics::new("emdisplayd");
+    let metrics = DisplayMetrics {
+        messages_relayed: IntCounterVec::new(
+            Opts::new(
+                "mqtt_messages_relayed_total",
+                "Messages relayed to the display",
+            ),
+            &["topic"],
+        )
+        .unwrap(),
+        display_online: IntGaugeVec::new(
+            Opts::new("display_online", "Whether the display is reachable"),
+            &["display"],
+        )
+        .unwrap(),
+        common,
+    };
+
+    metrics
+        .common
+        .register(Box::new(metrics.messages_relayed.clone()));
+    metrics
+        .common
+        .register(Box::new(metrics.display_online.clone()));
+    metrics
+});
+
+pub fn record_publish_failure(topic: &str) {
+    METRICS
+        .common
+        .mqtt_publish_failures
+        .with_label_values(&[topic])
+        .inc();
+}
@@ -0,0 +1,231 @@
+use crate::{
+    availability::AvailabilityConfig,
+    awtrix3::{dto::*, topics::*},
+    config::Config,
+    display::{DisplayConfig, Displays},
+    metrics::METRICS,
+    schedule::{parse_presence, Scheduler},
+    templates::AppTemplate,
+    views::Views,
+};
+use bytes::Bytes;
+use chrono::Local;
+use energy_monitor_lib::{service::Availability, topic::Decode};
+use log::{debug, error, info};
+use rumqttc::Publish;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Prefixes of the clocks connected through MQTT
+fn prefixes(displays: &[DisplayConfig]) -> impl Iterator<Item = &str> {
+    displays.iter().filter_map(|display| match display {
+        DisplayConfig::Mqtt { prefix } => Some(prefix.as_str()),
+        _ => None,
+    })
+}
+
+/// Turns the messages of the source topics into screens on the displays
+pub struct Relay {
+    config: Config,
+    displays: Displays,
+    scheduler: Option<Scheduler>,
+    views: Views,
+    /// Last payload of every source topic, used to show apps again when
+    /// they become visible or after reconnecting to the broker
+    last_values: BTreeMap<String, Bytes>,
+}
+
+impl Relay {
+    pub fn new(config: Config, displays: Displays) -> Self {
+        Self {
+            scheduler: config.schedule.clone().map(Scheduler::new),
+            views: Views::new(&config.apps),
+            displays,
+            config,
+            last_values: BTreeMap::new(),
+        }
+    }
+
+    /// Source topics of the apps, the presence topic, the status of the
+    /// watched daemons and the stats and buttons of the clocks
+    pub fn topics(&self) -> BTreeSet<String> {
+        let mut topics: BTreeSet<String> = self
+            .config
+            .apps
+            .iter()
+            .map(|template| template.source_topic.clone())
+            .collect();
+        topics.extend(self.config.availability.topics());
+        if let Some(topic) = self.scheduler.as_ref().and_then(Scheduler::presence_topic) {
+            topics.insert(topic.to_string());
+        }
+        for prefix in prefixes(&self.config.displays) {
+            topics.insert(stats_topic(prefix));
+            for button in Button::ALL {
+                topics.insert(button_topic(prefix, button));
+            }
+        }
+        topics
+    }
+
+    /// Malformed payloads are logged and dropped, they must not stop the
+    /// other apps from being shown
+    pub async fn handle_publish(&mut self, publish: &Publish) {
+        if let Some(scheduler) = self.scheduler.as_mut() {
+            if scheduler.presence_topic() == Some(publish.topic.as_str()) {
+                scheduler.set_presence(parse_presence(&publish.payload));
+            }
+        }
+
+        if let Some(prefix) =
+            prefixes(&self.config.displays).find(|prefix| stats_topic(prefix) == publish.topic)
+        {
+            match Stats::decode(&publish.payload) {
+                Ok(stats) => {
+                    debug!("Stats of {prefix}: {:?}", stats);
+                    if let Some((scheduler, lux)) = self.scheduler.as_mut().zip(stats.lux) {
+                        scheduler.set_lux(lux);
+                    }
+                }
+                Err(e) => error!("Invalid stats from {prefix}: {:?}", e),
+            }
+            return;
+        }
+
+        if let Some(daemon) = self.config.availability.daemon(&publish.topic) {
+            let state = Availability::from_payload(&publish.payload);
+            self.show_availability(daemon, state).await;
+            return;
+        }
+
+        let event = prefixes(&self.config.displays)
+            .find_map(|prefix| parse_button_event(prefix, &publish.topic, &publish.payload));
+        if let Some(event) = event {
+            if event.pressed {
+                self.press(event.button).await;
+            }
+            return;
+        }
+
+        self.last_values
+            .insert(publish.topic.clone(), publish.payload.clone());
+
+        let mut relayed = false;
+        for template in self
+            .config
+            .apps
+            .iter()
+            .filter(|template| template.source_topic == publish.topic)
+            .filter(|template| self.is_visible(template))
+        {
+            relayed |= self.show(template, &publish.payload).await;
+        }
+
+        if relayed {
+            METRICS
+                .messages_relayed
+                .with_label_values(&[&publish.topic])
+                .inc();
+        }
+    }
+
+    /// Shows all apps with their last values and sends the display state
+    /// again, the clocks may have restarted while we were disconnected
+    pub async fn reconnected(&mut self) {
+        if let Some(scheduler) = self.scheduler.as_mut() {
+            scheduler.resend();
+        }
+        self.apply_schedule().await;
+        self.refresh(self.config.apps.iter()).await;
+    }
+
+    /// Sends brightness and power to the displays when the schedule changes
+    /// them and removes the daylight only apps after sunset
+    pub async fn apply_schedule(&mut self) {
+        let Some(state) = self
+            .scheduler
+            .as_mut()
+            .and_then(|scheduler| scheduler.update(&Local::now()))
+        else {
+            return;
+        };
+        info!("Display state changed to {:?}", state);
+
+        self.displays.apply(&state).await;
+        let apps = self.config.apps.iter().filter(|app| app.daylight_only);
+        self.refresh(apps).await;
+    }
+
+    /// Cycles the views, the apps of the previous view are removed and the
+    /// apps of the new one are shown with the last values received
+    async fn press(&mut self, button: Button) {
+        if !self.views.press(button) {
+            return;
+        }
+        info!("Showing view {}", self.views.active().unwrap_or_default());
+
+        let apps = self.config.apps.iter().filter(|app| app.view.is_some());
+        self.refresh(apps).await;
+    }
+
+    /// Shows the visible apps with their last value and removes the hidden ones
+    async fn refresh<'a>(&self, apps: impl Iterator<Item = &'a AppTemplate>) {
+        for template in apps.filter(|template| !template.notify) {
+            if !self.is_visible(template) {
+                self.displays.remove(&template.name).await;
+            } else if let Some(payload) = self.last_values.get(&template.source_topic) {
+                self.show(template, payload).await;
+            }
+        }
+    }
+
+    /// Shows a warning while the daemon is offline
+    async fn show_availability(&self, daemon: &str, state: Availability) {
+        info!("{daemon} is {:?}", state);
+        match self.config.availability.render(daemon, state) {
+            Some(screen) => self.displays.show(&screen).await,
+            None => {
+                let name = AvailabilityConfig::screen_name(daemon);
+                self.displays.remove(&name).await;
+            }
+        }
+    }
+
+    /// Removes the apps from the displays, the clocks would otherwise keep
+    /// showing the last values after the daemon stopped
+    pub async fn clear(&self) {
+        for template in self.config.apps.iter().filter(|template| !template.notify) {
+            self.displays.remove(&template.name).await;
+        }
+        for daemon in &self.config.availability.daemons {
+            let name = AvailabilityConfig::screen_name(daemon);
+            self.displays.remove(&name).await;
+        }
+    }
+
+    fn is_visible(&self, template: &AppTemplate) -> bool {
+        let daylight = self
+            .scheduler
+            .as_ref()
+            .is_none_or(|scheduler| scheduler.state().daylight);
+        (daylight || !template.daylight_only) && self.views.is_visible(template)
+    }
+
+    /// Returns false if the payload can not be rendered
+    async fn show(&self, template: &AppTemplate, payload: &[u8]) -> bool {
+        let screen = match template.render(payload) {
+            Ok(screen) => screen,
+            Err(e) => {
+                error!("Error rendering app {}: {:?}", template.name, e);
+                return false;
+            }
+        };
+
+        info!("{}: {}", template.name, screen.text);
+        if template.notify {
+            self.displays.notify(&screen).await;
+        } else {
+            self.displays.show(&screen).await;
+        }
+        true
+    }
+}
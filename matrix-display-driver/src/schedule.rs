@@ -0,0 +1,224 @@
+use crate::sun;
+use chrono::{DateTime, NaiveTime, TimeZone};
+use serde::{de, Deserialize, Deserializer};
+
+/// When the display is dimmed, turned off and which apps are shown
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Location used to calculate sunrise and sunset
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Local time the night starts, e.g. "22:30". Without `night_start` and
+    /// `night_end` the night lasts from sunset to sunrise.
+    #[serde(default, deserialize_with = "deserialize_time")]
+    pub night_start: Option<NaiveTime>,
+    #[serde(default, deserialize_with = "deserialize_time")]
+    pub night_end: Option<NaiveTime>,
+    /// Brightness (0-255) during the day, the automatic brightness of the clock if not set
+    pub day_brightness: Option<u8>,
+    /// Brightness during the day following the lux reported by the clock,
+    /// takes precedence over `day_brightness` once a reading was received
+    pub lux_brightness: Option<LuxBrightness>,
+    /// Brightness (0-255) at night, 0 turns the display off
+    #[serde(default)]
+    pub night_brightness: u8,
+    /// Topic telling whether someone is home. The display stays off until a
+    /// message on this topic says so.
+    pub presence_topic: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LuxBrightness {
+    /// At or below this lux `min` is used
+    pub dark: f64,
+    /// At or above this lux `max` is used
+    pub bright: f64,
+    pub min: u8,
+    pub max: u8,
+}
+
+/// Number of brightness levels between `min` and `max`, fewer levels avoid
+/// changing the brightness on every small change of the light
+const LUX_BRIGHTNESS_STEPS: f64 = 10.0;
+
+impl LuxBrightness {
+    pub fn brightness(&self, lux: f64) -> u8 {
+        let fraction = ((lux - self.dark) / (self.bright - self.dark)).clamp(0.0, 1.0);
+        let fraction = (fraction * LUX_BRIGHTNESS_STEPS).round() / LUX_BRIGHTNESS_STEPS;
+        (self.min as f64 + (self.max as f64 - self.min as f64) * fraction).round() as u8
+    }
+}
+
+fn deserialize_time<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<NaiveTime>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&text, "%H:%M")
+        .map(Some)
+        .map_err(|_| de::Error::custom(format!("Invalid time {text}, expected HH:MM")))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayState {
+    pub power: bool,
+    /// `None` selects the automatic brightness
+    pub brightness: Option<u8>,
+    /// The sun is up, PV apps are shown
+    pub daylight: bool,
+}
+
+impl Default for DisplayState {
+    /// The state of the display if no schedule is configured
+    fn default() -> Self {
+        Self {
+            power: true,
+            brightness: None,
+            daylight: true,
+        }
+    }
+}
+
+pub struct Scheduler {
+    config: ScheduleConfig,
+    present: bool,
+    lux: Option<f64>,
+    state: Option<DisplayState>,
+}
+
+impl Scheduler {
+    pub fn new(config: ScheduleConfig) -> Self {
+        Self {
+            present: config.presence_topic.is_none(),
+            config,
+            lux: None,
+            state: None,
+        }
+    }
+
+    pub fn presence_topic(&self) -> Option<&str> {
+        self.config.presence_topic.as_deref()
+    }
+
+    pub fn set_presence(&mut self, present: bool) {
+        self.present = present;
+    }
+
+    pub fn set_lux(&mut self, lux: f64) {
+        self.lux = Some(lux);
+    }
+
+    pub fn state_at<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> DisplayState {
+        let daylight = sun::daylight(
+            now.date_naive(),
+            self.config.latitude,
+            self.config.longitude,
+        )
+        .contains(now.to_utc());
+
+        let night = match (self.config.night_start, self.config.night_end) {
+            (Some(start), Some(end)) => {
+                let time = now.time();
+                if start <= end {
+                    start <= time && time < end
+                } else {
+                    // The night spans midnight
+                    start <= time || time < end
+                }
+            }
+            _ => !daylight,
+        };
+
+        let brightness = if night {
+            Some(self.config.night_brightness)
+        } else {
+            self.config
+                .lux_brightness
+                .as_ref()
+                .zip(self.lux)
+                .map(|(lux_brightness, lux)| lux_brightness.brightness(lux))
+                .or(self.config.day_brightness)
+        };
+
+        DisplayState {
+            power: self.present && brightness != Some(0),
+            brightness,
+            daylight,
+        }
+    }
+
+    /// Returns the new state if it differs from the previous one
+    pub fn update<Tz: TimeZone>(&mut self, now: &DateTime<Tz>) -> Option<DisplayState> {
+        let state = self.state_at(now);
+        if self.state == Some(state) {
+            return None;
+        }
+        self.state = Some(state);
+        Some(state)
+    }
+
+    /// The next `update` returns the current state even if it did not change
+    pub fn resend(&mut self) {
+        self.state = None;
+    }
+
+    /// The last state returned by `update`
+    pub fn state(&self) -> DisplayState {
+        self.state.unwrap_or_default()
+    }
+}
+
+/// Presence payloads like `true`, `1`, `on` or `home` mean someone is home
+pub fn parse_presence(payload: &[u8]) -> bool {
+    let payload = String::from_utf8_lossy(payload);
+    let payload = payload.trim().trim_matches('"');
+    ["1", "true", "on", "home"]
+        .iter()
+        .any(|value| payload.eq_ignore_ascii_case(value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::FixedOffset;
+
+    #[test]
+    fn test_scheduler() {
+        let config: ScheduleConfig = toml::from_str(
+            r#"
+            latitude = 52.52
+            longitude = 13.405
+            night_start = "22:30"
+            night_end = "06:00"
+            night_brightness = 0
+            presence_topic = "home/presence"
+            "#,
+        )
+        .unwrap();
+        let mut scheduler = Scheduler::new(config);
+
+        let berlin = FixedOffset::east_opt(2 * 3600).unwrap();
+        let noon = berlin.with_ymd_and_hms(2024, 6, 21, 12, 0, 0).unwrap();
+        assert!(!scheduler.update(&noon).unwrap().power);
+        assert_eq!(scheduler.update(&noon), None);
+
+        assert!(parse_presence(b"home"));
+        scheduler.set_presence(true);
+        let state = scheduler.update(&noon).unwrap();
+        assert!(state.power && state.brightness.is_none());
+
+        scheduler.config.lux_brightness = Some(LuxBrightness {
+            dark: 0.0,
+            bright: 400.0,
+            min: 10,
+            max: 210,
+        });
+        scheduler.set_lux(190.0);
+        assert_eq!(scheduler.state_at(&noon).brightness, Some(110));
+
+        let night = berlin.with_ymd_and_hms(2024, 6, 21, 23, 0, 0).unwrap();
+        let state = scheduler.state_at(&night);
+        assert!(!state.power && !state.daylight);
+    }
+}
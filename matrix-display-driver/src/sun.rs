@@ -0,0 +1,93 @@
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+
+/// Julian day of 2000-01-01 12:00 UTC
+const J2000: f64 = 2451545.0;
+/// Julian day of the unix epoch
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2440587.5;
+/// Sun altitude at sunrise/sunset, corrected for refraction and the sun's radius
+const SUNRISE_ALTITUDE: f64 = -0.833;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Daylight {
+    /// Sunrise and sunset on this day
+    Between(DateTime<Utc>, DateTime<Utc>),
+    /// The sun does not set (polar day)
+    AllDay,
+    /// The sun does not rise (polar night)
+    None,
+}
+
+impl Daylight {
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        match self {
+            Daylight::Between(sunrise, sunset) => *sunrise <= time && time < *sunset,
+            Daylight::AllDay => true,
+            Daylight::None => false,
+        }
+    }
+}
+
+/// Sunrise and sunset following the sunrise equation as described on
+/// <https://en.wikipedia.org/wiki/Sunrise_equation>. Accurate to about a minute.
+pub fn daylight(date: NaiveDate, latitude: f64, longitude: f64) -> Daylight {
+    // Days since 2000-01-01
+    let n = (date.num_days_from_ce() - 730120) as f64;
+
+    let mean_solar_time = n - longitude / 360.0;
+    let mean_anomaly = (357.5291 + 0.98560028 * mean_solar_time).rem_euclid(360.0);
+    let m = mean_anomaly.to_radians();
+    let center = 1.9148 * m.sin() + 0.0200 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+    let ecliptic_longitude = (mean_anomaly + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit =
+        J2000 + mean_solar_time + 0.0053 * m.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+
+    let declination = (ecliptic_longitude.sin() * 23.4397f64.to_radians().sin()).asin();
+    let latitude = latitude.to_radians();
+    let cos_hour_angle = (SUNRISE_ALTITUDE.to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+
+    if cos_hour_angle < -1.0 {
+        return Daylight::AllDay;
+    }
+    if cos_hour_angle > 1.0 {
+        return Daylight::None;
+    }
+
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+    Daylight::Between(
+        from_julian_day(transit - hour_angle / 360.0),
+        from_julian_day(transit + hour_angle / 360.0),
+    )
+}
+
+fn from_julian_day(julian_day: f64) -> DateTime<Utc> {
+    let seconds = (julian_day - UNIX_EPOCH_JULIAN_DAY) * 86400.0;
+    Utc.timestamp_opt(seconds.round() as i64, 0).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_daylight_berlin() {
+        let Daylight::Between(sunrise, sunset) =
+            daylight(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), 52.52, 13.405)
+        else {
+            panic!("Expected sunrise and sunset");
+        };
+
+        // 04:43 and 21:33 local time (CEST)
+        let expected_sunrise = Utc.with_ymd_and_hms(2024, 6, 21, 2, 43, 0).unwrap();
+        let expected_sunset = Utc.with_ymd_and_hms(2024, 6, 21, 19, 33, 0).unwrap();
+        assert!((sunrise - expected_sunrise).num_minutes().abs() <= 3);
+        assert!((sunset - expected_sunset).num_minutes().abs() <= 3);
+
+        assert_eq!(
+            daylight(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), 78.2, 15.6),
+            Daylight::AllDay
+        );
+    }
+}
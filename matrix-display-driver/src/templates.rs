@@ -0,0 +1,284 @@
+use crate::{
+    awtrix3::{color::ColorScale, Color},
+    display::Screen,
+};
+use anyhow::{anyhow, Context, Result};
+use energy_monitor_lib::{
+    opendtu::topics::{OPEN_DTU_AC_POWER_TOPIC, OPEN_DTU_AC_YIELD_DAY_TOPIC},
+    pulse::topics::PULSE_CONSUMPTION_TOPIC,
+    tibber::topics::TIBBER_PRICE_INFORMATION_TOPIC,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Describes how the messages of a source topic are shown as screen on the displays
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AppTemplate {
+    /// Name of the screen, on Awtrix clocks it is published to `<prefix>/custom/<name>`
+    pub name: String,
+    /// Topic providing the value
+    pub source_topic: String,
+    /// Field holding the value if the payload is a JSON object, nested
+    /// fields are separated by dots, e.g. `total.amount`. Numbers given as
+    /// strings are accepted.
+    pub field: Option<String>,
+    /// The value is multiplied with this factor, e.g. 0.001 to convert W to kW
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    /// Number of decimal places
+    #[serde(default)]
+    pub precision: usize,
+    /// Text shown on the display, `{value}` is replaced by the value
+    #[serde(default = "default_format")]
+    pub format: String,
+    pub icon: Option<String>,
+    pub color: Option<Color>,
+    /// Field of the payload whose text selects the color from `colors`
+    pub color_field: Option<String>,
+    #[serde(default)]
+    pub colors: BTreeMap<String, Color>,
+    /// Value (before scaling) at which the progress bar is full and the
+    /// color scale ends, e.g. the PV peak power or the main fuse capacity
+    pub max: Option<f64>,
+    /// Colors from 0 to `max` used for the text and the progress bar
+    pub color_scale: Option<ColorScale>,
+    /// Show a progress bar from 0 to `max`
+    #[serde(default)]
+    pub progress: bool,
+    /// Defaults to a dimmed version of the bar color
+    pub progress_background: Option<Color>,
+    pub gradient: Option<Vec<Color>>,
+    pub background: Option<Color>,
+    /// Seconds the app is shown
+    pub duration: Option<i32>,
+    /// Seconds after which the app is removed if no update was received
+    pub life_time: Option<i32>,
+    /// Field of the payload holding the seconds the value is valid, e.g. the
+    /// duration of a price. Replaces `life_time` if present, with a margin
+    /// for the next update to arrive.
+    pub life_time_field: Option<String>,
+    /// Only shown while the sun is up, requires a `[schedule]` with the location
+    #[serde(default)]
+    pub daylight_only: bool,
+    /// Show every update once as notification instead of keeping an app
+    #[serde(default)]
+    pub notify: bool,
+    /// Only shown while this view is selected with the buttons of the clock
+    pub view: Option<String>,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_format() -> String {
+    "{value}".to_string()
+}
+
+impl AppTemplate {
+    fn new(name: &str, source_topic: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            source_topic: source_topic.to_string(),
+            field: None,
+            scale: default_scale(),
+            precision: 0,
+            format: default_format(),
+            icon: None,
+            color: None,
+            color_field: None,
+            colors: BTreeMap::new(),
+            max: None,
+            color_scale: None,
+            progress: false,
+            progress_background: None,
+            gradient: None,
+            background: None,
+            duration: None,
+            life_time: None,
+            life_time_field: None,
+            daylight_only: false,
+            notify: false,
+            view: None,
+        }
+    }
+
+    pub fn render(&self, payload: &[u8]) -> Result<Screen> {
+        let payload: Value = serde_json::from_slice(payload).context("Payload is not JSON")?;
+        let value = match &self.field {
+            Some(field) => lookup(&payload, field),
+            None => Some(&payload),
+        }
+        .and_then(number)
+        .ok_or_else(|| anyhow!("No numeric value in payload for app {}", self.name))?;
+
+        let fraction = self.max.map(|max| (value / max).clamp(0.0, 1.0));
+        let scale_color = self
+            .color_scale
+            .as_ref()
+            .zip(fraction)
+            .and_then(|(scale, fraction)| scale.color_at(fraction));
+
+        let color = self
+            .color_field
+            .as_ref()
+            .and_then(|field| lookup(&payload, field))
+            .and_then(Value::as_str)
+            .and_then(|key| self.colors.get(key).copied())
+            .or(scale_color)
+            .or(self.color);
+
+        let life_time = self
+            .life_time_field
+            .as_ref()
+            .and_then(|field| lookup(&payload, field))
+            .and_then(Value::as_i64)
+            .and_then(|seconds| i32::try_from(seconds + LIFE_TIME_MARGIN).ok())
+            .or(self.life_time);
+
+        Ok(Screen {
+            name: self.name.clone(),
+            text: self.format.replace(
+                "{value}",
+                &format!("{:.*}", self.precision, value * self.scale),
+            ),
+            icon: self.icon.clone(),
+            color,
+            gradient: self.gradient.clone(),
+            background: self.background,
+            progress: fraction
+                .filter(|_| self.progress)
+                .map(|fraction| (fraction * 100.0).round() as i32),
+            progress_color: color.filter(|_| self.progress),
+            progress_background: self
+                .progress_background
+                .or(color.map(|color| color.blend(Color::new(0, 0, 0), 0.8)))
+                .filter(|_| self.progress),
+            duration: self.duration,
+            life_time,
+        })
+    }
+}
+
+/// Field of a JSON object, `a.b` is the field `b` of the object in `a`
+fn lookup<'a>(payload: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(payload, |value, field| value.get(field))
+}
+
+/// Decimals like prices are serialized as strings to keep all digits
+fn number(value: &Value) -> Option<f64> {
+    value
+        .as_f64()
+        .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+}
+
+/// Seconds added to the `life_time_field`, the next value is usually
+/// published a few seconds after the current one expired
+const LIFE_TIME_MARGIN: i64 = 120;
+
+/// Peak power of the PV system in W
+const DEFAULT_MAX_PRODUCTION: f64 = 800.0;
+/// Power in W at which the house is considered fully loaded
+const DEFAULT_MAX_CONSUMPTION: f64 = 10000.0;
+
+/// The apps shown if no configuration file exists
+pub fn default_templates() -> Vec<AppTemplate> {
+    vec![
+        AppTemplate {
+            icon: Some(52455.to_string()),
+            duration: Some(5),
+            daylight_only: true,
+            ..AppTemplate::new("yieldday", OPEN_DTU_AC_YIELD_DAY_TOPIC.name())
+        },
+        AppTemplate {
+            icon: Some(37515.to_string()),
+            duration: Some(5),
+            max: Some(DEFAULT_MAX_PRODUCTION),
+            color_scale: Some(ColorScale(vec![
+                Color::new(0x80, 0x80, 0x80),
+                Color::new(0xFF, 0xD7, 0x00),
+            ])),
+            progress: true,
+            daylight_only: true,
+            ..AppTemplate::new("power", OPEN_DTU_AC_POWER_TOPIC.name())
+        },
+        AppTemplate {
+            field: Some("consumption".to_string()),
+            scale: 0.001,
+            precision: 1,
+            icon: Some(55888.to_string()),
+            duration: Some(5),
+            life_time: Some(10), // if no update within 10 seconds remove
+            max: Some(DEFAULT_MAX_CONSUMPTION),
+            color_scale: Some(ColorScale(vec![
+                Color::new(0x66, 0xFF, 0x00),
+                Color::new(0xFF, 0xD7, 0x00),
+                Color::new(0xFF, 0x08, 0x00),
+            ])),
+            progress: true,
+            ..AppTemplate::new("consumption", PULSE_CONSUMPTION_TOPIC.name())
+        },
+        AppTemplate {
+            field: Some("total.amount".to_string()),
+            precision: 2,
+            icon: Some(54231.to_string()),
+            color_field: Some("level".to_string()),
+            colors: [
+                ("Cheap", "#66FF00"),
+                ("Expensive", "#FF0800"),
+                ("Normal", "#ED872D"),
+                ("VeryCheap", "#66FF00"),
+                ("VeryExpensive", "#FF0800"),
+                ("None", "#FF00FF"),
+            ]
+            .into_iter()
+            .map(|(level, color)| (level.to_string(), color.parse().unwrap()))
+            .collect(),
+            duration: Some(2),
+            life_time: Some(60 * 62), // 1 hour and 2 minutes if the price has no duration
+            life_time_field: Some("duration".to_string()),
+            ..AppTemplate::new("tibberprice", TIBBER_PRICE_INFORMATION_TOPIC.name())
+        },
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_templates() {
+        let templates = default_templates();
+        let consumption = templates.iter().find(|t| t.name == "consumption").unwrap();
+        let app = consumption.render(br#"{"consumption":1234}"#).unwrap();
+        assert_eq!(app.text, "1.2");
+        assert_eq!(app.life_time, Some(10));
+        assert_eq!(app.progress, Some(12));
+        assert_eq!(app.progress_color, app.color);
+
+        let price = templates.iter().find(|t| t.name == "tibberprice").unwrap();
+        let app = price
+            .render(br#"{"total":{"amount":"0.2512","currency":"EUR"},"level":"Expensive"}"#)
+            .unwrap();
+        assert_eq!(app.text, "0.25");
+        assert_eq!(app.color, Some(Color::new(0xFF, 0x08, 0x00)));
+        assert_eq!(app.progress, None);
+        assert_eq!(app.life_time, Some(3720));
+
+        // Quarter-hourly prices expire sooner
+        let app = price
+            .render(br#"{"total":{"amount":"0.2512","currency":"EUR"},"level":"Normal","duration":900}"#)
+            .unwrap();
+        assert_eq!(app.life_time, Some(1020));
+
+        // Levels without a color are shown in the default color
+        let app = price
+            .render(br#"{"total":{"amount":"0.2512","currency":"EUR"},"level":"ExtremelyCheap"}"#)
+            .unwrap();
+        assert_eq!(app.text, "0.25");
+        assert_eq!(app.color, None);
+    }
+}
@@ -0,0 +1,73 @@
+use crate::{awtrix3::dto::Button, templates::AppTemplate};
+
+/// Apps can be grouped into views which are cycled with the buttons of the
+/// clock. Apps without a view are always shown.
+#[derive(Debug)]
+pub struct Views {
+    names: Vec<String>,
+    active: usize,
+}
+
+impl Views {
+    /// The views in the order their first app is listed
+    pub fn new(apps: &[AppTemplate]) -> Self {
+        let mut names: Vec<String> = Vec::new();
+        for view in apps.iter().filter_map(|app| app.view.as_ref()) {
+            if !names.contains(view) {
+                names.push(view.clone());
+            }
+        }
+        Self { names, active: 0 }
+    }
+
+    pub fn active(&self) -> Option<&str> {
+        self.names.get(self.active).map(String::as_str)
+    }
+
+    pub fn is_visible(&self, app: &AppTemplate) -> bool {
+        app.view.is_none() || app.view.as_deref() == self.active()
+    }
+
+    /// Right selects the next, left the previous and select the first view.
+    /// Returns whether the active view changed.
+    pub fn press(&mut self, button: Button) -> bool {
+        if self.names.is_empty() {
+            return false;
+        }
+        let previous = self.active;
+        self.active = match button {
+            Button::Left => (self.active + self.names.len() - 1) % self.names.len(),
+            Button::Select => 0,
+            Button::Right => (self.active + 1) % self.names.len(),
+        };
+        self.active != previous
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::templates::default_templates;
+
+    #[test]
+    fn test_cycle_views() {
+        let mut apps = default_templates();
+        apps[0].view = Some("today".to_string());
+        apps[1].view = Some("month".to_string());
+        apps[2].view = Some("today".to_string());
+        let mut views = Views::new(&apps);
+
+        assert_eq!(views.active(), Some("today"));
+        assert!(views.is_visible(&apps[0]) && !views.is_visible(&apps[1]));
+        assert!(views.is_visible(&apps[3]));
+
+        assert!(views.press(Button::Right));
+        assert_eq!(views.active(), Some("month"));
+        assert!(views.press(Button::Right));
+        assert_eq!(views.active(), Some("today"));
+        assert!(views.press(Button::Left));
+        assert_eq!(views.active(), Some("month"));
+        assert!(views.press(Button::Select));
+        assert!(!views.press(Button::Select));
+    }
+}
@@ -0,0 +1,134 @@
+//! Day-ahead prices of the aWATTar API, `https://api.awattar.de/v1/marketdata`
+//! for Germany and `https://api.awattar.at/v1/marketdata` for Austria
+
+use crate::{errors::PriceLoaderError, select, spot_price, TIMEOUT};
+use chrono::{DateTime, Utc};
+use energy_monitor_lib::{money::Currency, price::PriceProvider, tibber::dto::PriceDetails};
+use reqwest::Client;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+const DEFAULT_URL: &str = "https://api.awattar.de/v1/marketdata";
+
+pub struct AwattarProvider {
+    url: String,
+    client: Client,
+}
+
+#[derive(Deserialize, Debug)]
+struct MarketData {
+    data: Vec<MarketPrice>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MarketPrice {
+    /// Milliseconds since the epoch
+    start_timestamp: i64,
+    end_timestamp: i64,
+    marketprice: Decimal,
+    /// Always `Eur/MWh`
+    unit: String,
+}
+
+impl AwattarProvider {
+    pub fn new(url: &str) -> Result<Self, PriceLoaderError> {
+        Ok(Self {
+            url: url.to_string(),
+            client: Client::builder().timeout(TIMEOUT).build()?,
+        })
+    }
+
+    /// The German API unless `AWATTAR_URL` is set
+    pub fn from_env() -> Result<Self, PriceLoaderError> {
+        Self::new(&std::env::var("AWATTAR_URL").unwrap_or(DEFAULT_URL.to_string()))
+    }
+}
+
+impl PriceProvider for AwattarProvider {
+    type Error = PriceLoaderError;
+
+    async fn prices(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<PriceDetails>, Self::Error> {
+        let data = self
+            .client
+            .get(&self.url)
+            .query(&[
+                ("start", from.timestamp_millis()),
+                ("end", to.timestamp_millis()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(select(parse(&data)?, from, to))
+    }
+}
+
+fn parse(data: &str) -> Result<Vec<PriceDetails>, PriceLoaderError> {
+    let data: MarketData =
+        serde_json::from_str(data).map_err(|e| PriceLoaderError::InvalidPrices(e.to_string()))?;
+    data.data
+        .into_iter()
+        .map(|price| {
+            if !price.unit.eq_ignore_ascii_case("Eur/MWh") {
+                return Err(PriceLoaderError::InvalidPrices(format!(
+                    "Unexpected unit {}",
+                    price.unit
+                )));
+            }
+            let time = |millis| {
+                DateTime::from_timestamp_millis(millis).ok_or_else(|| {
+                    PriceLoaderError::InvalidPrices(format!("Invalid timestamp {millis}"))
+                })
+            };
+            spot_price(
+                time(price.start_timestamp)?,
+                time(price.end_timestamp)?,
+                price.marketprice,
+                Currency::EUR,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use energy_monitor_lib::{money::Money, tibber::dto::PriceLevel};
+
+    #[test]
+    fn test_parse() {
+        let data = r#"{
+            "object": "list",
+            "data": [
+                {"start_timestamp": 1718924400000, "end_timestamp": 1718928000000, "marketprice": 87.5, "unit": "Eur/MWh"},
+                {"start_timestamp": 1718928000000, "end_timestamp": 1718931600000, "marketprice": -3.02, "unit": "Eur/MWh"}
+            ],
+            "url": "/de/v1/marketdata"
+        }"#;
+        let prices = parse(data).unwrap();
+        assert_eq!(prices.len(), 2);
+        assert_eq!(
+            prices[0].starts_at,
+            "2024-06-21T01:00:00+02:00"
+                .parse::<DateTime<Utc>>()
+                .unwrap()
+        );
+        assert_eq!(prices[0].duration, 3600);
+        assert_eq!(
+            prices[0].total,
+            Money::new(Decimal::new(875, 4), Currency::EUR)
+        );
+        assert_eq!(prices[0].energy, prices[0].total);
+        assert_eq!(prices[0].level, PriceLevel::None);
+        // Negative prices are kept
+        assert_eq!(prices[1].total.amount, Decimal::new(-302, 5));
+
+        let data = r#"{"data": [{"start_timestamp": 0, "end_timestamp": 1, "marketprice": 1, "unit": "ct/kWh"}]}"#;
+        assert!(parse(data).is_err());
+    }
+}
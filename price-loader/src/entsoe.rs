@@ -0,0 +1,258 @@
+//! Day-ahead prices (document type A44) of the ENTSO-E transparency platform.
+//! A security token is requested from the platform's support, the bidding
+//! zone is given by its EIC code.
+
+use crate::{errors::PriceLoaderError, select, spot_price, TIMEOUT};
+use chrono::{DateTime, DurationRound, NaiveDateTime, TimeDelta, Utc};
+use energy_monitor_lib::{money::Currency, price::PriceProvider, tibber::dto::PriceDetails};
+use reqwest::Client;
+use roxmltree::{Document, Node};
+use rust_decimal::Decimal;
+
+const DEFAULT_URL: &str = "https://web-api.tp.entsoe.eu/api";
+/// Bidding zone Germany-Luxembourg
+const DEFAULT_AREA: &str = "10Y1001A1001A82H";
+/// Reason code of the acknowledgement sent if there are no prices yet
+const NO_MATCHING_DATA: &str = "999";
+
+pub struct EntsoeProvider {
+    url: String,
+    token: String,
+    area: String,
+    client: Client,
+}
+
+impl EntsoeProvider {
+    pub fn new(url: &str, token: &str, area: &str) -> Result<Self, PriceLoaderError> {
+        Ok(Self {
+            url: url.to_string(),
+            token: token.to_string(),
+            area: area.to_string(),
+            client: Client::builder().timeout(TIMEOUT).build()?,
+        })
+    }
+
+    /// Configured with `ENTSOE_API_TOKEN` and `ENTSOE_AREA`, the bidding
+    /// zone Germany-Luxembourg if not set
+    pub fn from_env() -> Result<Self, PriceLoaderError> {
+        let token = std::env::var("ENTSOE_API_TOKEN")
+            .map_err(|_| PriceLoaderError::MissingConfig("ENTSOE_API_TOKEN"))?;
+        let area = std::env::var("ENTSOE_AREA").unwrap_or(DEFAULT_AREA.to_string());
+        Self::new(DEFAULT_URL, &token, &area)
+    }
+}
+
+impl PriceProvider for EntsoeProvider {
+    type Error = PriceLoaderError;
+
+    async fn prices(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<PriceDetails>, Self::Error> {
+        // Periods are requested in whole hours
+        let hour = TimeDelta::hours(1);
+        let period_start = from.duration_trunc(hour).unwrap_or(from);
+        let period_end = to.duration_trunc(hour).unwrap_or(to) + hour;
+        let format = |time: DateTime<Utc>| time.format("%Y%m%d%H%M").to_string();
+
+        // Errors are reported in an acknowledgement with status 400
+        let document = self
+            .client
+            .get(&self.url)
+            .query(&[
+                ("securityToken", self.token.as_str()),
+                ("documentType", "A44"),
+                ("in_Domain", &self.area),
+                ("out_Domain", &self.area),
+                ("periodStart", &format(period_start)),
+                ("periodEnd", &format(period_end)),
+            ])
+            .send()
+            .await?
+            .text()
+            .await?;
+        Ok(select(parse(&document)?, from, to))
+    }
+}
+
+/// Prices of a `Publication_MarketDocument`. Points with the same price as
+/// the one before may be left out.
+fn parse(document: &str) -> Result<Vec<PriceDetails>, PriceLoaderError> {
+    let document = Document::parse(document)?;
+    let root = document.root_element();
+    if root.tag_name().name() == "Acknowledgement_MarketDocument" {
+        let reason = child(root, "Reason")?;
+        return match text(reason, "code")? {
+            NO_MATCHING_DATA => Ok(Vec::new()),
+            _ => Err(PriceLoaderError::Rejected(
+                text(reason, "text")?.to_string(),
+            )),
+        };
+    }
+
+    let mut prices = Vec::new();
+    for series in children(root, "TimeSeries") {
+        let currency: Currency = text(series, "currency_Unit.name")?.parse()?;
+        let unit = text(series, "price_Measure_Unit.name")?;
+        if unit != "MWH" {
+            return Err(invalid(format!("Unexpected unit {unit}")));
+        }
+
+        for period in children(series, "Period") {
+            let interval = child(period, "timeInterval")?;
+            let start = time(text(interval, "start")?)?;
+            let end = time(text(interval, "end")?)?;
+            let resolution = resolution(text(period, "resolution")?)?;
+
+            let mut points = children(period, "Point")
+                .map(|point| {
+                    let position: i32 = text(point, "position")?
+                        .parse()
+                        .map_err(|_| invalid("Invalid position"))?;
+                    let price: Decimal = text(point, "price.amount")?
+                        .parse()
+                        .map_err(|_| invalid("Invalid price"))?;
+                    Ok((position, price))
+                })
+                .collect::<Result<Vec<_>, PriceLoaderError>>()?;
+            points.sort_by_key(|(position, _)| *position);
+
+            let mut points = points.into_iter().peekable();
+            let mut price = None;
+            let mut starts_at = start;
+            let mut position = 1;
+            while starts_at < end {
+                if let Some((_, next)) = points.next_if(|(next, _)| *next <= position) {
+                    price = Some(next);
+                }
+                let price = price.ok_or_else(|| invalid("Period without first point"))?;
+                prices.push(spot_price(
+                    starts_at,
+                    starts_at + resolution,
+                    price,
+                    currency,
+                )?);
+                starts_at += resolution;
+                position += 1;
+            }
+        }
+    }
+    Ok(prices)
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.tag_name().name() == name)
+}
+
+fn child<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> Result<Node<'a, 'input>, PriceLoaderError> {
+    children(node, name)
+        .next()
+        .ok_or_else(|| invalid(format!("Missing {name}")))
+}
+
+fn text<'a>(node: Node<'a, '_>, name: &'static str) -> Result<&'a str, PriceLoaderError> {
+    Ok(child(node, name)?.text().unwrap_or_default().trim())
+}
+
+/// Times like `2024-06-20T22:00Z`
+fn time(text: &str) -> Result<DateTime<Utc>, PriceLoaderError> {
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%MZ")
+        .map(|time| time.and_utc())
+        .map_err(|_| invalid(format!("Invalid time {text}")))
+}
+
+/// Durations like `PT15M` or `PT60M`
+fn resolution(text: &str) -> Result<TimeDelta, PriceLoaderError> {
+    text.strip_prefix("PT")
+        .and_then(|minutes| minutes.strip_suffix('M'))
+        .and_then(|minutes| minutes.parse().ok())
+        .filter(|minutes| *minutes > 0)
+        .map(TimeDelta::minutes)
+        .ok_or_else(|| invalid(format!("Unsupported resolution {text}")))
+}
+
+fn invalid(message: impl Into<String>) -> PriceLoaderError {
+    PriceLoaderError::InvalidPrices(message.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DOCUMENT: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<Publication_MarketDocument xmlns="urn:iec62325.351:tc57wg16:451-3:publicationdocument:7:3">
+  <mRID>1</mRID>
+  <type>A44</type>
+  <TimeSeries>
+    <mRID>1</mRID>
+    <currency_Unit.name>EUR</currency_Unit.name>
+    <price_Measure_Unit.name>MWH</price_Measure_Unit.name>
+    <curveType>A03</curveType>
+    <Period>
+      <timeInterval>
+        <start>2024-06-20T22:00Z</start>
+        <end>2024-06-20T23:00Z</end>
+      </timeInterval>
+      <resolution>PT15M</resolution>
+      <Point>
+        <position>1</position>
+        <price.amount>92.1</price.amount>
+      </Point>
+      <Point>
+        <position>3</position>
+        <price.amount>-5</price.amount>
+      </Point>
+    </Period>
+  </TimeSeries>
+</Publication_MarketDocument>"#;
+
+    #[test]
+    fn test_parse() {
+        let prices = parse(DOCUMENT).unwrap();
+        let amounts: Vec<_> = prices.iter().map(|price| price.total.amount).collect();
+        // The second point is left out as it has the same price as the first
+        assert_eq!(
+            amounts,
+            [
+                Decimal::new(921, 4),
+                Decimal::new(921, 4),
+                Decimal::new(-5, 3),
+                Decimal::new(-5, 3)
+            ]
+        );
+        assert_eq!(
+            prices[1].starts_at,
+            "2024-06-21T00:15:00+02:00"
+                .parse::<DateTime<Utc>>()
+                .unwrap()
+        );
+        assert_eq!(prices[1].duration, 900);
+        assert_eq!(prices[3].ends_at(), time("2024-06-20T23:00Z").unwrap());
+    }
+
+    #[test]
+    fn test_acknowledgement() {
+        let acknowledgement = |code| {
+            format!(
+                r#"<Acknowledgement_MarketDocument xmlns="urn:iec62325.351:tc57wg16:451-1:acknowledgementdocument:7:0">
+                    <Reason><code>{code}</code><text>No matching data found</text></Reason>
+                </Acknowledgement_MarketDocument>"#
+            )
+        };
+        assert!(parse(&acknowledgement("999")).unwrap().is_empty());
+        assert!(matches!(
+            parse(&acknowledgement("401")),
+            Err(PriceLoaderError::Rejected(_))
+        ));
+        assert_eq!(resolution("PT60M").unwrap(), TimeDelta::hours(1));
+        assert!(resolution("P1D").is_err());
+    }
+}
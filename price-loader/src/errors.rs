@@ -0,0 +1,32 @@
+use energy_monitor_lib::money::MoneyError;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PriceLoaderError {
+    #[error("Unknown price provider {0}, expected tibber, awattar, entsoe or file")]
+    UnknownProvider(String),
+
+    #[error("{0} is not set")]
+    MissingConfig(&'static str),
+
+    #[error("Failed to fetch: {0}")]
+    FetchError(#[from] reqwest::Error),
+
+    #[error("Failed to read the price file: {0}")]
+    ReadError(#[from] std::io::Error),
+
+    #[error("Invalid price file: {0}")]
+    CsvError(#[from] csv::Error),
+
+    #[error("Invalid ENTSO-E document: {0}")]
+    XmlError(#[from] roxmltree::Error),
+
+    #[error("Invalid prices: {0}")]
+    InvalidPrices(String),
+
+    #[error(transparent)]
+    MoneyError(#[from] MoneyError),
+
+    #[error("ENTSO-E rejected the request: {0}")]
+    Rejected(String),
+}
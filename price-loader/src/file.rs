@@ -0,0 +1,112 @@
+//! Prices from a CSV file, e.g. exported from EPEX or written for tests:
+//!
+//! ```text
+//! starts_at,ends_at,price,currency
+//! 2024-06-21T00:00:00+02:00,2024-06-21T01:00:00+02:00,87.50,EUR
+//! ```
+//!
+//! Prices are per MWh, the currency is optional and EUR by default. The file
+//! is read on every request, so it can be updated while the daemon runs.
+
+use crate::{errors::PriceLoaderError, select, spot_price};
+use chrono::{DateTime, FixedOffset, Utc};
+use energy_monitor_lib::{money::Currency, price::PriceProvider, tibber::dto::PriceDetails};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+pub struct FileProvider {
+    path: PathBuf,
+}
+
+#[derive(Deserialize, Debug)]
+struct Row {
+    starts_at: DateTime<FixedOffset>,
+    ends_at: DateTime<FixedOffset>,
+    price: Decimal,
+    #[serde(default = "default_currency")]
+    currency: Currency,
+}
+
+fn default_currency() -> Currency {
+    Currency::EUR
+}
+
+impl FileProvider {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Reads the file in `PRICE_FILE`
+    pub fn from_env() -> Result<Self, PriceLoaderError> {
+        let path = std::env::var("PRICE_FILE")
+            .map_err(|_| PriceLoaderError::MissingConfig("PRICE_FILE"))?;
+        Ok(Self::new(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl PriceProvider for FileProvider {
+    type Error = PriceLoaderError;
+
+    async fn prices(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<PriceDetails>, Self::Error> {
+        let file = tokio::fs::read(&self.path).await?;
+        Ok(select(parse(&file)?, from, to))
+    }
+}
+
+fn parse(file: &[u8]) -> Result<Vec<PriceDetails>, PriceLoaderError> {
+    csv::Reader::from_reader(file)
+        .deserialize()
+        .map(|row| {
+            let row: Row = row?;
+            spot_price(
+                row.starts_at.to_utc(),
+                row.ends_at.to_utc(),
+                row.price,
+                row.currency,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_prices() {
+        let path = std::env::temp_dir().join(format!("prices-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "starts_at,ends_at,price\n\
+             2024-06-21T01:00:00+02:00,2024-06-21T02:00:00+02:00,80.00\n\
+             2024-06-21T00:00:00+02:00,2024-06-21T01:00:00+02:00,87.50\n\
+             2024-06-21T02:00:00+02:00,2024-06-21T02:15:00+02:00,12.4\n",
+        )
+        .unwrap();
+        let provider = FileProvider::new(&path);
+
+        let from = "2024-06-21T00:00:00+02:00".parse().unwrap();
+        let to = "2024-06-21T02:00:00+02:00".parse().unwrap();
+        let prices = provider.prices(from, to).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Ordered by start, the last one starts too late
+        assert_eq!(prices.len(), 2);
+        assert_eq!(prices[0].starts_at, from);
+        assert_eq!(prices[0].total.to_string(), "0.0875 EUR");
+        assert_eq!(prices[1].ends_at(), to);
+
+        let invalid =
+            "starts_at,ends_at,price\n2024-06-21T00:00:00+02:00,2024-06-21T00:00:00+02:00,1\n";
+        assert!(parse(invalid.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+//! Day-ahead spot prices from other sources than Tibber: the aWATTar API,
+//! the ENTSO-E transparency platform and CSV files, e.g. exported from EPEX.
+//! All of them quote prices per MWh, they are converted to net prices per
+//! kWh. Surcharges and VAT are added by a spot tariff.
+
+use crate::{
+    awattar::AwattarProvider, entsoe::EntsoeProvider, errors::PriceLoaderError, file::FileProvider,
+};
+use chrono::{DateTime, Local, Utc};
+use energy_monitor_lib::{
+    money::{Currency, Money},
+    price::PriceProvider,
+    tibber::dto::{PriceDetails, PriceLevel},
+};
+use rust_decimal::Decimal;
+use std::{fmt, time::Duration};
+
+pub mod awattar;
+pub mod entsoe;
+pub mod errors;
+pub mod file;
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Provider selected with `PRICE_PROVIDER`
+pub enum Provider {
+    Awattar(AwattarProvider),
+    Entsoe(EntsoeProvider),
+    File(FileProvider),
+}
+
+impl Provider {
+    /// None if `PRICE_PROVIDER` is not set or `tibber`
+    pub fn from_env() -> Result<Option<Self>, PriceLoaderError> {
+        let provider = match std::env::var("PRICE_PROVIDER").as_deref() {
+            Err(_) | Ok("tibber") => return Ok(None),
+            Ok("awattar") => Provider::Awattar(AwattarProvider::from_env()?),
+            Ok("entsoe") => Provider::Entsoe(EntsoeProvider::from_env()?),
+            Ok("file") => Provider::File(FileProvider::from_env()?),
+            Ok(other) => return Err(PriceLoaderError::UnknownProvider(other.to_string())),
+        };
+        Ok(Some(provider))
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Provider::Awattar(_) => write!(f, "aWATTar"),
+            Provider::Entsoe(_) => write!(f, "ENTSO-E"),
+            Provider::File(provider) => write!(f, "{}", provider.path().display()),
+        }
+    }
+}
+
+impl PriceProvider for Provider {
+    type Error = PriceLoaderError;
+
+    async fn prices(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<PriceDetails>, Self::Error> {
+        match self {
+            Provider::Awattar(provider) => provider.prices(from, to).await,
+            Provider::Entsoe(provider) => provider.prices(from, to).await,
+            Provider::File(provider) => provider.prices(from, to).await,
+        }
+    }
+}
+
+/// Net price per kWh of the period from the price per MWh
+fn spot_price(
+    starts_at: DateTime<Utc>,
+    ends_at: DateTime<Utc>,
+    per_mwh: Decimal,
+    currency: Currency,
+) -> Result<PriceDetails, PriceLoaderError> {
+    let duration = u32::try_from((ends_at - starts_at).num_seconds())
+        .ok()
+        .filter(|duration| *duration > 0)
+        .ok_or_else(|| PriceLoaderError::InvalidPrices(format!("Empty period at {starts_at}")))?;
+    let price = Money::new(per_mwh / Decimal::ONE_THOUSAND, currency);
+    Ok(PriceDetails {
+        total: price,
+        energy: price,
+        tax: Money::zero(currency),
+        level: PriceLevel::None,
+        starts_at: starts_at.with_timezone(&Local).fixed_offset(),
+        duration,
+    })
+}
+
+/// Prices starting between `from` and `to` ordered by their start, the first
+/// of prices starting at the same time is kept
+fn select(
+    mut prices: Vec<PriceDetails>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<PriceDetails> {
+    prices.retain(|price| from <= price.starts_at && price.starts_at < to);
+    prices.sort_by_key(|price| price.starts_at);
+    prices.dedup_by_key(|price| price.starts_at);
+    prices
+}
@@ -0,0 +1,81 @@
+use crate::{record_publish_failure, records::RecordWriter};
+use anyhow::{Context, Result};
+use chrono::{Local, Utc};
+use energy_monitor_lib::{
+    energy::{
+        aggregator::Aggregator,
+        topics::{ENERGY_DAILY_TOPIC, ENERGY_MONTHLY_TOPIC},
+    },
+    mqtt::MqttClient,
+    tibber::dto::PriceDetails,
+};
+use log::{debug, error};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+const DEFAULT_STATE_PATH: &str = "/var/lib/emtibberd/aggregate.json";
+
+/// Daily and monthly energy totals shared by the jobs feeding and publishing them
+#[derive(Clone)]
+pub struct SharedAggregator {
+    aggregator: Arc<Mutex<Aggregator<Local>>>,
+    path: PathBuf,
+    records: Option<Arc<Mutex<RecordWriter>>>,
+}
+
+impl SharedAggregator {
+    /// Continues with the totals saved in `EMTIBBERD_STATE_PATH`
+    pub fn load() -> Result<Self> {
+        let path: PathBuf = std::env::var("EMTIBBERD_STATE_PATH")
+            .unwrap_or(DEFAULT_STATE_PATH.to_string())
+            .into();
+        let aggregator = Aggregator::load(Local, &path, Utc::now())
+            .with_context(|| format!("Failed to load {}", path.display()))?;
+        Ok(Self {
+            aggregator: Arc::new(Mutex::new(aggregator)),
+            path,
+            records: RecordWriter::from_env().map(|records| Arc::new(Mutex::new(records))),
+        })
+    }
+
+    pub fn update<T>(&self, f: impl FnOnce(&mut Aggregator<Local>) -> T) -> T {
+        f(&mut self.aggregator.lock().unwrap())
+    }
+
+    /// Applies the price to the energy drawn from now on and records the
+    /// energy of the price period which ended
+    pub fn start_period(&self, price: &PriceDetails) -> Result<()> {
+        let mut aggregator = self.aggregator.lock().unwrap();
+        aggregator.set_price(price.total);
+        match &self.records {
+            Some(records) => records.lock().unwrap().start_period(price, &mut aggregator),
+            None => Ok(()),
+        }
+    }
+
+    /// Publishes the retained totals and saves them for the next start
+    pub async fn publish_and_save(&self, client: &MqttClient) -> Result<()> {
+        let (daily, monthly) = {
+            let mut aggregator = self.aggregator.lock().unwrap();
+            aggregator.advance(Utc::now());
+            if let Err(e) = aggregator.save(&self.path) {
+                error!("Failed to save {}: {:?}", self.path.display(), e);
+            }
+            (aggregator.daily(), aggregator.monthly())
+        };
+        debug!("Today: {:?}", daily);
+
+        ENERGY_DAILY_TOPIC
+            .publish(client, &daily)
+            .await
+            .inspect_err(|_| record_publish_failure(ENERGY_DAILY_TOPIC.name()))
+            .context("Failed to publish daily energy")?;
+        ENERGY_MONTHLY_TOPIC
+            .publish(client, &monthly)
+            .await
+            .inspect_err(|_| record_publish_failure(ENERGY_MONTHLY_TOPIC.name()))
+            .context("Failed to publish monthly energy")
+    }
+}
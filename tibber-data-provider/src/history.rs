@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use energy_monitor_lib::tibber::dto::PriceDetails;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+
+const DEFAULT_HISTORY_PATH: &str = "/var/lib/emtibberd/prices.json";
+/// The prices of the next day are published around noon, once fewer prices
+/// are known ahead they are due
+const PUBLISHED_AHEAD: TimeDelta = TimeDelta::hours(12);
+/// How often the prices are asked for while the next day is due
+const REFRESH_INTERVAL: TimeDelta = TimeDelta::hours(1);
+
+/// Day-ahead prices fetched before. Tibber only provides the prices of today
+/// and tomorrow, the days before are needed to classify them. The markets are
+/// only asked again once the prices of the next day may be published.
+pub struct PriceHistory {
+    saved: Saved,
+    path: PathBuf,
+    fetched_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Saved {
+    /// Prices of different sources are not comparable, e.g. Tibber's include
+    /// taxes while the markets' do not
+    source: String,
+    prices: Vec<PriceDetails>,
+}
+
+impl PriceHistory {
+    /// Continues with the prices of `source` saved in
+    /// `EMTIBBERD_PRICE_HISTORY_PATH`
+    pub fn load(source: &str) -> Result<Self> {
+        let path: PathBuf = std::env::var("EMTIBBERD_PRICE_HISTORY_PATH")
+            .unwrap_or(DEFAULT_HISTORY_PATH.to_string())
+            .into();
+        Self::open(path, source)
+    }
+
+    pub fn open(path: PathBuf, source: &str) -> Result<Self> {
+        let saved = if path.exists() {
+            let file =
+                fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            serde_json::from_slice(&file).with_context(|| format!("Invalid {}", path.display()))?
+        } else {
+            Saved::default()
+        };
+        let saved = if saved.source == source {
+            saved
+        } else {
+            Saved {
+                source: source.to_string(),
+                prices: Vec::new(),
+            }
+        };
+        Ok(Self {
+            saved,
+            path,
+            fetched_at: None,
+        })
+    }
+
+    /// Whether the prices have to be fetched: none is known for `now`, or
+    /// the next day is due and was not asked for within the last hour
+    pub fn needs_update(&self, now: DateTime<Utc>) -> bool {
+        let Some(ends_at) = self.saved.prices.last().map(PriceDetails::ends_at) else {
+            return true;
+        };
+        if ends_at <= now {
+            return true;
+        }
+        ends_at.to_utc() - now < PUBLISHED_AHEAD
+            && self
+                .fetched_at
+                .is_none_or(|fetched_at| now - fetched_at >= REFRESH_INTERVAL)
+    }
+
+    /// Whether a price is known for `now`
+    pub fn covers(&self, now: DateTime<Utc>) -> bool {
+        self.saved
+            .prices
+            .iter()
+            .any(|price| price.starts_at <= now && now < price.ends_at())
+    }
+
+    /// Adds the fetched prices and drops the ones starting before `from`.
+    /// Fetched prices replace the saved ones starting at the same time.
+    pub fn merge(&mut self, fetched: Vec<PriceDetails>, from: DateTime<Utc>, now: DateTime<Utc>) {
+        let mut prices = fetched;
+        prices.append(&mut self.saved.prices);
+        prices.retain(|price| price.starts_at >= from);
+        // The sort is stable, so the fetched price comes first
+        prices.sort_by_key(|price| price.starts_at);
+        prices.dedup_by_key(|price| price.starts_at);
+        self.saved.prices = prices;
+        self.fetched_at = Some(now);
+    }
+
+    /// Prices starting from `from` ordered by their start
+    pub fn prices(&self, from: DateTime<Utc>) -> Vec<PriceDetails> {
+        self.saved
+            .prices
+            .iter()
+            .filter(|price| price.starts_at >= from)
+            .cloned()
+            .collect()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        // Write to a temporary file first so a crash never leaves a truncated history
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, serde_json::to_vec(&self.saved)?)?;
+        fs::rename(temporary, &self.path)
+            .with_context(|| format!("Failed to save {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::FixedOffset;
+    use energy_monitor_lib::{
+        money::{Currency, Money},
+        tibber::dto::PriceLevel,
+    };
+
+    fn price(starts_at: DateTime<FixedOffset>, amount: f64) -> PriceDetails {
+        let price = Money::from_f64(amount, Currency::EUR).unwrap();
+        PriceDetails {
+            total: price,
+            energy: price,
+            tax: Money::zero(Currency::EUR),
+            level: PriceLevel::Normal,
+            starts_at,
+            duration: 3600,
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let path = std::env::temp_dir().join(format!("prices-{}.json", std::process::id()));
+        let start: DateTime<FixedOffset> = "2024-06-18T00:00:00+02:00".parse().unwrap();
+        let hour = |hour| start + TimeDelta::hours(hour);
+
+        let mut history = PriceHistory::open(path.clone(), "tibber").unwrap();
+        let now = hour(0).to_utc();
+        history.merge((0..3).map(|i| price(hour(i), 0.3)).collect(), now, now);
+        history.save().unwrap();
+
+        // The saved prices are kept after a restart, the old ones are dropped
+        let mut history = PriceHistory::open(path.clone(), "tibber").unwrap();
+        history.merge(
+            vec![price(hour(3), 0.2), price(hour(2), 0.4)],
+            hour(1).into(),
+            now,
+        );
+        history.save().unwrap();
+        // Unless they come from another source
+        let other = PriceHistory::open(path.clone(), "aWATTar").unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let totals: Vec<_> = history
+            .prices(hour(0).into())
+            .iter()
+            .map(|price| (price.starts_at, price.total.to_f64()))
+            .collect();
+        assert_eq!(totals, vec![(hour(1), 0.3), (hour(2), 0.4), (hour(3), 0.2)]);
+        assert_eq!(history.prices(hour(3).into()).len(), 1);
+        assert!(other.prices(hour(0).into()).is_empty());
+    }
+
+    #[test]
+    fn test_needs_update() {
+        let path = std::env::temp_dir().join("unused-prices.json");
+        let mut history = PriceHistory::open(path, "tibber").unwrap();
+        let midnight: DateTime<FixedOffset> = "2024-06-18T00:00:00+02:00".parse().unwrap();
+        let at = |hours: i64| (midnight + TimeDelta::hours(hours)).to_utc();
+        assert!(history.needs_update(at(0)));
+
+        // Today is known, tomorrow is due at noon
+        let today = (0..24).map(|hour| price(midnight + TimeDelta::hours(hour), 0.3));
+        history.merge(today.collect(), at(0), at(0));
+        assert!(!history.needs_update(at(1)));
+        assert!(!history.needs_update(at(11)));
+        assert!(history.needs_update(at(13)));
+
+        // Not published yet, asked again an hour later
+        history.merge(vec![], at(0), at(13));
+        assert!(!history.needs_update(at(13) + TimeDelta::minutes(30)));
+        assert!(history.needs_update(at(14)));
+
+        // Tomorrow is known
+        let tomorrow = (24..48).map(|hour| price(midnight + TimeDelta::hours(hour), 0.3));
+        history.merge(tomorrow.collect(), at(0), at(14));
+        assert!(!history.needs_update(at(15)));
+        assert!(!history.needs_update(at(35)));
+        assert!(history.needs_update(at(48)));
+    }
+}
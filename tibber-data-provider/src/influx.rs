@@ -0,0 +1,447 @@
+use anyhow::{anyhow, Context, Result};
+use energy_monitor_lib::{pulse::dto::MeterReading, tibber::dto::PriceInformation};
+use log::{debug, error, info, warn};
+use reqwest::Client;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    sync::mpsc,
+    time::{self, Duration},
+};
+
+const CHANNEL_SIZE: usize = 100;
+const DEFAULT_BATCH_SIZE: usize = 50;
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_SPOOL_PATH: &str = "/var/lib/emtibberd/influx.spool";
+const DEFAULT_MAX_SPOOL_SIZE: u64 = 16 * 1024 * 1024;
+/// Spooled lines are sent in batches of the size InfluxDB recommends
+const SPOOL_BATCH_SIZE: usize = 5000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Float(f64),
+    Integer(i64),
+    String(String),
+}
+
+/// A single point in the InfluxDB line protocol
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    measurement: String,
+    tags: BTreeMap<String, String>,
+    fields: Vec<(String, FieldValue)>,
+    /// Nanoseconds since the epoch
+    timestamp: i64,
+}
+
+impl Point {
+    pub fn new(measurement: &str) -> Self {
+        Self {
+            measurement: measurement.to_string(),
+            tags: BTreeMap::new(),
+            fields: vec![],
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as i64)
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn field(mut self, key: &str, value: FieldValue) -> Self {
+        self.fields.push((key.to_string(), value));
+        self
+    }
+
+    pub fn to_line(&self) -> String {
+        let mut line = escape(&self.measurement, &[',', ' ']);
+        for (key, value) in &self.tags {
+            let _ = write!(
+                line,
+                ",{}={}",
+                escape(key, &[',', '=', ' ']),
+                escape(value, &[',', '=', ' '])
+            );
+        }
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            line.push(if i == 0 { ' ' } else { ',' });
+            line.push_str(&escape(key, &[',', '=', ' ']));
+            line.push('=');
+            match value {
+                FieldValue::Float(v) => line.push_str(&v.to_string()),
+                FieldValue::Integer(v) => {
+                    let _ = write!(line, "{v}i");
+                }
+                FieldValue::String(v) => {
+                    let _ = write!(line, "\"{}\"", escape(v, &['"']));
+                }
+            }
+        }
+        let _ = write!(line, " {}", self.timestamp);
+        line
+    }
+}
+
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl From<&MeterReading> for Point {
+    fn from(reading: &MeterReading) -> Self {
+        let mut point = Point::new("meter")
+            .tag("meter_id", &reading.meter_id)
+            .field("power", FieldValue::Integer(reading.power as i64));
+        if let Some(import) = reading.import_wh {
+            point = point.field("import_wh", FieldValue::Float(import));
+        }
+        if let Some(export) = reading.export_wh {
+            point = point.field("export_wh", FieldValue::Float(export));
+        }
+        point
+    }
+}
+
+impl From<&PriceInformation> for Point {
+    fn from(price: &PriceInformation) -> Self {
+        Point::new("price")
+            .field("total", FieldValue::Float(price.total.to_f64()))
+            .tag("currency", price.total.currency.code())
+            .field("level", FieldValue::String(price.level.name().to_string()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InfluxConfig {
+    /// Base URL of the server, e.g. `http://localhost:8086`
+    pub url: String,
+    pub org: String,
+    pub bucket: String,
+    pub token: Option<String>,
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    /// Lines which could not be written are kept here until the server is back
+    pub spool_path: PathBuf,
+    /// Bytes the spool file may grow to, newer lines are dropped beyond
+    pub max_spool_size: u64,
+}
+
+impl InfluxConfig {
+    /// Returns `None` if `INFLUXDB_URL` is not set
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(url) = std::env::var("INFLUXDB_URL") else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            url,
+            org: std::env::var("INFLUXDB_ORG").context("INFLUXDB_ORG is not set")?,
+            bucket: std::env::var("INFLUXDB_BUCKET").context("INFLUXDB_BUCKET is not set")?,
+            token: std::env::var("INFLUXDB_TOKEN").ok(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            spool_path: std::env::var("INFLUXDB_SPOOL_PATH")
+                .unwrap_or(DEFAULT_SPOOL_PATH.to_string())
+                .into(),
+            max_spool_size: std::env::var("INFLUXDB_SPOOL_MAX_SIZE")
+                .ok()
+                .map(|size| size.parse())
+                .transpose()
+                .context("INFLUXDB_SPOOL_MAX_SIZE is not a number of bytes")?
+                .unwrap_or(DEFAULT_MAX_SPOOL_SIZE),
+        }))
+    }
+}
+
+/// Handle to the task writing points to InfluxDB
+#[derive(Clone)]
+pub struct InfluxExporter {
+    tx: mpsc::Sender<String>,
+    home_id: Arc<Mutex<Option<String>>>,
+}
+
+impl InfluxExporter {
+    pub fn spawn(config: InfluxConfig) -> Result<Self> {
+        let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
+        let writer = Writer {
+            client: Client::builder()
+                .timeout(Duration::from_secs(15))
+                .build()
+                .context("Failed to build HTTP client")?,
+            buffer: vec![],
+            config,
+        };
+        tokio::spawn(writer.run(rx));
+
+        Ok(Self {
+            tx,
+            home_id: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// The Tibber home ID is added as tag to all following points
+    pub fn set_home_id(&self, home_id: &str) {
+        *self.home_id.lock().unwrap() = Some(home_id.to_string());
+    }
+
+    pub async fn write(&self, point: Point) {
+        let point = match self.home_id.lock().unwrap().as_deref() {
+            Some(home_id) => point.tag("home_id", home_id),
+            None => point,
+        };
+        if let Err(e) = self.tx.send(point.to_line()).await {
+            error!("InfluxDB writer has stopped: {:?}", e);
+        }
+    }
+}
+
+struct Writer {
+    client: Client,
+    config: InfluxConfig,
+    buffer: Vec<String>,
+}
+
+impl Writer {
+    async fn run(mut self, mut rx: mpsc::Receiver<String>) {
+        let mut interval = time::interval(self.config.flush_interval);
+        loop {
+            tokio::select! {
+                line = rx.recv() => match line {
+                    Some(line) => {
+                        self.buffer.push(line);
+                        if self.buffer.len() >= self.config.batch_size {
+                            self.flush().await;
+                        }
+                    }
+                    None => {
+                        self.flush().await;
+                        return;
+                    }
+                },
+                _ = interval.tick() => self.flush().await,
+            }
+        }
+    }
+
+    /// Sends the spooled lines followed by the buffered ones. If this fails
+    /// the buffered lines are appended to the spool file.
+    async fn flush(&mut self) {
+        let result = match self.flush_spool().await {
+            Ok(()) if self.buffer.is_empty() => return,
+            Ok(()) => {
+                let mut body = String::new();
+                for line in &self.buffer {
+                    body.push_str(line);
+                    body.push('\n');
+                }
+                self.post(body).await
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => debug!("Wrote {} points to InfluxDB", self.buffer.len()),
+            Err(e) => {
+                warn!("Failed to write to InfluxDB, spooling points: {:?}", e);
+                if let Err(e) = self.spool().await {
+                    error!("Failed to spool points, dropping them: {:?}", e);
+                }
+            }
+        }
+        self.buffer.clear();
+    }
+
+    /// Sends the spool file in batches without reading it at once. If a batch
+    /// fails the lines not sent yet are kept.
+    async fn flush_spool(&self) -> Result<()> {
+        let file = match fs::File::open(&self.config.spool_path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut lines = BufReader::new(file).lines();
+        let mut sent = 0;
+        loop {
+            let mut body = String::new();
+            let mut count = 0;
+            while count < SPOOL_BATCH_SIZE {
+                let Some(line) = lines.next_line().await? else {
+                    break;
+                };
+                body.push_str(&line);
+                body.push('\n');
+                count += 1;
+            }
+            if count == 0 {
+                break;
+            }
+            if let Err(e) = self.post(body).await {
+                if sent > 0 {
+                    self.drop_spooled(sent).await?;
+                }
+                return Err(e);
+            }
+            sent += count;
+        }
+
+        if sent > 0 {
+            info!("Flushed {sent} spooled points to InfluxDB");
+        }
+        Ok(fs::remove_file(&self.config.spool_path).await?)
+    }
+
+    /// Removes the first `count` lines of the spool file
+    async fn drop_spooled(&self, count: usize) -> Result<()> {
+        let path = &self.config.spool_path;
+        let temporary = path.with_extension("tmp");
+        let mut lines = BufReader::new(fs::File::open(path).await?).lines();
+        let mut file = fs::File::create(&temporary).await?;
+        let mut skipped = 0;
+        while let Some(line) = lines.next_line().await? {
+            if skipped < count {
+                skipped += 1;
+                continue;
+            }
+            file.write_all(line.as_bytes()).await?;
+            file.write_all(b"\n").await?;
+        }
+        file.flush().await?;
+        Ok(fs::rename(temporary, path).await?)
+    }
+
+    async fn post(&self, body: String) -> Result<()> {
+        let mut request = self
+            .client
+            .post(format!("{}/api/v2/write", self.config.url))
+            .query(&[
+                ("org", self.config.org.as_str()),
+                ("bucket", self.config.bucket.as_str()),
+                ("precision", "ns"),
+            ])
+            .header(reqwest::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(body);
+        if let Some(token) = &self.config.token {
+            request = request.header(reqwest::header::AUTHORIZATION, format!("Token {token}"));
+        }
+
+        let response = request.send().await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(anyhow!("InfluxDB returned {}", response.status()))
+        }
+    }
+
+    async fn spool(&self) -> Result<()> {
+        let size = match fs::metadata(&self.config.spool_path).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        let added: usize = self.buffer.iter().map(|line| line.len() + 1).sum();
+        if size + added as u64 > self.config.max_spool_size {
+            return Err(anyhow!(
+                "The spool file reached {} bytes",
+                self.config.max_spool_size
+            ));
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.spool_path)
+            .await?;
+        for line in &self.buffer {
+            file.write_all(line.as_bytes()).await?;
+            file.write_all(b"\n").await?;
+        }
+        Ok(file.flush().await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use energy_monitor_lib::testing::HttpStub;
+
+    #[test]
+    fn test_line_protocol() {
+        let mut point = Point::new("my meter")
+            .tag("home_id", "a=b,c")
+            .field("power", FieldValue::Integer(-230))
+            .field("total", FieldValue::Float(0.25))
+            .field("level", FieldValue::String("say \"cheap\"".to_string()));
+        point.timestamp = 1_700_000_000_000_000_000;
+
+        assert_eq!(
+            point.to_line(),
+            r#"my\ meter,home_id=a\=b\,c power=-230i,total=0.25,level="say \"cheap\"" 1700000000000000000"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spool_and_flush_on_reconnect() {
+        let stub = HttpStub::start(503).await;
+        let spool_path =
+            std::env::temp_dir().join(format!("emtibberd-influx-{}.spool", std::process::id()));
+
+        let mut writer = Writer {
+            client: Client::new(),
+            config: InfluxConfig {
+                url: stub.url.clone(),
+                org: "home".to_string(),
+                bucket: "energy".to_string(),
+                token: None,
+                batch_size: 2,
+                flush_interval: DEFAULT_FLUSH_INTERVAL,
+                spool_path: spool_path.clone(),
+                max_spool_size: 40,
+            },
+            buffer: vec!["meter power=1i 1".to_string()],
+        };
+
+        // Server unavailable, the points end up on disk until it is full
+        writer.flush().await;
+        assert!(writer.buffer.is_empty());
+        writer.buffer.push("meter power=2i 2".to_string());
+        writer.flush().await;
+        writer.buffer.push("meter power=3i 3".to_string());
+        writer.flush().await;
+        assert_eq!(
+            fs::read_to_string(&spool_path).await.unwrap(),
+            "meter power=1i 1\nmeter power=2i 2\n"
+        );
+
+        // Sent lines are removed from the spool, the others are kept
+        writer.drop_spooled(1).await.unwrap();
+        assert_eq!(
+            fs::read_to_string(&spool_path).await.unwrap(),
+            "meter power=2i 2\n"
+        );
+
+        // Server is back, the spooled points are sent before the new ones
+        stub.set_status(204);
+        writer.buffer.push("meter power=4i 4".to_string());
+        writer.flush().await;
+        assert!(!spool_path.exists());
+
+        let bodies: Vec<_> = stub.requests().into_iter().map(|r| r.body).collect();
+        assert_eq!(bodies.len(), 5);
+        assert_eq!(bodies[3..], ["meter power=2i 2\n", "meter power=4i 4\n"]);
+    }
+}
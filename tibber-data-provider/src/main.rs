@@ -1,21 +1,48 @@
+use aggregate::SharedAggregator;
 use anyhow::{anyhow, Context};
+use chrono::{DateTime, DurationRound, FixedOffset, Local, TimeDelta, Utc};
 use energy_monitor_lib::{
-    pulse::{dto::Consumption, topics::PULSE_CONSUMPTION_TOPIC},
-    tibber::{dto, topics::TIBBER_PRICE_INFORMATION_TOPIC},
+    mqtt::{self, MqttClient, MqttConfig},
+    opendtu::topics::OPEN_DTU_AC_POWER_TOPIC,
+    price::{self, Classification, PriceProvider},
+    pulse::{
+        dto::{Consumption, MeterReading},
+        topics::PULSE_CONSUMPTION_TOPIC,
+    },
+    service::{self, Daemon},
+    tariff::{Pricing, Tariff},
+    tibber::{
+        dto,
+        topics::{TIBBER_PRICE_DETAILS_TOPIC, TIBBER_PRICE_INFORMATION_TOPIC},
+    },
 };
+use history::PriceHistory;
+use influx::{InfluxConfig, InfluxExporter, Point};
 use log::{debug, error, info};
+use metrics::{record_tibber_call, METRICS};
+use price_loader::Provider;
 use reqwest::Client;
-use rumqttc::{AsyncClient, MqttOptions, QoS};
+use rumqttc::{Event, Packet, QoS};
 use sml_rs::parser::{
-    common::Value,
+    common::{ListEntry, Value},
     complete::{parse, MessageBody},
 };
 use sml_rs::transport::decode;
-use std::error::Error;
+use std::{error::Error, fmt, fs, future::Future, net::SocketAddr};
 use syslog::{Facility, Formatter3164};
-use tibber_loader::{config::Config, gql::queries::PriceLevel};
-use tokio::{task, time::Duration};
+use tibber_loader::{config::Config, errors::TibberLoaderError};
+use tokio::{
+    task,
+    time::{timeout, Duration},
+};
 use tokio_cron_scheduler::{Job, JobScheduler};
+use tokio_util::sync::CancellationToken;
+
+mod aggregate;
+mod history;
+mod influx;
+mod metrics;
+mod records;
 
 const TIBBER_API_URL: &str = "https://api.tibber.com/v1-beta/gql";
 const PULSE_BRIDGE_URL: &str = "http://192.168.100.60/data.json?node_id=1";
@@ -23,13 +50,19 @@ const MQTT_CLIENT_NAME: &str = "tibber_bridge_data_provider";
 const MQTT_BROKER_ADDRESS: &str = "rpiserver";
 const MQTT_BROKER_PORT: u16 = 1883;
 const PULSE_BRIDGE_USERNAME: &str = "admin";
+const METRICS_ADDRESS_ENV: &str = "EMTIBBERD_METRICS_ADDRESS";
+const TARIFF_ENV: &str = "EMTIBBERD_TARIFF";
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+const DAEMON_NAME: &str = "emtibberd";
+/// Delay after the start of a price period before the price is fetched
+const PRICE_SWITCH_DELAY: Duration = Duration::from_secs(2);
 
 #[tokio::main()]
 async fn main() -> Result<(), Box<dyn Error>> {
     let formatter = Formatter3164 {
         facility: Facility::LOG_DAEMON,
         hostname: None,
-        process: "emtibberd".into(),
+        process: DAEMON_NAME.into(),
         pid: 0,
     };
 
@@ -41,39 +74,141 @@ async fn main() -> Result<(), Box<dyn Error>> {
         env!("CARGO_PKG_VERSION")
     );
 
-    // Check if Tibber API key env variable is set
+    let tariff = match load_tariff() {
+        Ok(tariff) => tariff,
+        Err(e) => {
+            error!("Failed to load the tariff: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Day-ahead prices are net prices, the spot tariff adds the surcharges and VAT
+    let provider = match Provider::from_env() {
+        Ok(provider) => provider,
+        Err(e) => {
+            error!("Failed to configure the price provider: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(provider) = &provider {
+        if !matches!(tariff.pricing, Pricing::Spot { .. }) {
+            error!("The prices of {provider} need a spot tariff");
+            std::process::exit(1);
+        }
+    }
+
+    // Check if Tibber API key env variable is set if the price comes from Tibber
     // In case of failsure, fail early
-    if Config::new(TIBBER_API_URL).is_err() {
-        error!("Failed to load tibber config. Check if TIBBER_API_TOKEN is set");
+    if tariff.needs_market_price() && provider.is_none() {
+        if let Err(e) = Config::new(TIBBER_API_URL) {
+            error!("Failed to load tibber config: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+    if pulse_bridge_password().is_err() {
+        error!("PULSE_BRIDGE_PASSWORD is not set or empty");
         std::process::exit(1);
     }
 
-    let sched = JobScheduler::new().await?;
-    let mut handles = Vec::new();
+    let mut sched = JobScheduler::new().await?;
+
+    // The metrics endpoint is only started if an address is configured
+    if let Ok(address) = std::env::var(METRICS_ADDRESS_ENV) {
+        let address: SocketAddr = address
+            .parse()
+            .map_err(|e| format!("{METRICS_ADDRESS_ENV} is invalid: {e}"))?;
+        info!("Serving metrics on http://{address}/metrics");
+        task::spawn(async move {
+            if let Err(e) =
+                energy_monitor_lib::metrics::serve(address, METRICS.common.clone()).await
+            {
+                error!("Metrics endpoint failed: {:?}", e);
+            }
+        });
+    }
 
-    let mut mqttoptions = MqttOptions::new(MQTT_CLIENT_NAME, MQTT_BROKER_ADDRESS, MQTT_BROKER_PORT);
-    mqttoptions.set_keep_alive(Duration::from_secs(10));
+    // Measurements are only written to InfluxDB if INFLUXDB_URL is set
+    let influx = InfluxConfig::from_env()?
+        .map(InfluxExporter::spawn)
+        .transpose()?;
+
+    // Daily and monthly totals continue where the last run stopped
+    let aggregator = SharedAggregator::load()?;
+    aggregator.update(|aggregator| {
+        aggregator.set_feed_in_price(tariff.feed_in());
+        aggregator.set_base_fee(tariff.base_fee());
+    });
+    // Market prices are kept until the next day is published, Tibber's
+    // are classified together with the ones fetched before
+    let source = match provider {
+        Some(provider) => Source::Market(provider),
+        None => Source::Tibber(None),
+    };
+    let history = PriceHistory::load(&source.to_string())?;
 
-    let (client, mut eventloop) = AsyncClient::new(mqttoptions, 5);
+    // The broker, credentials and TLS can be changed through EMTIBBERD_MQTT_*
+    let mut mqtt_config = MqttConfig::new(MQTT_CLIENT_NAME, MQTT_BROKER_ADDRESS, MQTT_BROKER_PORT);
+    mqtt_config.keep_alive = Duration::from_secs(10);
+    mqtt_config.load_env("EMTIBBERD")?;
+    let daemon = Daemon::new(DAEMON_NAME, env!("CARGO_PKG_VERSION"));
+    let (client, mut supervisor) = mqtt::connect(&mqtt_config, &daemon)?;
 
     let pulse_bridge_client = client.clone();
     let tibber_client = client.clone();
+    let aggregate_client = client.clone();
+    let pulse_bridge_influx = influx.clone();
+    let tibber_influx = influx.clone();
+    let pulse_bridge_aggregator = aggregator.clone();
+    let tibber_aggregator = aggregator.clone();
+    let event_loop_aggregator = aggregator.clone();
+    let shutdown_aggregator = aggregator.clone();
+
+    // The PV production is taken from OpenDTU for the daily totals
+    client
+        .subscribe(OPEN_DTU_AC_POWER_TOPIC.name(), QoS::AtMostOnce)
+        .await?;
 
-    // When first stating the application, we want to fetch the current price
-    if let Err(e) = get_tibber_data_and_publish(&client.clone()).await {
-        error!("Failed to retive Tibber current price info: {:?}", e);
-    } else {
-        info!("Successfully retrieved and published Tibber current price info");
-    }
+    // Runs until the client disconnected on shutdown, reconnects if the
+    // broker goes away
+    let event_loop = task::spawn(async move {
+        while let Some(event) = supervisor.poll().await {
+            match event {
+                Event::Incoming(Packet::ConnAck(_)) => service::notify("READY=1"),
+                Event::Incoming(Packet::Publish(publish))
+                    if publish.topic == OPEN_DTU_AC_POWER_TOPIC.name() =>
+                {
+                    match OPEN_DTU_AC_POWER_TOPIC.decode(&publish.payload) {
+                        Ok(power) => {
+                            if let Err(e) = event_loop_aggregator.update(|aggregator| {
+                                aggregator.add_production(Utc::now(), power as f64)
+                            }) {
+                                error!("Production not added to the totals: {e}");
+                            }
+                        }
+                        Err(e) => error!("Invalid production {:?}", e),
+                    }
+                }
+                _ => {}
+            }
+        }
+    });
 
     // This job runs every 10 seconds and retrieves the current power consumption
     // from the Pulse Bridge
     let mut pulse_bridge_job = Job::new_async("1/10 * * * * *", move |_, _| {
         let publish_client_tibber_data = pulse_bridge_client.clone();
+        let influx = pulse_bridge_influx.clone();
+        let aggregator = pulse_bridge_aggregator.clone();
 
         Box::pin(async move {
-            if let Err(e) = get_pulse_bridge_data_and_publish(&publish_client_tibber_data).await {
-                error!("Failed Tibber API job: {:?}", e);
+            if let Err(e) = get_pulse_bridge_data_and_publish(
+                &publish_client_tibber_data,
+                influx.as_ref(),
+                &aggregator,
+            )
+            .await
+            {
+                error!("Failed Pulse Bridge job: {:?}", e);
             }
         })
     })?;
@@ -92,138 +227,324 @@ async fn main() -> Result<(), Box<dyn Error>> {
         )
         .await?;
 
-    let mut tibber_job = Job::new_async("0 2 * * * *", move |_, _| {
-        let publish_client_tibber_data = tibber_client.clone();
+    // Publishes the daily and monthly totals every minute
+    let aggregate_job = Job::new_async("30 * * * * *", move |_, _| {
+        let client = aggregate_client.clone();
+        let aggregator = aggregator.clone();
         Box::pin(async move {
-            if let Err(e) = get_tibber_data_and_publish(&publish_client_tibber_data).await {
-                error!("Failed Pulse Bridge job: {:?}", e);
+            if let Err(e) = aggregator.publish_and_save(&client).await {
+                error!("Failed aggregate job: {:?}", e);
             }
         })
     })?;
 
-    tibber_job
-        .on_stop_notification_add(
-            &sched,
-            Box::new(|job_id, notification_id, type_of_notification| {
-                Box::pin(async move {
-                    info!(
-                        "Job {:?} was completed, notification {:?} ran ({:?})",
-                        job_id, notification_id, type_of_notification
-                    );
-                })
-            }),
-        )
-        .await?;
-
     sched.add(pulse_bridge_job).await?;
-    sched.add(tibber_job).await?;
+    sched.add(aggregate_job).await?;
     sched.start().await?;
 
-    handles.push(task::spawn(async move {
-        loop {
-            if let Err(e) = eventloop.poll().await {
-                // In case of an error stop event loop and terminate task
-                // this will result in aborting the program
-                error!("Error MQTT Event loop returned: {:?}", e);
-                break;
+    // The price is published at startup and whenever a new price period
+    // starts, hourly or quarter-hourly depending on TIBBER_PRICE_RESOLUTION
+    let token = CancellationToken::new();
+    let tibber_task = task::spawn(run_tibber_job(
+        tibber_client,
+        tibber_influx,
+        tibber_aggregator,
+        tariff,
+        source,
+        history,
+        token.clone(),
+    ));
+
+    let watchdog_client = client.clone();
+    service::spawn_watchdog(token.clone(), move |period| {
+        watchdog_client.is_active(period)
+    });
+
+    service::shutdown_signal().await?;
+    info!("Shutting down");
+    service::notify("STOPPING=1");
+    token.cancel();
+
+    sched.shutdown().await?;
+    tibber_task.await?;
+    // Keep the totals of the last minute
+    if let Err(e) = shutdown_aggregator.publish_and_save(&client).await {
+        error!("Failed to save the totals: {:?}", e);
+    }
+    if let Err(e) = client.disconnect().await {
+        error!("Failed to disconnect: {:?}", e);
+    }
+    if timeout(SHUTDOWN_TIMEOUT, event_loop).await.is_err() {
+        error!("Timeout flushing the MQTT messages");
+    }
+    Ok(())
+}
+
+/// Publishes the current price and sleeps until the next price period starts
+async fn run_tibber_job(
+    client: MqttClient,
+    influx: Option<InfluxExporter>,
+    aggregator: SharedAggregator,
+    tariff: Tariff,
+    mut source: Source,
+    mut history: PriceHistory,
+    token: CancellationToken,
+) {
+    loop {
+        let ends_at = match get_tibber_data_and_publish(
+            &client,
+            influx.as_ref(),
+            &aggregator,
+            &tariff,
+            &mut source,
+            &mut history,
+        )
+        .await
+        {
+            Ok(ends_at) => {
+                info!("Successfully published the current price");
+                ends_at
             }
-        }
-    }));
+            Err(e) => {
+                error!("Failed Tibber API job: {:?}", e);
+                None
+            }
+        };
 
-    // In case any of the tasks panic abort the program
-    for handle in handles {
-        if let Err(e) = handle.await {
-            error!("Task panicked: {:?}", e);
-            std::process::exit(1);
+        let delay = next_price_delay(Utc::now(), ends_at);
+        debug!("Fetching the next price in {:?}", delay);
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = token.cancelled() => break,
         }
     }
-    Ok(())
 }
 
+/// Time until the next price period starts, if the end of the current one
+/// is unknown the next quarter hour is assumed. The API is asked a few
+/// seconds later to be sure it switched to the new price.
+fn next_price_delay(now: DateTime<Utc>, ends_at: Option<DateTime<FixedOffset>>) -> Duration {
+    let next = ends_at
+        .map(|ends_at| ends_at.with_timezone(&Utc))
+        .filter(|ends_at| *ends_at > now)
+        .unwrap_or_else(|| {
+            let quarter = TimeDelta::minutes(15);
+            now.duration_trunc(quarter).unwrap_or(now) + quarter
+        });
+    (next - now).to_std().unwrap_or_default() + PRICE_SWITCH_DELAY
+}
+
+/// Loads the tariff from the TOML file in `EMTIBBERD_TARIFF`, the Tibber
+/// prices are used as they are if it is not set
+fn load_tariff() -> Result<Tariff, anyhow::Error> {
+    let Ok(path) = std::env::var(TARIFF_ENV) else {
+        return Ok(Tariff::default());
+    };
+    let tariff = fs::read_to_string(&path).with_context(|| format!("Failed to read {path}"))?;
+    toml::from_str(&tariff).with_context(|| format!("Invalid tariff {path}"))
+}
+
+/// Publishes the current price of the tariff, returns when the next price
+/// period starts
 async fn get_tibber_data_and_publish(
-    publish_client_tibber_data: &AsyncClient,
-) -> Result<(), anyhow::Error> {
-    println!("Executing Tibber job");
-    let config = Config::new(TIBBER_API_URL)?;
+    publish_client_tibber_data: &MqttClient,
+    influx: Option<&InfluxExporter>,
+    aggregator: &SharedAggregator,
+    tariff: &Tariff,
+    source: &mut Source,
+    history: &mut PriceHistory,
+) -> Result<Option<DateTime<FixedOffset>>, anyhow::Error> {
+    let now = Utc::now();
+    let details = match (tariff.price_at(&Local::now()), source) {
+        (Some(details), _) => details,
+        (None, Source::Market(provider)) => {
+            // Classified like Tibber does unless the tariff defines the levels
+            let levels = tariff.levels.clone().unwrap_or_default();
+            get_day_ahead_price(provider, tariff, Some(&levels), history, now).await?
+        }
+        (None, Source::Tibber(session)) => {
+            // Tibber's own levels are kept unless the tariff defines them
+            let session = tibber_session(session, influx).await?;
+            get_day_ahead_price(session, tariff, tariff.levels.as_ref(), history, now).await?
+        }
+    };
+    info!("Current price: {}", details.total);
+    info!("Price Level: {:?}", details.level);
+
+    let price_information = details.information();
+    METRICS.common.price_total.set(details.total.to_f64());
+    if let Err(e) = aggregator.start_period(&details) {
+        error!(
+            "Failed to write the record of the last price period: {:?}",
+            e
+        );
+    }
+    METRICS.common.set_price_level(&price_information.level);
+    if let Some(influx) = influx {
+        influx.write(Point::from(&price_information)).await;
+    }
 
-    let session = tibber_loader::Session::new(config)
+    TIBBER_PRICE_INFORMATION_TOPIC
+        .publish(publish_client_tibber_data, &price_information)
         .await
-        .context("Failed to create Tibber API session")?;
+        .inspect_err(|_| record_publish_failure(TIBBER_PRICE_INFORMATION_TOPIC.name()))
+        .context("Failed to publish current price Tibber message")?;
+    TIBBER_PRICE_DETAILS_TOPIC
+        .publish(publish_client_tibber_data, &details)
+        .await
+        .inspect_err(|_| record_publish_failure(TIBBER_PRICE_DETAILS_TOPIC.name()))
+        .context("Failed to publish Tibber price details")?;
+    Ok(Some(details.ends_at()))
+}
 
-    // Try to get the current price 3 times. If this is not successful,
-    // we will try again in the next run of the job
-    let mut retry_cnt = 0;
-    loop {
-        match session
-            .get_current_price()
-            .await
-            .context("Failed to get current price from Tibber API")
-        {
-            Ok(Some(price)) => {
-                info!("Current price: {:?} Euro", price.total);
-                info!("Price Level: {:?}", price.level);
-
-                let price_information = dto::PriceInformation {
-                    total: price.total as f32,
-                    level: match price.level {
-                        PriceLevel::Cheap => dto::PriceLevel::Cheap,
-                        PriceLevel::Expensive => dto::PriceLevel::Expensive,
-                        PriceLevel::Normal => dto::PriceLevel::Normal,
-                        PriceLevel::VeryCheap => dto::PriceLevel::VeryCheap,
-                        PriceLevel::VeryExpensive => dto::PriceLevel::VeryExpensive,
-                        PriceLevel::None => dto::PriceLevel::None,
-                        _ => dto::PriceLevel::None,
-                    },
-                };
-
-                return publish_client_tibber_data
-                    .publish(
-                        TIBBER_PRICE_INFORMATION_TOPIC.name(),
-                        QoS::AtMostOnce,
-                        false,
-                        TIBBER_PRICE_INFORMATION_TOPIC.encode(&price_information),
-                    )
-                    .await
-                    .context("Failed to publish current price Tibber message");
+/// Where the day-ahead prices come from
+enum Source {
+    Market(Provider),
+    /// The session is created on first use
+    Tibber(Option<TibberPrices>),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Market(provider) => write!(f, "{provider}"),
+            Source::Tibber(_) => write!(f, "Tibber"),
+        }
+    }
+}
+
+/// The Tibber API as price provider, the calls are counted in the metrics
+struct TibberPrices(tibber_loader::Session);
+
+impl PriceProvider for TibberPrices {
+    type Error = TibberLoaderError;
+
+    async fn prices(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<dto::PriceDetails>, Self::Error> {
+        record_tibber_call(self.0.prices(from, to).await)
+    }
+}
+
+/// Creates the Tibber API session on first use
+async fn tibber_session<'a>(
+    session: &'a mut Option<TibberPrices>,
+    influx: Option<&InfluxExporter>,
+) -> Result<&'a TibberPrices, anyhow::Error> {
+    match session {
+        Some(session) => Ok(session),
+        None => {
+            let config = Config::new(TIBBER_API_URL)?;
+            let created = record_tibber_call(tibber_loader::Session::new(config).await)
+                .context("Failed to create Tibber API session")?;
+            if let Some(influx) = influx {
+                influx.set_home_id(&created.home_id().0);
             }
-            Ok(None) => {
-                info!("No price information available");
+            Ok(session.insert(TibberPrices(created)))
+        }
+    }
+}
+
+/// Current end-customer price from the day-ahead prices of `provider`. They
+/// are fetched again once the next day is due, the prices before are kept
+/// for the levels. Without levels the ones of the provider are kept.
+async fn get_day_ahead_price<P: PriceProvider>(
+    provider: &P,
+    tariff: &Tariff,
+    levels: Option<&Classification>,
+    history: &mut PriceHistory,
+    now: DateTime<Utc>,
+) -> Result<dto::PriceDetails, anyhow::Error> {
+    // The whole day is kept for percentiles
+    let from = now - levels.map_or(TimeDelta::days(1), Classification::history);
+    if history.needs_update(now) {
+        let to = now + TimeDelta::days(1);
+        let fetch = || async move {
+            provider
+                .prices(from, to)
+                .await
+                .context("Failed to get the day-ahead prices")
+        };
+        // Only retried if the current price is unknown
+        let known = history.covers(now);
+        let fetched = if known {
+            fetch().await
+        } else {
+            retry(fetch).await
+        };
+        match fetched {
+            Ok(prices) => {
+                history.merge(prices, from, now);
+                if let Err(e) = history.save() {
+                    error!("Failed to save the price history: {:?}", e);
+                }
             }
-            Err(e) => {
-                error!("Failed to get current price: {:?}", e);
+            Err(e) if known => {
+                error!("Keeping the known prices: {:?}", e);
+                // Asked again after the refresh interval
+                history.merge(Vec::new(), from, now);
             }
+            Err(e) => return Err(e),
+        }
+    }
+    current_price(history.prices(from), tariff, levels, now)
+}
+
+/// Applies the tariff to the market prices and classifies them, the rest of
+/// the day is needed for percentiles
+fn current_price(
+    prices: Vec<dto::PriceDetails>,
+    tariff: &Tariff,
+    levels: Option<&Classification>,
+    now: DateTime<Utc>,
+) -> Result<dto::PriceDetails, anyhow::Error> {
+    let mut prices = prices
+        .into_iter()
+        .map(|price| tariff.apply(price))
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to apply the tariff")?;
+    if let Some(levels) = levels {
+        levels.classify(&mut prices);
+    }
+    price::current(&prices, now.fixed_offset())
+        .cloned()
+        .ok_or_else(|| anyhow!("No price for {now}"))
+}
+
+/// Tries to get the prices 3 times. If this is not successful, we will try
+/// again in the next run of the job
+async fn retry<T, F, R>(mut call: F) -> Result<T, anyhow::Error>
+where
+    F: FnMut() -> R,
+    R: Future<Output = Result<T, anyhow::Error>>,
+{
+    let mut retry_cnt = 0;
+    loop {
+        match call().await {
+            Ok(result) => return Ok(result),
+            Err(e) => error!("Failed to get the prices: {:?}", e),
         }
         retry_cnt += 1;
         if retry_cnt == 3 {
-            break;
+            return Err(anyhow!("Failed to get the prices {retry_cnt} times"));
         }
         tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
-        debug!("Retrying to get current price from Tibber API count ={retry_cnt}");
-    }
-    if retry_cnt == 3 {
-        Err(anyhow::anyhow!(
-            "Failed to get current price from Tibber API"
-        ))
-    } else {
-        Ok(())
+        debug!("Retrying to get the prices count ={retry_cnt}");
     }
 }
 
 async fn get_pulse_bridge_data_and_publish(
-    publish_client_tibber_data: &AsyncClient,
+    publish_client_tibber_data: &MqttClient,
+    influx: Option<&InfluxExporter>,
+    aggregator: &SharedAggregator,
 ) -> Result<(), anyhow::Error> {
     info!("Executing data fetch from Pulse Bridge job");
 
-    let pulse_bridge_pwd = std::env::var("PULSE_BRIDGE_PASSWORD")
-        .context("PULSE_BRIDGE_PASSWORD is not set")
-        .and_then(|pwd| {
-            if pwd.is_empty() {
-                error!("PULSE_BRIDGE_PASSWORD is empty");
-                std::process::exit(1);
-            } else {
-                Ok(pwd)
-            }
-        })?;
+    let pulse_bridge_pwd = pulse_bridge_password()?;
+
+    let fetch_timer = METRICS.pulse_bridge_fetch_duration.start_timer();
 
     let bridge_client = Client::builder()
         .timeout(Duration::from_secs(15))
@@ -242,8 +563,45 @@ async fn get_pulse_bridge_data_and_publish(
     while let Some(chunk) = resp.chunk().await? {
         buffer.extend_from_slice(&chunk);
     }
+    fetch_timer.observe_duration();
+
+    let reading = decode_meter_reading(&buffer).inspect_err(|_| METRICS.sml_decode_errors.inc())?;
+    info!("Power = {}W", reading.power);
+    METRICS.common.consumption.set(reading.power as i64);
+    if let Err(e) =
+        aggregator.update(|aggregator| aggregator.add_meter_reading(Utc::now(), &reading))
+    {
+        error!("Cost of the consumption not added to the totals: {e}");
+    }
+    if let Some(influx) = influx {
+        influx.write(Point::from(&reading)).await;
+    }
 
-    let result = decode(buffer.to_vec());
+    PULSE_CONSUMPTION_TOPIC
+        .publish(
+            publish_client_tibber_data,
+            &Consumption {
+                consumption: reading.power,
+            },
+        )
+        .await
+        .inspect_err(|_| record_publish_failure(PULSE_CONSUMPTION_TOPIC.name()))
+        .context("Failed to publish current consumption message")
+}
+
+fn pulse_bridge_password() -> Result<String, anyhow::Error> {
+    std::env::var("PULSE_BRIDGE_PASSWORD")
+        .context("PULSE_BRIDGE_PASSWORD is not set")
+        .and_then(|pwd| match pwd.is_empty() {
+            true => Err(anyhow!("PULSE_BRIDGE_PASSWORD is empty")),
+            false => Ok(pwd),
+        })
+}
+
+/// Extracts the current power (OBIS 1-0:16.7.0) and the energy counters
+/// (1-0:1.8.0, 1-0:2.8.0) from the SML data of the Pulse Bridge
+fn decode_meter_reading(buffer: &[u8]) -> Result<MeterReading, anyhow::Error> {
+    let result = decode(buffer);
     if result.is_empty() {
         return Err(anyhow!("Failed to decode pulse bridge message"));
     }
@@ -260,30 +618,30 @@ async fn get_pulse_bridge_data_and_publish(
     } else {
         match &result.messages[1].message_body {
             MessageBody::GetListResponse(le) => {
+                let mut power = None;
+                let mut import_wh = None;
+                let mut export_wh = None;
                 for entry in &le.val_list {
-                    // Current power consumption
-                    if entry.obj_name == [1, 0, 16, 7, 0, 255] {
-                        let current_power = match entry.value {
-                            Value::I32(v) => v,
-                            _ => 0,
-                        };
-                        info!("Power = {current_power}W");
-
-                        let res = publish_client_tibber_data
-                            .publish(
-                                PULSE_CONSUMPTION_TOPIC.name(),
-                                QoS::AtMostOnce,
-                                false,
-                                PULSE_CONSUMPTION_TOPIC.encode(&Consumption {
-                                    consumption: current_power,
-                                }),
-                            )
-                            .await
-                            .context("Failed to publish current consumption message");
-                        return res;
+                    match entry.obj_name {
+                        // Current power consumption
+                        [1, 0, 16, 7, 0, 255] => {
+                            power = Some(match entry.value {
+                                Value::I32(v) => v,
+                                _ => 0,
+                            })
+                        }
+                        [1, 0, 1, 8, 0, 255] => import_wh = scaled_value(entry),
+                        [1, 0, 2, 8, 0, 255] => export_wh = scaled_value(entry),
+                        _ => {}
                     }
                 }
-                Err(anyhow!("No power consumption data in pluse bridge data"))
+                Ok(MeterReading {
+                    meter_id: hex::encode(le.server_id),
+                    power: power
+                        .ok_or_else(|| anyhow!("No power consumption data in pluse bridge data"))?,
+                    import_wh,
+                    export_wh,
+                })
             }
             _ => Err(anyhow!(
                 "Wrong structure in pulse bridge data expected ListEntry missing"
@@ -291,3 +649,207 @@ async fn get_pulse_bridge_data_and_publish(
         }
     }
 }
+
+fn scaled_value(entry: &ListEntry) -> Option<f64> {
+    let value = match entry.value {
+        Value::I8(v) => v as f64,
+        Value::I16(v) => v as f64,
+        Value::I32(v) => v as f64,
+        Value::I64(v) => v as f64,
+        Value::U8(v) => v as f64,
+        Value::U16(v) => v as f64,
+        Value::U32(v) => v as f64,
+        Value::U64(v) => v as f64,
+        _ => return None,
+    };
+    Some(value * 10f64.powi(entry.scaler.unwrap_or(0) as i32))
+}
+
+fn record_publish_failure(topic: &str) {
+    METRICS
+        .common
+        .mqtt_publish_failures
+        .with_label_values(&[topic])
+        .inc();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use energy_monitor_lib::money::{Currency, Money};
+
+    #[test]
+    fn test_next_price_delay() {
+        let now: DateTime<Utc> = "2025-10-01T10:07:30Z".parse().unwrap();
+        let ends_at = "2025-10-01T13:00:00+02:00".parse().unwrap();
+        assert_eq!(
+            next_price_delay(now, Some(ends_at)),
+            Duration::from_secs(52 * 60 + 30 + 2)
+        );
+
+        // Unknown or already passed, the next quarter hour is used
+        assert_eq!(
+            next_price_delay(now, None),
+            Duration::from_secs(7 * 60 + 30 + 2)
+        );
+        let passed = "2025-10-01T12:00:00+02:00".parse().unwrap();
+        assert_eq!(
+            next_price_delay(now, Some(passed)),
+            Duration::from_secs(7 * 60 + 30 + 2)
+        );
+    }
+
+    #[test]
+    fn test_example_tariff() {
+        let tariff: Tariff = toml::from_str(include_str!("../tariff.example.toml")).unwrap();
+        let eur = |amount| Money::from_f64(amount, Currency::EUR).unwrap();
+        assert_eq!(tariff.base_fee(), Some(eur(12.5)));
+        assert_eq!(tariff.feed_in(), Some(eur(0.082)));
+        assert!(!tariff.needs_market_price());
+
+        let evening = "2025-10-01T22:30:00+02:00"
+            .parse::<DateTime<FixedOffset>>()
+            .unwrap();
+        let details = tariff.price_at(&evening).unwrap();
+        assert_eq!(details.total, eur(0.24));
+        assert_eq!(details.information().ends_at(), Some(details.ends_at()));
+        assert_eq!(details.ends_at().to_rfc3339(), "2025-10-02T06:00:00+02:00");
+
+        let tariff: Tariff = toml::from_str(
+            r#"
+            [pricing]
+            type = "tibber"
+
+            [levels]
+            method = "average"
+            window = 24
+            thresholds = { very_cheap = 0.6, cheap = 0.9, expensive = 1.15, very_expensive = 1.4 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(tariff.levels.unwrap().history(), TimeDelta::hours(24));
+    }
+
+    #[tokio::test]
+    async fn test_day_ahead_price() {
+        // 3 days at 100 EUR/MWh, then an hour at 20 EUR/MWh
+        let start: DateTime<Utc> = "2024-06-18T00:00:00Z".parse().unwrap();
+        let mut file = "starts_at,ends_at,price\n".to_string();
+        for hour in 0..73 {
+            let starts_at = start + TimeDelta::hours(hour);
+            let price = if hour < 72 { 100 } else { 20 };
+            file += &format!(
+                "{},{},{price}\n",
+                starts_at.to_rfc3339(),
+                (starts_at + TimeDelta::hours(1)).to_rfc3339()
+            );
+        }
+        let path = std::env::temp_dir().join(format!("day-ahead-{}.csv", std::process::id()));
+        fs::write(&path, file).unwrap();
+        let provider = price_loader::file::FileProvider::new(&path);
+        let tariff: Tariff =
+            toml::from_str("[pricing]\ntype = \"spot\"\nsurcharge = 0.18\nvat = 19").unwrap();
+
+        let history_path =
+            std::env::temp_dir().join(format!("day-ahead-{}.json", std::process::id()));
+        let mut history = PriceHistory::open(history_path.clone(), "file").unwrap();
+        let levels = Some(&Classification::default());
+
+        let now = start + TimeDelta::minutes(72 * 60 + 10);
+        let price = get_day_ahead_price(&provider, &tariff, levels, &mut history, now)
+            .await
+            .unwrap();
+        // The file is only read again once the known prices run out
+        fs::write(&path, "starts_at,ends_at,price\n").unwrap();
+        let cached = get_day_ahead_price(
+            &provider,
+            &tariff,
+            levels,
+            &mut history,
+            now + TimeDelta::minutes(20),
+        )
+        .await
+        .unwrap();
+        let later = get_day_ahead_price(
+            &provider,
+            &tariff,
+            levels,
+            &mut history,
+            now + TimeDelta::hours(1),
+        )
+        .await;
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&history_path).unwrap();
+
+        let eur = |amount| Money::from_f64(amount, Currency::EUR).unwrap();
+        // (0.02 + 0.18) * 1.19 compared to (0.10 + 0.18) * 1.19
+        assert_eq!(price.total, eur(0.238));
+        assert_eq!(price.energy, eur(0.02));
+        assert_eq!(price.level, dto::PriceLevel::Cheap);
+        assert_eq!(price.ends_at(), start + TimeDelta::hours(73));
+        assert_eq!(cached, price);
+        assert!(later.is_err());
+    }
+
+    #[test]
+    fn test_tibber_levels() {
+        let tariff: Tariff = toml::from_str(
+            r#"
+            [pricing]
+            type = "tibber"
+
+            [levels]
+            method = "percentile"
+            thresholds = { very_cheap = 0.1, cheap = 0.3, expensive = 0.7, very_expensive = 0.9 }
+            "#,
+        )
+        .unwrap();
+        let levels = tariff.levels.clone().unwrap();
+
+        // Tibber calls the whole day normal, 0.20 to 0.43 EUR over the day
+        let start: DateTime<FixedOffset> = "2024-06-21T00:00:00+02:00".parse().unwrap();
+        let eur = |amount| Money::from_f64(amount, Currency::EUR).unwrap();
+        let prices: Vec<_> = (0..24)
+            .map(|hour| dto::PriceDetails {
+                total: eur(0.2 + hour as f64 / 100.0),
+                energy: eur(0.1 + hour as f64 / 100.0),
+                tax: eur(0.1),
+                level: dto::PriceLevel::Normal,
+                starts_at: start + TimeDelta::hours(hour),
+                duration: 3600,
+            })
+            .collect();
+        let at = |hour: i64| (start + TimeDelta::minutes(hour * 60 + 30)).to_utc();
+
+        let cheapest = current_price(prices.clone(), &tariff, Some(&levels), at(0)).unwrap();
+        assert_eq!(cheapest.total, eur(0.2));
+        assert_eq!(cheapest.tax, eur(0.1));
+        assert_eq!(cheapest.level, dto::PriceLevel::VeryCheap);
+        let level = |hour| {
+            current_price(prices.clone(), &tariff, Some(&levels), at(hour))
+                .unwrap()
+                .level
+        };
+        // Tibber's levels are kept without a classification
+        assert_eq!(
+            current_price(prices.clone(), &tariff, None, at(0))
+                .unwrap()
+                .level,
+            dto::PriceLevel::Normal
+        );
+        assert_eq!(level(6), dto::PriceLevel::Cheap);
+        assert_eq!(level(12), dto::PriceLevel::Normal);
+        assert_eq!(level(18), dto::PriceLevel::Expensive);
+        assert_eq!(level(23), dto::PriceLevel::VeryExpensive);
+
+        let unordered = r#"
+            [pricing]
+            type = "tibber"
+
+            [levels]
+            method = "average"
+            thresholds = { very_cheap = 0.9, cheap = 0.6, expensive = 1.15, very_expensive = 1.4 }
+            "#;
+        assert!(toml::from_str::<Tariff>(unordered).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use energy_monitor_lib::metrics::Metrics;
+use prometheus::{Histogram, HistogramOpts, IntCounter, IntCounterVec, Opts};
+use std::sync::LazyLock;
+use tibber_loader::errors::TibberLoaderError;
+
+pub struct TibberMetrics {
+    pub common: Metrics,
+    pub tibber_api_calls: IntCounter,
+    /// Failed calls by `TibberLoaderError` variant
+    pub tibber_api_failures: IntCounterVec,
+    pub pulse_bridge_fetch_duration: Histogram,
+    pub sml_decode_errors: IntCounter,
+}
+
+pub static METRICS: LazyLock<TibberMetrics> = LazyLock::new(|| {
+    let common = Metrics::new("emtibberd");
+    let metrics = TibberMetrics {
+        tibber_api_calls: IntCounter::new("tibber_api_calls_total", "Calls to the Tibber API")
+            .unwrap(),
+        tibber_api_failures: IntCounterVec::new(
+            Opts::new(
+                "tibber_api_failures_total",
+                "Failed calls to the Tibber API",
+            ),
+            &["error"],
+        )
+        .unwrap(),
+        pulse_bridge_fetch_duration: Histogram::with_opts(HistogramOpts::new(
+            "pulse_bridge_fetch_duration_seconds",
+            "Time to fetch the data from the Pulse Bridge",
+        ))
+        .unwrap(),
+        sml_decode_errors: IntCounter::new(
+            "sml_decode_errors_total",
+            "Pulse Bridge messages which could not be decoded",
+        )
+        .unwrap(),
+        common,
+    };
+
+    metrics
+        .common
+        .register(Box::new(metrics.tibber_api_calls.clone()));
+    metrics
+        .common
+        .register(Box::new(metrics.tibber_api_failures.clone()));
+    metrics
+        .common
+        .register(Box::new(metrics.pulse_bridge_fetch_duration.clone()));
+    metrics
+        .common
+        .register(Box::new(metrics.sml_decode_errors.clone()));
+    metrics
+});
+
+/// Counts a call to the Tibber API and its failure
+pub fn record_tibber_call<T>(result: Result<T, TibberLoaderError>) -> Result<T, TibberLoaderError> {
+    METRICS.tibber_api_calls.inc();
+    if let Err(e) = &result {
+        METRICS
+            .tibber_api_failures
+            .with_label_values(&[e.kind()])
+            .inc();
+    }
+    result
+}
@@ -0,0 +1,167 @@
+use anyhow::{Context, Result};
+use chrono::TimeZone;
+use energy_monitor_lib::{
+    energy::aggregator::{Aggregator, PeriodEnergy},
+    tibber::dto::PriceDetails,
+};
+use log::warn;
+use std::{fmt::Write as _, fs::OpenOptions, io::Write as _, path::PathBuf};
+
+/// Columns `emreport` reads
+const HEADER: &str = "starts_at,grid_wh,pv_wh,export_wh,energy,tax,total,currency,level";
+
+/// Appends the energy of every price period together with its price to a
+/// CSV file, the input of `emreport`
+pub struct RecordWriter {
+    path: PathBuf,
+    /// Price of the period the aggregator counts the energy of, unknown
+    /// until the first price after a start
+    current: Option<PriceDetails>,
+}
+
+impl RecordWriter {
+    /// Records are only written if `EMTIBBERD_RECORDS_PATH` is set
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("EMTIBBERD_RECORDS_PATH").ok()?;
+        Some(Self::new(path.into()))
+    }
+
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            current: None,
+        }
+    }
+
+    /// Writes the record of the period which ended and counts the energy
+    /// of the period of `price` from now on. Does nothing if `price` is the
+    /// price of the current period.
+    pub fn start_period<Tz: TimeZone>(
+        &mut self,
+        price: &PriceDetails,
+        aggregator: &mut Aggregator<Tz>,
+    ) -> Result<()> {
+        if let Some(current) = &self.current {
+            if current.starts_at == price.starts_at {
+                return Ok(());
+            }
+        }
+        let energy = aggregator.take_period();
+        let Some(ended) = self.current.replace(price.clone()) else {
+            // The energy counted before has no known price
+            return Ok(());
+        };
+        // e.g. no price was known for a while
+        if ended.ends_at() != price.starts_at {
+            warn!(
+                "No record for the energy since {}, the prices in between are unknown",
+                ended.starts_at
+            );
+            return Ok(());
+        }
+        self.append(&ended, &energy)
+    }
+
+    fn append(&self, price: &PriceDetails, energy: &PeriodEnergy) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+
+        let mut lines = String::new();
+        if file.metadata()?.len() == 0 {
+            let _ = writeln!(lines, "{HEADER}");
+        }
+        let _ = writeln!(
+            lines,
+            "{},{:.1},{:.1},{:.1},{},{},{},{},{}",
+            price.starts_at.to_rfc3339(),
+            energy.import_wh,
+            energy.production_wh,
+            energy.export_wh,
+            price.energy.amount,
+            price.tax.amount,
+            price.total.amount,
+            price.total.currency,
+            price.level.name()
+        );
+        file.write_all(lines.as_bytes())
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
+    use energy_monitor_lib::{
+        money::{Currency, Money},
+        pulse::dto::MeterReading,
+        tibber::dto::PriceLevel,
+    };
+    use std::fs;
+
+    fn price(starts_at: DateTime<FixedOffset>, level: PriceLevel) -> PriceDetails {
+        let eur = |amount: &str| Money::new(amount.parse().unwrap(), Currency::EUR);
+        PriceDetails {
+            total: eur("0.2400"),
+            energy: eur("0.0712"),
+            tax: eur("0.1688"),
+            level,
+            starts_at,
+            duration: 3600,
+        }
+    }
+
+    #[test]
+    fn test_start_period() {
+        let path = std::env::temp_dir().join(format!("records-{}.csv", std::process::id()));
+        let mut records = RecordWriter::new(path.clone());
+        let start: DateTime<FixedOffset> = "2024-06-01T13:00:00+02:00".parse().unwrap();
+        let at = |minutes| (start + TimeDelta::minutes(minutes)).to_utc();
+        let mut aggregator = Aggregator::new(Utc, at(0));
+        let meter = |import_wh| MeterReading {
+            meter_id: "1".to_string(),
+            power: 120,
+            import_wh: Some(import_wh),
+            export_wh: Some(0.0),
+        };
+
+        // Drawn before the price was known
+        aggregator
+            .add_meter_reading(at(-30), &meter(900.0))
+            .unwrap();
+        aggregator.add_meter_reading(at(0), &meter(1000.0)).unwrap();
+        let first = price(start, PriceLevel::Cheap);
+        records.start_period(&first, &mut aggregator).unwrap();
+        aggregator
+            .add_meter_reading(at(30), &meter(1060.0))
+            .unwrap();
+        // Asked again within the period
+        records.start_period(&first, &mut aggregator).unwrap();
+        aggregator
+            .add_meter_reading(at(60), &meter(1120.0))
+            .unwrap();
+        let second = price(start + TimeDelta::hours(1), PriceLevel::VeryExpensive);
+        records.start_period(&second, &mut aggregator).unwrap();
+        aggregator
+            .add_meter_reading(at(120), &meter(1200.0))
+            .unwrap();
+        // The price of the hour in between is missing
+        records
+            .start_period(
+                &price(start + TimeDelta::hours(3), PriceLevel::Normal),
+                &mut aggregator,
+            )
+            .unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            written,
+            "starts_at,grid_wh,pv_wh,export_wh,energy,tax,total,currency,level\n\
+             2024-06-01T13:00:00+02:00,120.0,0.0,0.0,0.0712,0.1688,0.2400,EUR,Cheap\n"
+        );
+    }
+}
@@ -1,18 +1,27 @@
-use crate::errors::TibberLoaderError;
-use anyhow::Result;
+use crate::{errors::TibberLoaderError, gql::queries::Resolution};
+use anyhow::{Context, Result};
 
 pub struct Config {
     pub token: String,
     pub url: String,
+    /// Taken from `TIBBER_PRICE_RESOLUTION`, hourly if not set
+    pub resolution: Resolution,
 }
 
 impl Config {
     pub fn new(url: &str) -> Result<Self> {
+        let resolution = match std::env::var("TIBBER_PRICE_RESOLUTION") {
+            Ok(resolution) => resolution
+                .parse()
+                .context("TIBBER_PRICE_RESOLUTION is invalid, expected HOURLY or QUARTER_HOURLY")?,
+            Err(_) => Resolution::default(),
+        };
         match std::env::var("TIBBER_API_TOKEN") {
             Ok(token) => {
                 let config = Self {
                     token,
                     url: url.to_string(),
+                    resolution,
                 };
                 Ok(config)
             }
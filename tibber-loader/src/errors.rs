@@ -35,4 +35,27 @@ pub enum TibberLoaderError {
 
     #[error("No current price")]
     NoCurrentPrice,
+
+    #[error("Invalid price resolution {0}, expected HOURLY or QUARTER_HOURLY")]
+    InvalidResolution(String),
+}
+
+impl TibberLoaderError {
+    /// Name of the variant, e.g. for labeling metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TibberLoaderError::TokenMissing => "TokenMissing",
+            TibberLoaderError::Unauthorized => "Unauthorized",
+            TibberLoaderError::InvalidHeader(_) => "InvalidHeader",
+            TibberLoaderError::MissingUserId => "MissingUserId",
+            TibberLoaderError::MissingResponseData => "MissingResponseData",
+            TibberLoaderError::OnlyOneHomeSupported => "OnlyOneHomeSupported",
+            TibberLoaderError::GraphQLError(_) => "GraphQLError",
+            TibberLoaderError::FetchError(_) => "FetchError",
+            TibberLoaderError::NoSubscription => "NoSubscription",
+            TibberLoaderError::NoPriceInfo => "NoPriceInfo",
+            TibberLoaderError::NoCurrentPrice => "NoCurrentPrice",
+            TibberLoaderError::InvalidResolution(_) => "InvalidResolution",
+        }
+    }
 }
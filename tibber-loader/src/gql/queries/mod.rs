@@ -1,5 +1,13 @@
-use chrono::{DateTime, FixedOffset};
-use graphql_client::GraphQLQuery;
+use crate::errors::TibberLoaderError;
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use energy_monitor_lib::{
+    money::{Currency, Money},
+    tibber::dto,
+};
+use graphql_client::{GraphQLQuery, QueryBody};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::str::FromStr;
 
 #[derive(GraphQLQuery)]
 #[graphql(
@@ -17,6 +25,105 @@ pub struct Viewer;
 )]
 pub struct Price;
 
+/// The [`Price`] query with the amounts deserialized straight into decimals,
+/// the generated response holds every GraphQL `Float` as `f64`
+pub struct DecimalPrice;
+
+impl GraphQLQuery for DecimalPrice {
+    type Variables = price::Variables;
+    type ResponseData = PriceData;
+
+    fn build_query(variables: Self::Variables) -> QueryBody<Self::Variables> {
+        Price::build_query(variables)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PriceData {
+    pub viewer: PriceViewer,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PriceViewer {
+    pub home: PriceHome,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceHome {
+    pub current_subscription: Option<PriceSubscription>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceSubscription {
+    pub price_info: Option<PriceInfoFields>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PriceInfoFields {
+    pub current: Option<PriceFields>,
+    #[serde(default)]
+    pub today: Vec<Option<PriceFields>>,
+    #[serde(default)]
+    pub tomorrow: Vec<Option<PriceFields>>,
+}
+
+/// The `PriceFields` fragment of the [`Price`] query
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceFields {
+    #[serde(default, with = "rust_decimal::serde::float_option")]
+    pub total: Option<Decimal>,
+    #[serde(default, with = "rust_decimal::serde::float_option")]
+    pub energy: Option<Decimal>,
+    #[serde(default, with = "rust_decimal::serde::float_option")]
+    pub tax: Option<Decimal>,
+    pub starts_at: Option<String>,
+    pub currency: String,
+    pub level: Option<price::PriceLevel>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Temporal resolution of the prices, day-ahead markets move to 15 minute products
+pub enum Resolution {
+    #[default]
+    Hourly,
+    QuarterHourly,
+}
+
+impl Resolution {
+    /// Length of a price period
+    pub fn duration(self) -> TimeDelta {
+        match self {
+            Resolution::Hourly => TimeDelta::hours(1),
+            Resolution::QuarterHourly => TimeDelta::minutes(15),
+        }
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = TibberLoaderError;
+
+    /// Accepts the names used by the Tibber API (e.g. `QUARTER_HOURLY`) in any case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "HOURLY" => Ok(Resolution::Hourly),
+            "QUARTER_HOURLY" => Ok(Resolution::QuarterHourly),
+            _ => Err(TibberLoaderError::InvalidResolution(s.to_string())),
+        }
+    }
+}
+
+impl From<Resolution> for price::PriceInfoResolution {
+    fn from(resolution: Resolution) -> Self {
+        match resolution {
+            Resolution::Hourly => price::PriceInfoResolution::HOURLY,
+            Resolution::QuarterHourly => price::PriceInfoResolution::QUARTER_HOURLY,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 /// Price level based on trailing price average (3 days for hourly values and 30 days for daily values)
 pub enum PriceLevel {
@@ -36,31 +143,50 @@ pub enum PriceLevel {
     None,
 }
 
+impl From<&str> for PriceLevel {
+    /// Maps the level names used by the Tibber API (e.g. `VERY_CHEAP`) in
+    /// any case and without underscores, e.g. `VeryCheap`
+    fn from(level: &str) -> Self {
+        match level.replace('_', "").to_ascii_uppercase().as_str() {
+            "VERYCHEAP" => PriceLevel::VeryCheap,
+            "CHEAP" => PriceLevel::Cheap,
+            "NORMAL" => PriceLevel::Normal,
+            "EXPENSIVE" => PriceLevel::Expensive,
+            "VERYEXPENSIVE" => PriceLevel::VeryExpensive,
+            "" => PriceLevel::None,
+            _ => PriceLevel::Other(level.to_string()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
-/// Price information related to the subscription for the current hour
+/// Price information related to the subscription for one price period
 pub struct PriceInfo {
     /// The total price (incl. tax)
-    pub total: f64,
+    pub total: Money,
     /// Nord Pool spot price
-    pub energy: f64,
+    pub energy: Money,
     /// The tax part of the price (guarantee of origin certificate, energy tax (Sweden only) and VAT)
-    pub tax: f64,
+    pub tax: Money,
     /// The start time of the price
     pub starts_at: DateTime<FixedOffset>,
-    /// The cost currency
-    pub currency: String,
+    /// How long the price applies, an hour or 15 minutes depending on the resolution
+    pub duration: TimeDelta,
     /// The price level compared to recent price values
     pub level: PriceLevel,
 }
 
 impl PriceInfo {
-    pub fn new(pinfo: price::PriceViewerHomeCurrentSubscriptionPriceInfoCurrent) -> Option<Self> {
-        let total = pinfo.total?;
-        let (energy, tax) = match (pinfo.energy, pinfo.tax) {
+    /// Price of the period starting at `starts_at` and lasting `duration`
+    pub fn new(pinfo: PriceFields, duration: TimeDelta) -> Option<Self> {
+        let currency: Currency = pinfo.currency.parse().ok()?;
+        let money = |amount| Money::new(amount, currency);
+        let total = money(pinfo.total?);
+        let (energy, tax) = match (pinfo.energy.map(money), pinfo.tax.map(money)) {
             (Some(energy), Some(tax)) => (energy, tax),
-            (Some(energy), None) => (energy, total - energy),
-            (None, Some(tax)) => (total - tax, tax),
-            _ => (total, 0.0),
+            (Some(energy), None) => (energy, total.checked_sub(energy).ok()?),
+            (None, Some(tax)) => (total.checked_sub(tax).ok()?, tax),
+            _ => (total, Money::zero(currency)),
         };
 
         let level = match pinfo.level {
@@ -87,8 +213,160 @@ impl PriceInfo {
             energy,
             tax,
             starts_at,
-            currency: pinfo.currency,
+            duration,
             level,
         })
     }
+
+    /// Start of the next price period
+    pub fn ends_at(&self) -> DateTime<FixedOffset> {
+        self.starts_at + self.duration
+    }
+
+    /// The price as published on `Tibber/price_details`
+    pub fn details(&self) -> dto::PriceDetails {
+        dto::PriceDetails {
+            total: self.total,
+            energy: self.energy,
+            tax: self.tax,
+            level: match &self.level {
+                PriceLevel::Cheap => dto::PriceLevel::Cheap,
+                PriceLevel::Expensive => dto::PriceLevel::Expensive,
+                PriceLevel::Normal => dto::PriceLevel::Normal,
+                PriceLevel::VeryCheap => dto::PriceLevel::VeryCheap,
+                PriceLevel::VeryExpensive => dto::PriceLevel::VeryExpensive,
+                PriceLevel::None => dto::PriceLevel::None,
+                PriceLevel::Other(level) => dto::PriceLevel::Other(level.clone()),
+            },
+            starts_at: self.starts_at,
+            duration: u32::try_from(self.duration.num_seconds()).unwrap_or_default(),
+        }
+    }
+
+    /// Whether the price applies at `time`
+    pub fn contains(&self, time: DateTime<FixedOffset>) -> bool {
+        self.starts_at <= time && time < self.ends_at()
+    }
+
+    /// Sets the durations of consecutive prices from the gaps between their
+    /// start times. The last one keeps its duration, as do prices followed
+    /// by a gap longer than their duration.
+    pub fn set_durations(prices: &mut [PriceInfo]) {
+        for i in 1..prices.len() {
+            let duration = prices[i].starts_at - prices[i - 1].starts_at;
+            if duration > TimeDelta::zero() && duration <= prices[i - 1].duration {
+                prices[i - 1].duration = duration;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn price(starts_at: &str, duration: TimeDelta) -> PriceInfo {
+        PriceInfo::new(
+            PriceFields {
+                total: Some(Decimal::new(3, 1)),
+                energy: Some(Decimal::new(1, 1)),
+                tax: None,
+                starts_at: Some(starts_at.to_string()),
+                currency: "EUR".to_string(),
+                level: Some(price::PriceLevel::NORMAL),
+            },
+            duration,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_set_durations() {
+        let fallback = Resolution::Hourly.duration();
+        let mut prices = vec![
+            price("2025-10-01T23:00:00+02:00", fallback),
+            price("2025-10-02T00:00:00+02:00", fallback),
+            price("2025-10-02T00:15:00+02:00", fallback),
+            price("2025-10-02T03:00:00+02:00", fallback),
+        ];
+        PriceInfo::set_durations(&mut prices);
+
+        let durations: Vec<_> = prices.iter().map(|price| price.duration).collect();
+        assert_eq!(
+            durations,
+            [
+                TimeDelta::hours(1),
+                TimeDelta::minutes(15),
+                TimeDelta::hours(1),
+                TimeDelta::hours(1)
+            ]
+        );
+        assert_eq!(prices[1].ends_at(), prices[2].starts_at);
+        assert!(prices[1].contains(prices[1].starts_at));
+        assert!(!prices[1].contains(prices[2].starts_at));
+        assert_eq!(prices[0].tax, Money::from_f64(0.2, Currency::EUR).unwrap());
+    }
+
+    #[test]
+    fn test_decimal_response() {
+        let response = r#"{"viewer":{"home":{"currentSubscription":{"priceInfo":{
+            "current":{"total":0.3012,"energy":0.1012,"tax":0.2,"startsAt":"2025-10-01T12:15:00+02:00","currency":"EUR","level":"VERY_CHEAP"},
+            "today":[{"total":0.1,"energy":null,"tax":0.2,"startsAt":"2025-10-01T00:00:00+02:00","currency":"EUR","level":"NORMAL"}],
+            "tomorrow":[]
+        }}}}}"#;
+        let data: PriceData = serde_json::from_str(response).unwrap();
+        let price_info = data
+            .viewer
+            .home
+            .current_subscription
+            .unwrap()
+            .price_info
+            .unwrap();
+
+        let current = PriceInfo::new(price_info.current.unwrap(), TimeDelta::minutes(15)).unwrap();
+        assert_eq!(current.total.amount, Decimal::new(3012, 4));
+        assert_eq!(current.energy.amount, Decimal::new(1012, 4));
+        assert_eq!(current.energy.checked_add(current.tax), Ok(current.total));
+        assert!(matches!(current.level, PriceLevel::VeryCheap));
+
+        // 0.1 - 0.2 without the binary rounding of floats
+        let today = price_info.today[0].clone().unwrap();
+        let today = PriceInfo::new(today, TimeDelta::hours(1)).unwrap();
+        assert_eq!(today.energy.amount, Decimal::new(-1, 1));
+    }
+
+    #[test]
+    fn test_details() {
+        let mut price = price("2025-10-01T12:15:00+02:00", TimeDelta::minutes(15));
+        price.level = PriceLevel::Other("UNKNOWN".to_string());
+        let details = price.details();
+        assert_eq!(details.total.to_string(), "0.3 EUR");
+        assert_eq!(details.energy.checked_add(details.tax), Ok(details.total));
+        assert_eq!(details.duration, 900);
+        // Levels Tibber adds are passed on as Tibber names them
+        assert_eq!(details.level, dto::PriceLevel::Other("UNKNOWN".to_string()));
+        price.level = PriceLevel::from("EXTREMELY_CHEAP");
+        let published = serde_json::to_value(price.details()).unwrap();
+        assert_eq!(published["level"], "EXTREMELY_CHEAP");
+        assert!(matches!(
+            PriceLevel::from("very_cheap"),
+            PriceLevel::VeryCheap
+        ));
+        // As emtibberd writes the records for emreport
+        assert!(matches!(
+            PriceLevel::from("VeryExpensive"),
+            PriceLevel::VeryExpensive
+        ));
+    }
+
+    #[test]
+    fn test_resolution() {
+        assert_eq!(
+            "QUARTER_HOURLY".parse::<Resolution>().unwrap(),
+            Resolution::QuarterHourly
+        );
+        assert_eq!("hourly".parse::<Resolution>().unwrap(), Resolution::Hourly);
+        assert!("DAILY".parse::<Resolution>().is_err());
+        assert_eq!(Resolution::QuarterHourly.duration(), TimeDelta::minutes(15));
+    }
 }
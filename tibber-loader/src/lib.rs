@@ -2,8 +2,10 @@ use crate::{
     client::{connect, post_graphql},
     config::Config,
     errors::TibberLoaderError,
-    gql::queries::{self, PriceInfo},
+    gql::queries::{self, PriceInfo, Resolution},
 };
+use chrono::{DateTime, Utc};
+use energy_monitor_lib::{price::PriceProvider, tibber::dto::PriceDetails};
 use reqwest::Client;
 
 pub mod client;
@@ -22,6 +24,7 @@ pub struct Session {
     pub user_id: String,
     /// Only a single home is supported
     home_id: HomeId,
+    resolution: Resolution,
     client: Client,
 }
 
@@ -36,16 +39,22 @@ struct User {
 impl Session {
     pub async fn new(config: Config) -> Result<Self, TibberLoaderError> {
         let url = config.url.clone();
+        let resolution = config.resolution;
         let client = connect(&config)?;
         let user = Session::get_user(&client, config).await?;
         Ok(Session {
             url,
             user_id: user.user_id,
             home_id: user.home_id,
+            resolution,
             client,
         })
     }
 
+    pub fn home_id(&self) -> &HomeId {
+        &self.home_id
+    }
+
     async fn get_user(client: &Client, config: Config) -> Result<User, TibberLoaderError> {
         let viewer = post_graphql::<queries::Viewer, _>(
             client,
@@ -71,26 +80,78 @@ impl Session {
         }
     }
 
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    /// Current price, its duration is taken from the start of the next price
     pub async fn get_current_price(&self) -> Result<Option<PriceInfo>, TibberLoaderError> {
-        let price = post_graphql::<queries::Price, _>(
+        let (current, prices) = self.fetch_prices().await?;
+        let current = PriceInfo::new(current, self.resolution.duration());
+        Ok(current.map(|current| {
+            prices
+                .into_iter()
+                .find(|price| price.starts_at == current.starts_at)
+                .unwrap_or(current)
+        }))
+    }
+
+    /// Prices of today and, once published, tomorrow
+    pub async fn get_prices(&self) -> Result<Vec<PriceInfo>, TibberLoaderError> {
+        Ok(self.fetch_prices().await?.1)
+    }
+
+    async fn fetch_prices(
+        &self,
+    ) -> Result<(queries::PriceFields, Vec<PriceInfo>), TibberLoaderError> {
+        let price = post_graphql::<queries::DecimalPrice, _>(
             &self.client,
             &self.url.clone(),
             queries::price::Variables {
                 id: self.home_id.0.clone(),
+                resolution: Some(self.resolution.into()),
             },
         )
         .await?
         .viewer;
 
-        Ok(PriceInfo::new(
-            price
-                .home
-                .current_subscription
-                .ok_or(TibberLoaderError::NoSubscription)?
-                .price_info
-                .ok_or(TibberLoaderError::NoPriceInfo)?
-                .current
-                .ok_or(TibberLoaderError::NoCurrentPrice)?,
-        ))
+        let price_info = price
+            .home
+            .current_subscription
+            .ok_or(TibberLoaderError::NoSubscription)?
+            .price_info
+            .ok_or(TibberLoaderError::NoPriceInfo)?;
+        let mut prices: Vec<PriceInfo> = price_info
+            .today
+            .into_iter()
+            .chain(price_info.tomorrow)
+            .flatten()
+            .filter_map(|price| PriceInfo::new(price, self.resolution.duration()))
+            .collect();
+        PriceInfo::set_durations(&mut prices);
+
+        let current = price_info
+            .current
+            .ok_or(TibberLoaderError::NoCurrentPrice)?;
+        Ok((current, prices))
+    }
+}
+
+/// Only the prices of today and tomorrow are available
+impl PriceProvider for Session {
+    type Error = TibberLoaderError;
+
+    async fn prices(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<PriceDetails>, Self::Error> {
+        Ok(self
+            .get_prices()
+            .await?
+            .iter()
+            .filter(|price| from <= price.starts_at && price.starts_at < to)
+            .map(PriceInfo::details)
+            .collect())
     }
 }